@@ -164,11 +164,51 @@ async function classifyAudioBlob(audioBlob) {
     }
   }
 
+  // Names and attribution from the species manifest, if one was exported
+  const species = await loadSpeciesInfo(predictedClassName);
+  const displayName = species
+    ? species.scientific_name
+      ? `${species.common_name} (${species.scientific_name})`
+      : species.common_name
+    : predictedClassName;
+
   console.log('Predicted class:', predictedClassName);
   const statusElement = document.getElementById('modelStatus');
-  statusElement.textContent = 'Predicted class: ' + predictedClass;
+  statusElement.textContent = 'Predicted class: ' + displayName;
+
+  displayBirdImage(predictedClassName, species);
+}
+
+// Pending or finished fetch of model/species.json, shared by every lookup.
+let speciesManifest = null;
+
+/**
+ * Fetches model/species.json once and caches it. A failed request is not
+ * cached, so a later lookup tries again.
+ * @returns {Promise<object|null>} The manifest, or null if there is none
+ */
+function loadSpeciesManifest() {
+  if (!speciesManifest) {
+    speciesManifest = fetch('./model/species.json')
+      .then((response) => (response.ok ? response.json() : null))
+      .catch((error) => {
+        console.warn('Species manifest not available:', error);
+        speciesManifest = null;
+        return null;
+      });
+  }
+  return speciesManifest;
+}
 
-  displayBirdImage(predictedClassName);
+/**
+ * Looks up a class in model/species.json, written by the scraper's
+ * --class-map export.
+ * @param {string} className - Label from the class map
+ * @returns {Promise<object|null>} The species entry, or null if unavailable
+ */
+async function loadSpeciesInfo(className) {
+  const manifest = await loadSpeciesManifest();
+  return manifest?.species?.find((entry) => entry.label === className) || null;
 }
 
 async function saveModelToLocalStorage(model) {
@@ -229,8 +269,9 @@ function downloadTextFile(filename, text) {
 /**
  * Displays an image of the predicted bird.
  * @param {string} birdClassName - The class name of the bird to display
+ * @param {object|null} species - Entry from the species manifest, if any
  */
-function displayBirdImage(birdClassName) {
+function displayBirdImage(birdClassName, species = null) {
   let container = document.getElementById('birdImageContainer');
   if (container) {
     // Apply centering styles to container
@@ -256,8 +297,10 @@ function displayBirdImage(birdClassName) {
     container.appendChild(birdImage);
   }
   
-  // Set the image source based on the class name
-  const imagePath = `./birds/${birdClassName.toLowerCase()}.png`;
+  // Set the image source from the manifest, or based on the class name
+  const imagePath = species && species.image
+    ? `./${species.image}`
+    : `./birds/${birdClassName.toLowerCase()}.png`;
   
   // Set the src and handle loading errors
   birdImage.src = imagePath;
@@ -268,4 +311,8 @@ function displayBirdImage(birdClassName) {
   };
   
   birdImage.style.display = 'block';
+  if (species) {
+    birdImage.alt = species.common_name;
+    birdImage.title = species.attribution;
+  }
 }
\ No newline at end of file
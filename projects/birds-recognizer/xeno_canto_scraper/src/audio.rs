@@ -0,0 +1,314 @@
+use crate::flac::write_flac;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use std::f64::consts::PI;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+
+// Sample rate recordings are converted to, as with the ffmpeg conversion
+pub const TARGET_SAMPLE_RATE: u32 = 44100;
+
+// Half-width (in input samples) of the windowed-sinc resampling kernel
+const RESAMPLER_HALF_TAPS: isize = 16;
+
+// Audio container formats we can decode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Mp3,
+    Wav,
+    Flac,
+    Ogg,
+}
+
+impl InputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            InputFormat::Mp3 => "mp3",
+            InputFormat::Wav => "wav",
+            InputFormat::Flac => "flac",
+            InputFormat::Ogg => "ogg",
+        }
+    }
+
+    // Detect the format from the first bytes of a file
+    pub fn from_magic(bytes: &[u8]) -> Option<InputFormat> {
+        if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WAVE") {
+            Some(InputFormat::Wav)
+        } else if bytes.starts_with(b"fLaC") {
+            Some(InputFormat::Flac)
+        } else if bytes.starts_with(b"OggS") {
+            Some(InputFormat::Ogg)
+        } else if bytes.starts_with(b"ID3") || (bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] & 0xE0 == 0xE0) {
+            Some(InputFormat::Mp3)
+        } else {
+            None
+        }
+    }
+
+    // Map an HTTP Content-Type header to a format
+    pub fn from_content_type(content_type: &str) -> Option<InputFormat> {
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+        match mime.as_str() {
+            "audio/mpeg" | "audio/mp3" | "audio/mpeg3" => Some(InputFormat::Mp3),
+            "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => Some(InputFormat::Wav),
+            "audio/flac" | "audio/x-flac" => Some(InputFormat::Flac),
+            "audio/ogg" | "audio/vorbis" | "application/ogg" => Some(InputFormat::Ogg),
+            _ => None,
+        }
+    }
+
+    pub fn from_extension(path: &Path) -> Option<InputFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "mp3" => Some(InputFormat::Mp3),
+            "wav" => Some(InputFormat::Wav),
+            "flac" => Some(InputFormat::Flac),
+            "ogg" | "oga" => Some(InputFormat::Ogg),
+            _ => None,
+        }
+    }
+}
+
+// Format converted recordings are stored in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Wav,  // 16-bit PCM, what the training page loads
+    Flac, // Lossless, roughly half the size of WAV
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "wav",
+            OutputFormat::Flac => "flac",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wav" => Ok(OutputFormat::Wav),
+            "flac" => Ok(OutputFormat::Flac),
+            _ => Err(format!("Unknown output format: {} (expected wav or flac)", s)),
+        }
+    }
+}
+
+// Decoded audio with interleaved samples normalized to [-1.0, 1.0]
+#[derive(Debug, Clone)]
+pub struct AudioBuffer {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>,
+}
+
+impl AudioBuffer {
+    // Number of sample frames (one sample per channel)
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    // Average all channels into a single mono signal
+    pub fn mono(&self) -> Vec<f32> {
+        let channels = self.channels.max(1) as usize;
+        if channels == 1 {
+            return self.samples.clone();
+        }
+        self.samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect()
+    }
+
+    // Samples of a single channel, like AudioBuffer.getChannelData in the browser
+    pub fn channel(&self, index: usize) -> Vec<f32> {
+        let channels = self.channels.max(1) as usize;
+        self.samples.iter().skip(index).step_by(channels).copied().collect()
+    }
+
+    // Copy the frames in [start, end) into a new buffer with the same layout
+    pub fn slice_frames(&self, start: usize, end: usize) -> AudioBuffer {
+        let channels = self.channels.max(1) as usize;
+        let end = end.min(self.frames());
+        let start = start.min(end);
+        AudioBuffer {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            samples: self.samples[start * channels..end * channels].to_vec(),
+        }
+    }
+}
+
+// Decode a PCM or float WAV file
+fn decode_wav<R: Read>(source: R) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
+    let mut reader = WavReader::new(source)?;
+    let spec = reader.spec();
+
+    let samples = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|v| v as f32 / scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    Ok(AudioBuffer {
+        sample_rate: spec.sample_rate,
+        channels: spec.channels,
+        samples,
+    })
+}
+
+// Write audio as 16-bit PCM, the same format the ffmpeg conversion produces
+pub fn write_wav(path: &Path, audio: &AudioBuffer) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = WavSpec {
+        channels: audio.channels,
+        sample_rate: audio.sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+
+    let mut writer = WavWriter::create(path, spec)?;
+    for &sample in &audio.samples {
+        let value = (sample.clamp(-1.0, 1.0) * 32768.0).round().min(32767.0);
+        writer.write_sample(value as i16)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+fn decode_mp3<R: Read>(source: R) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
+    let mut decoder = minimp3::Decoder::new(source);
+    let mut samples = Vec::new();
+    let mut format = None;
+
+    loop {
+        match decoder.next_frame() {
+            Ok(frame) => {
+                format.get_or_insert((frame.sample_rate as u32, frame.channels as u16));
+                samples.extend(frame.data.iter().map(|&s| s as f32 / 32768.0));
+            }
+            Err(minimp3::Error::SkippedData) => continue,
+            Err(minimp3::Error::Eof) | Err(minimp3::Error::InsufficientData) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let (sample_rate, channels) = format.ok_or("No MP3 frames found")?;
+    Ok(AudioBuffer { sample_rate, channels, samples })
+}
+
+fn decode_flac<R: Read>(source: R) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
+    let mut reader = claxon::FlacReader::new(source)?;
+    let info = reader.streaminfo();
+    let scale = (1i64 << (info.bits_per_sample - 1)) as f32;
+    let samples = reader
+        .samples()
+        .map(|s| s.map(|v| v as f32 / scale))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(AudioBuffer {
+        sample_rate: info.sample_rate,
+        channels: info.channels as u16,
+        samples,
+    })
+}
+
+fn decode_ogg<R: Read + Seek>(source: R) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
+    let mut reader = lewton::inside_ogg::OggStreamReader::new(source)?;
+    let mut samples = Vec::new();
+    while let Some(packet) = reader.read_dec_packet_itl()? {
+        samples.extend(packet.iter().map(|&s| s as f32 / 32768.0));
+    }
+
+    Ok(AudioBuffer {
+        sample_rate: reader.ident_hdr.audio_sample_rate,
+        channels: reader.ident_hdr.audio_channels as u16,
+        samples,
+    })
+}
+
+// Decode an in-memory file of the given format
+pub fn decode_bytes(bytes: &[u8], format: InputFormat) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
+    match format {
+        InputFormat::Mp3 => decode_mp3(Cursor::new(bytes)),
+        InputFormat::Wav => decode_wav(Cursor::new(bytes)),
+        InputFormat::Flac => decode_flac(Cursor::new(bytes)),
+        InputFormat::Ogg => decode_ogg(Cursor::new(bytes)),
+    }
+}
+
+// Decode the contents of an audio file, detecting the format from the bytes
+// and falling back to the file extension
+pub fn decode_file_bytes(path: &Path, bytes: &[u8]) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
+    let format = InputFormat::from_magic(bytes)
+        .or_else(|| InputFormat::from_extension(path))
+        .ok_or_else(|| format!("Unrecognized audio format: {}", path.display()))?;
+    decode_bytes(bytes, format)
+}
+
+// Read any supported audio file, detecting the format from its content
+pub fn read_audio(path: &Path) -> Result<AudioBuffer, Box<dyn std::error::Error>> {
+    decode_file_bytes(path, &std::fs::read(path)?)
+}
+
+// Write audio in the format given by the file extension (FLAC or WAV)
+pub fn write_audio(path: &Path, audio: &AudioBuffer) -> Result<(), Box<dyn std::error::Error>> {
+    if path.extension().is_some_and(|ext| ext == "flac") {
+        write_flac(path, audio)
+    } else {
+        write_wav(path, audio)
+    }
+}
+
+// Resample with a Hann-windowed sinc kernel, low-passing at the lower of
+// the two Nyquist frequencies to avoid aliasing when downsampling
+pub fn resample(audio: &AudioBuffer, sample_rate: u32) -> AudioBuffer {
+    if audio.sample_rate == sample_rate || audio.frames() == 0 {
+        return audio.clone();
+    }
+
+    let channels = audio.channels.max(1) as usize;
+    let ratio = sample_rate as f64 / audio.sample_rate as f64;
+    let cutoff = ratio.min(1.0);
+    let input_frames = audio.frames();
+    let output_frames = (input_frames as f64 * ratio).round() as usize;
+    let half_width = (RESAMPLER_HALF_TAPS as f64 / cutoff).ceil() as isize;
+
+    let mut samples = vec![0.0f32; output_frames * channels];
+    for i in 0..output_frames {
+        let position = i as f64 / ratio;
+        let center = position.floor() as isize;
+
+        let mut sums = vec![0.0f64; channels];
+        let mut weight_sum = 0.0;
+        for j in center - half_width + 1..=center + half_width {
+            if j < 0 || j as usize >= input_frames {
+                continue;
+            }
+            let t = (position - j as f64) * cutoff;
+            let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+            let window = 0.5 + 0.5 * (PI * t / RESAMPLER_HALF_TAPS as f64).cos();
+            let weight = sinc * window;
+            weight_sum += weight;
+            for (c, sum) in sums.iter_mut().enumerate() {
+                *sum += audio.samples[j as usize * channels + c] as f64 * weight;
+            }
+        }
+
+        for (c, sum) in sums.into_iter().enumerate() {
+            let value = if weight_sum != 0.0 { sum / weight_sum } else { 0.0 };
+            samples[i * channels + c] = value as f32;
+        }
+    }
+
+    AudioBuffer {
+        sample_rate,
+        channels: audio.channels,
+        samples,
+    }
+}
@@ -0,0 +1,470 @@
+use crate::audio::{read_audio, resample, write_wav, AudioBuffer, InputFormat};
+use crate::clips::{clip_counters, clips_csv_path, clips_dir, load_clips, next_clip_filename, write_clips_csv, ClipMetadata};
+use crate::dsp::rms_db;
+use crate::filters::{high_pass, low_pass};
+use crate::license::LicenseFilter;
+use crate::mapping::ClassMapping;
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::path::{Path, PathBuf};
+
+// WSOLA time-stretch settings (samples)
+const STRETCH_FRAME: usize = 1024;
+const STRETCH_HOP: usize = STRETCH_FRAME / 4;
+const STRETCH_TOLERANCE: usize = 64; // How far a frame may move to line up with the previous one
+
+// Ranges the augment stage draws its random parameters from. Each
+// transformation is applied to a copy with the given probability.
+#[derive(Debug, Clone)]
+pub struct AugmentConfig {
+    pub copies: usize,               // Augmented clips per source file
+    pub seed: u64,                   // Same seed, same clips
+    pub probability: f64,            // Chance of each transformation being applied
+    pub max_shift: f64,              // Circular time shift, fraction of the length
+    pub gain_db: f64,                // Gain change in [-gain_db, gain_db]
+    pub stretch: (f64, f64),         // Time-stretch factor range (output / input length)
+    pub pitch_semitones: f64,        // Pitch shift in [-pitch_semitones, pitch_semitones]
+    pub noise_dir: Option<PathBuf>,  // Folder of background noise clips
+    pub noise_snr_db: (f64, f64),    // Signal-to-noise ratio range for mixed noise
+    pub high_pass_hz: (f64, f64),    // Cutoff range of the random high-pass filter
+    pub low_pass_hz: (f64, f64),     // Cutoff range of the random low-pass filter
+}
+
+impl Default for AugmentConfig {
+    fn default() -> Self {
+        AugmentConfig {
+            copies: 3,
+            seed: 0,
+            probability: 0.5,
+            max_shift: 0.5,
+            gain_db: 6.0,
+            stretch: (0.85, 1.15),
+            pitch_semitones: 2.0,
+            noise_dir: None,
+            noise_snr_db: (5.0, 20.0),
+            high_pass_hz: (200.0, 2000.0),
+            low_pass_hz: (6000.0, 14000.0),
+        }
+    }
+}
+
+// Small deterministic generator (SplitMix64), so results do not depend on
+// a crate's algorithm choice between versions
+pub struct Rng(u64);
+
+impl Rng {
+    // Seed from the global seed and a string naming what is being generated
+    pub fn for_item(seed: u64, item: &str) -> Self {
+        let mut state = seed ^ 0x9e3779b97f4a7c15;
+        for byte in item.bytes() {
+            state = (state ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+        Rng(state)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.unit()
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+
+    fn index(&mut self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+}
+
+// Rotate the audio by `frames` frames, wrapping the end around to the start
+fn circular_shift(audio: &mut AudioBuffer, frames: usize) {
+    let channels = audio.channels.max(1) as usize;
+    let total = audio.frames();
+    if total > 0 {
+        audio.samples.rotate_right((frames % total) * channels);
+    }
+}
+
+fn apply_gain(audio: &mut AudioBuffer, gain_db: f64) {
+    let gain = 10f64.powf(gain_db / 20.0) as f32;
+    for sample in &mut audio.samples {
+        *sample = (*sample * gain).clamp(-1.0, 1.0);
+    }
+}
+
+// Change the duration by `factor` without changing the pitch (WSOLA). Each
+// analysis frame may move by up to STRETCH_TOLERANCE samples to best line up
+// with the natural continuation of the previous one, which avoids the
+// phasing of plain overlap-add. Positions are chosen on the mono mix and
+// applied to all channels.
+pub fn time_stretch(audio: &AudioBuffer, factor: f64) -> AudioBuffer {
+    let channels = audio.channels.max(1) as usize;
+    let input_frames = audio.frames();
+    if input_frames < STRETCH_FRAME + 2 * STRETCH_TOLERANCE || factor <= 0.0 {
+        return audio.clone();
+    }
+
+    let mono = audio.mono();
+    let window: Vec<f64> = (0..STRETCH_FRAME)
+        .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / STRETCH_FRAME as f64).cos())
+        .collect();
+    let output_frames = (input_frames as f64 * factor).round() as usize;
+    let input_hop = STRETCH_HOP as f64 / factor;
+    let last_start = input_frames - STRETCH_FRAME;
+
+    let mut output = vec![0.0f64; (output_frames + STRETCH_FRAME) * channels];
+    let mut window_sum = vec![0.0f64; output_frames + STRETCH_FRAME];
+    let mut previous: Option<usize> = None;
+    let mut k = 0;
+    while k * STRETCH_HOP < output_frames {
+        let nominal = ((k as f64 * input_hop).round() as usize).min(last_start);
+
+        let position = match previous {
+            None => nominal,
+            Some(previous) => {
+                // Compare candidates against what would have followed the previous frame
+                let natural = (previous + STRETCH_HOP).min(last_start);
+                let overlap = STRETCH_FRAME - STRETCH_HOP;
+                let low = nominal.saturating_sub(STRETCH_TOLERANCE);
+                let high = (nominal + STRETCH_TOLERANCE).min(last_start);
+                (low..=high)
+                    .map(|candidate| {
+                        let correlation: f64 = (0..overlap)
+                            .map(|n| mono[candidate + n] as f64 * mono[natural + n] as f64)
+                            .sum();
+                        (candidate, correlation)
+                    })
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(candidate, _)| candidate)
+                    .unwrap_or(nominal)
+            }
+        };
+
+        let offset = k * STRETCH_HOP;
+        for (n, w) in window.iter().enumerate() {
+            for c in 0..channels {
+                output[(offset + n) * channels + c] += audio.samples[(position + n) * channels + c] as f64 * w;
+            }
+            window_sum[offset + n] += w;
+        }
+
+        previous = Some(position);
+        k += 1;
+    }
+
+    let samples = (0..output_frames * channels)
+        .map(|i| {
+            let sum = window_sum[i / channels];
+            if sum > 1e-3 { (output[i] / sum) as f32 } else { 0.0 }
+        })
+        .collect();
+    AudioBuffer { sample_rate: audio.sample_rate, channels: audio.channels, samples }
+}
+
+// Shift the pitch by `semitones` while keeping the duration: stretch by the
+// pitch ratio, then resample back to the original length
+pub fn pitch_shift(audio: &AudioBuffer, semitones: f64) -> AudioBuffer {
+    let ratio = 2f64.powf(semitones / 12.0);
+    let mut stretched = time_stretch(audio, ratio);
+    stretched.sample_rate = (audio.sample_rate as f64 * ratio).round() as u32;
+    resample(&stretched, audio.sample_rate)
+}
+
+// Mix a noise clip into the audio at the given signal-to-noise ratio,
+// starting at a random point and looping the noise if it is too short
+fn mix_noise(audio: &mut AudioBuffer, noise: &AudioBuffer, snr_db: f64, rng: &mut Rng) {
+    let noise = if noise.sample_rate != audio.sample_rate {
+        resample(noise, audio.sample_rate)
+    } else {
+        noise.clone()
+    };
+    let noise_signal = noise.mono();
+    if noise_signal.is_empty() {
+        return;
+    }
+
+    let signal_db = rms_db(&audio.mono());
+    let noise_db = rms_db(&noise_signal);
+    let gain = 10f64.powf((signal_db - noise_db - snr_db) / 20.0) as f32;
+
+    let channels = audio.channels.max(1) as usize;
+    let start = rng.index(noise_signal.len());
+    for (frame, samples) in audio.samples.chunks_mut(channels).enumerate() {
+        let value = noise_signal[(start + frame) % noise_signal.len()] * gain;
+        for sample in samples {
+            *sample = (*sample + value).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+// Apply a random selection of transformations, returning a description of
+// what was done for the catalog. At least one transformation that can do
+// something with this config is chosen; the description is still empty if
+// that was noise mixing and the noise clip could not be read.
+fn augment_audio(
+    source: &AudioBuffer,
+    config: &AugmentConfig,
+    noise_files: &[PathBuf],
+    rng: &mut Rng,
+) -> (AudioBuffer, String) {
+    let mut audio = source.clone();
+    let mut steps = Vec::new();
+    let nyquist = audio.sample_rate as f64 / 2.0;
+
+    // Shift, stretch, pitch, noise, filter, gain
+    let applicable = [
+        config.max_shift > 0.0,
+        config.stretch != (1.0, 1.0),
+        config.pitch_semitones > 0.0,
+        !noise_files.is_empty(),
+        true,
+        config.gain_db > 0.0,
+    ];
+    let mut chosen: Vec<bool> = applicable.iter().map(|&a| rng.chance(config.probability) && a).collect();
+    if !chosen.iter().any(|&c| c) {
+        let candidates: Vec<usize> = (0..applicable.len()).filter(|&i| applicable[i]).collect();
+        chosen[candidates[rng.index(candidates.len())]] = true;
+    }
+
+    if chosen[0] {
+        let fraction = rng.range(0.0, config.max_shift);
+        let frames = (fraction * audio.frames() as f64) as usize;
+        circular_shift(&mut audio, frames);
+        steps.push(format!("shift={:.3}", fraction));
+    }
+    if chosen[1] {
+        let factor = rng.range(config.stretch.0, config.stretch.1);
+        audio = time_stretch(&audio, factor);
+        steps.push(format!("stretch={:.3}", factor));
+    }
+    if chosen[2] {
+        let semitones = rng.range(-config.pitch_semitones, config.pitch_semitones);
+        audio = pitch_shift(&audio, semitones);
+        steps.push(format!("pitch={:+.2}st", semitones));
+    }
+    if chosen[3] {
+        let noise_path = &noise_files[rng.index(noise_files.len())];
+        let snr_db = rng.range(config.noise_snr_db.0, config.noise_snr_db.1);
+        match read_audio(noise_path) {
+            Ok(noise) => {
+                mix_noise(&mut audio, &noise, snr_db, rng);
+                let name = noise_path.file_name().unwrap_or_default().to_string_lossy();
+                steps.push(format!("noise={}@{:.1}dB", name, snr_db));
+            }
+            Err(e) => println!("Error reading noise clip {}: {}", noise_path.display(), e),
+        }
+    }
+    if chosen[4] {
+        if rng.chance(0.5) {
+            let cutoff = rng.range(config.high_pass_hz.0, config.high_pass_hz.1.min(nyquist * 0.9));
+            high_pass(&mut audio, cutoff);
+            steps.push(format!("highpass={:.0}Hz", cutoff));
+        } else {
+            let cutoff = rng.range(config.low_pass_hz.0.min(nyquist * 0.9), config.low_pass_hz.1.min(nyquist * 0.9));
+            low_pass(&mut audio, cutoff);
+            steps.push(format!("lowpass={:.0}Hz", cutoff));
+        }
+    }
+    // Gain last, so it is not undone by the other steps
+    if chosen[5] {
+        let gain_db = rng.range(-config.gain_db, config.gain_db);
+        apply_gain(&mut audio, gain_db);
+        steps.push(format!("gain={:+.1}dB", gain_db));
+    }
+
+    (audio, steps.join(";"))
+}
+
+// Audio files in the noise folder, sorted so the choice is reproducible
+fn list_noise_files(noise_dir: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(noise_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| InputFormat::from_extension(path).is_some())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// Generate `copies` augmented clips for every downloaded recording, or for
+// every cut clip with `use_clips`. Augmented clips go to the clip catalog
+// with source "augment" and the original recording as parent, so splits
+// that group by recording keep them on the same side as their source.
+pub fn augment_directory(
+    output_dir: &str,
+    config: &AugmentConfig,
+    use_clips: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let noise_files = match &config.noise_dir {
+        Some(dir) => list_noise_files(dir)?,
+        None => Vec::new(),
+    };
+    if config.noise_dir.is_some() && noise_files.is_empty() {
+        println!("No noise clips found, skipping noise mixing");
+    }
+
+    let clips_path = clips_csv_path(output_dir);
+    let mut clips = load_clips(&clips_path)?;
+    let mut counters = clip_counters(&clips);
+    std::fs::create_dir_all(clips_dir(output_dir))?;
+
+    // (source path relative to output_dir, species, parent id, start, end)
+    let sources: Vec<(String, String, String, f64, f64)> = if use_clips {
+        clips
+            .iter()
+            .filter(|c| c.source != "augment")
+            .map(|c| {
+                (format!("clips/{}", c.filename), c.species.clone(), c.parent_id.clone(), c.start_secs, c.end_secs)
+            })
+            .collect()
+    } else {
+        let mapping = ClassMapping::load(output_dir)?;
+        crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?
+            .into_iter()
+            .filter(|m| m.is_downloaded)
+            .map(|m| (m.filename.clone(), mapping.recording_class(&m), m.id, 0.0, f64::NAN))
+            .collect()
+    };
+
+    // Sources augmented on an earlier run, or with an excluded license, are skipped
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+    let excluded = LicenseFilter::load(output_dir)?.excluded_ids(&metadata);
+    let processed: HashSet<String> = clips
+        .iter()
+        .filter(|c| c.source == "augment")
+        .map(|c| c.parent_filename.clone())
+        .collect();
+
+    let mut new_clips = 0;
+    for (source_name, species, parent_id, start_secs, end_secs) in sources {
+        if processed.contains(&source_name) || excluded.contains(&parent_id) {
+            continue;
+        }
+        let source_path = Path::new(output_dir).join(&source_name);
+        let audio = match read_audio(&source_path) {
+            Ok(audio) => audio,
+            Err(e) => {
+                println!("Error reading {}: {}", source_path.display(), e);
+                continue;
+            }
+        };
+        let end_secs = if end_secs.is_nan() { audio.frames() as f64 / audio.sample_rate as f64 } else { end_secs };
+
+        let mut written = 0;
+        for copy in 0..config.copies {
+            let mut rng = Rng::for_item(config.seed, &format!("{}#{}", source_name, copy));
+            let (augmented, description) = augment_audio(&audio, config, &noise_files, &mut rng);
+            // An unchanged copy would only duplicate the source
+            if description.is_empty() {
+                continue;
+            }
+
+            let (species, filename) = next_clip_filename(&species, &mut counters)?;
+            write_wav(&clips_dir(output_dir).join(&filename), &augmented)?;
+
+            clips.push(ClipMetadata {
+                filename,
+                species,
+                parent_id: parent_id.clone(),
+                parent_filename: source_name.clone(),
+                start_secs,
+                end_secs,
+                source: "augment".to_string(),
+                augmentation: description,
+                verified: false,
+                low_hz: None,
+                high_hz: None,
+            });
+            written += 1;
+        }
+        new_clips += written;
+        println!("Augmented {} ({} copies)", source_name, written);
+    }
+
+    write_clips_csv(&clips_path, &clips)?;
+    println!("Wrote {} new augmented clips to {}", new_clips, clips_dir(output_dir).display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frames: usize, channels: u16) -> AudioBuffer {
+        let samples = (0..frames)
+            .flat_map(|i| std::iter::repeat_n((0.5 * (2.0 * PI * 1000.0 * i as f64 / 22050.0).sin()) as f32, channels as usize))
+            .collect();
+        AudioBuffer { sample_rate: 22050, channels, samples }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_clip() {
+        let audio = tone(8192, 1);
+        let config = AugmentConfig::default();
+        let (first, first_description) = augment_audio(&audio, &config, &[], &mut Rng::for_item(7, "a.wav#0"));
+        let (second, second_description) = augment_audio(&audio, &config, &[], &mut Rng::for_item(7, "a.wav#0"));
+        assert_eq!(first_description, second_description);
+        assert_eq!(first.samples, second.samples);
+
+        let descriptions: HashSet<String> = (0..8)
+            .map(|copy| augment_audio(&audio, &config, &[], &mut Rng::for_item(7, &format!("a.wav#{}", copy))).1)
+            .collect();
+        assert!(descriptions.len() > 1);
+    }
+
+    #[test]
+    fn time_stretch_scales_the_length() {
+        let audio = tone(22050, 2);
+        for factor in [0.8, 1.25] {
+            let stretched = time_stretch(&audio, factor);
+            assert_eq!(stretched.channels, 2);
+            assert_eq!(stretched.frames(), (22050.0 * factor).round() as usize);
+        }
+    }
+
+    #[test]
+    fn pitch_shift_keeps_the_length() {
+        let audio = tone(8192, 1);
+        for semitones in [-2.0, 1.5] {
+            let shifted = pitch_shift(&audio, semitones);
+            assert_eq!(shifted.sample_rate, 22050);
+            assert!(shifted.frames().abs_diff(audio.frames()) <= 1, "{} frames", shifted.frames());
+        }
+    }
+
+    #[test]
+    fn description_is_never_empty() {
+        let audio = tone(4096, 1);
+        // Nothing is chosen by chance, so one transformation is forced
+        let config = AugmentConfig { probability: 0.0, ..AugmentConfig::default() };
+        for copy in 0..20 {
+            let (_, description) = augment_audio(&audio, &config, &[], &mut Rng::for_item(0, &copy.to_string()));
+            assert!(!description.is_empty());
+        }
+
+        // Only the filter can change anything, so it is the one forced
+        let config = AugmentConfig {
+            probability: 0.0,
+            max_shift: 0.0,
+            gain_db: 0.0,
+            stretch: (1.0, 1.0),
+            pitch_semitones: 0.0,
+            ..AugmentConfig::default()
+        };
+        for copy in 0..20 {
+            let (_, description) = augment_audio(&audio, &config, &[], &mut Rng::for_item(0, &copy.to_string()));
+            assert!(description.starts_with("highpass=") || description.starts_with("lowpass="), "{}", description);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use crate::clips::{clips_csv_path, clips_dir, load_clips};
+use crate::features::FeatureKind;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// On-disk cache of extracted features so that re-running an extraction only
+// computes what changed. Layout:
+//
+//   <dir>/.feature_cache/<config hash>/config.txt     the extractor's config_key
+//   <dir>/.feature_cache/<config hash>/<audio hash>   feature vector as little-endian f64
+//
+// The audio hash is the SHA-256 of the file contents and the config hash
+// that of FeatureKind::config_key, so editing a recording or changing the
+// extractor simply misses the old entries. `--cache-gc` deletes entries
+// that nothing in the catalog can hit any more.
+
+const CACHE_DIR: &str = ".feature_cache";
+const CONFIG_FILE: &str = "config.txt";
+
+// Numbers temporary files, so two writers of the same entry never share one
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn config_hash(kind: FeatureKind) -> String {
+    // 16 hex digits keep directory names short and are plenty for a handful of configs
+    hash_bytes(kind.config_key().as_bytes())[..16].to_string()
+}
+
+pub fn cache_root(output_dir: &str) -> PathBuf {
+    Path::new(output_dir).join(CACHE_DIR)
+}
+
+// Cached feature vectors for one extractor configuration
+#[derive(Debug, Clone)]
+pub struct FeatureCache {
+    dir: PathBuf,
+}
+
+impl FeatureCache {
+    pub fn open(output_dir: &str, kind: FeatureKind) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = cache_root(output_dir).join(config_hash(kind));
+        std::fs::create_dir_all(&dir)?;
+        let config_path = dir.join(CONFIG_FILE);
+        if !config_path.exists() {
+            std::fs::write(&config_path, format!("{}\n", kind.config_key()))?;
+        }
+        Ok(FeatureCache { dir })
+    }
+
+    pub fn get(&self, audio_hash: &str) -> Option<Vec<f64>> {
+        let bytes = std::fs::read(self.dir.join(audio_hash)).ok()?;
+        if !bytes.len().is_multiple_of(8) {
+            return None;
+        }
+        Some(
+            bytes
+                .chunks_exact(8)
+                .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect(),
+        )
+    }
+
+    // Entries are written to a temporary file unique to this process and
+    // call, then renamed into place, so concurrent workers (or runs) never
+    // see a partial vector
+    pub fn put(&self, audio_hash: &str, features: &[f64]) -> std::io::Result<()> {
+        let bytes: Vec<u8> = features.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let temp_path = self.dir.join(format!(
+            "{}.{}-{}.tmp",
+            audio_hash,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temp_path, bytes)?;
+        std::fs::rename(&temp_path, self.dir.join(audio_hash))
+    }
+}
+
+// Hashes of every audio file the catalog currently refers to
+fn live_audio_hashes(output_dir: &str) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+    let mut paths: Vec<PathBuf> = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?
+        .into_iter()
+        .filter(|m| m.is_downloaded)
+        .map(|m| Path::new(output_dir).join(m.filename))
+        .collect();
+    paths.extend(
+        load_clips(&clips_csv_path(output_dir))?
+            .into_iter()
+            .map(|clip| clips_dir(output_dir).join(clip.filename)),
+    );
+
+    let mut hashes = HashSet::new();
+    for path in paths {
+        if let Ok(bytes) = std::fs::read(&path) {
+            hashes.insert(hash_bytes(&bytes));
+        }
+    }
+    Ok(hashes)
+}
+
+// Remove cached vectors for audio that is no longer in the catalog (or has
+// changed since), and whole configurations the extractors no longer produce
+pub fn cache_gc(output_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let root = cache_root(output_dir);
+    if !root.exists() {
+        println!("No feature cache in {}", output_dir);
+        return Ok(());
+    }
+
+    let live_configs: HashSet<String> = [FeatureKind::Enhanced, FeatureKind::Simple]
+        .into_iter()
+        .map(config_hash)
+        .collect();
+    let live_audio = live_audio_hashes(output_dir)?;
+
+    let mut removed = 0;
+    let mut kept = 0;
+    let mut freed_bytes = 0;
+    for config_entry in std::fs::read_dir(&root)? {
+        let config_dir = config_entry?.path();
+        if !config_dir.is_dir() {
+            continue;
+        }
+        let config_name = config_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        if !live_configs.contains(&config_name) {
+            for entry in std::fs::read_dir(&config_dir)? {
+                let entry = entry?;
+                if entry.file_name() != CONFIG_FILE {
+                    freed_bytes += entry.metadata()?.len();
+                    removed += 1;
+                }
+            }
+            std::fs::remove_dir_all(&config_dir)?;
+            println!("Removed stale extractor configuration {}", config_name);
+            continue;
+        }
+
+        for entry in std::fs::read_dir(&config_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == CONFIG_FILE {
+                continue;
+            }
+            if live_audio.contains(&name) {
+                kept += 1;
+            } else {
+                freed_bytes += entry.metadata()?.len();
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+    }
+
+    println!(
+        "Feature cache: removed {} entries ({} KB), kept {}",
+        removed,
+        freed_bytes / 1024,
+        kept
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cache_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn hit_returns_the_stored_features() {
+        let dir = temp_output_dir("hit");
+        let cache = FeatureCache::open(dir.to_str().unwrap(), FeatureKind::Simple).unwrap();
+        let hash = hash_bytes(b"recording");
+        let features = vec![1.5, -0.25, f64::MAX, 0.0];
+        cache.put(&hash, &features).unwrap();
+        assert_eq!(cache.get(&hash), Some(features));
+
+        // Only the entry and the config file are left behind
+        assert_eq!(std::fs::read_dir(&cache.dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn changed_audio_or_extractor_misses() {
+        let dir = temp_output_dir("miss");
+        let output_dir = dir.to_str().unwrap();
+        let simple = FeatureCache::open(output_dir, FeatureKind::Simple).unwrap();
+        simple.put(&hash_bytes(b"recording"), &[1.0, 2.0]).unwrap();
+
+        assert_eq!(simple.get(&hash_bytes(b"edited recording")), None);
+        let enhanced = FeatureCache::open(output_dir, FeatureKind::Enhanced).unwrap();
+        assert_eq!(enhanced.get(&hash_bytes(b"recording")), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writers_of_one_entry_do_not_clash() {
+        let dir = temp_output_dir("concurrent");
+        let cache = FeatureCache::open(dir.to_str().unwrap(), FeatureKind::Simple).unwrap();
+        let hash = hash_bytes(b"recording");
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let hash = hash.clone();
+                std::thread::spawn(move || (0..20).for_each(|_| cache.put(&hash, &[3.0; 16]).unwrap()))
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(cache.get(&hash), Some(vec![3.0; 16]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use crate::audio::AudioBuffer;
+use crate::clips::cut_recordings;
+use crate::dsp::rms_db;
+
+// Settings for splitting recordings into fixed-length windows
+#[derive(Debug, Clone)]
+pub struct ChunkConfig {
+    pub window_secs: f64,         // Length of each window
+    pub hop_secs: f64,            // Distance between window starts
+    pub pad_tail: bool,           // Zero-pad the last partial window instead of dropping it
+    pub min_rms_db: Option<f64>,  // Windows quieter than this (dBFS) are discarded
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        ChunkConfig {
+            window_secs: 3.0,
+            hop_secs: 3.0,
+            pad_tail: false,
+            min_rms_db: None,
+        }
+    }
+}
+
+// Window positions as (start_frame, end_frame) for a signal of `total_frames`.
+// With `pad_tail` the last window may run past the end of the signal.
+pub fn window_positions(total_frames: usize, sample_rate: u32, config: &ChunkConfig) -> Vec<(usize, usize)> {
+    let window = (config.window_secs * sample_rate as f64).round() as usize;
+    let hop = (config.hop_secs * sample_rate as f64).round() as usize;
+    if window == 0 || hop == 0 {
+        return Vec::new();
+    }
+
+    let mut positions = Vec::new();
+    let mut start = 0;
+    while start + window <= total_frames {
+        positions.push((start, start + window));
+        start += hop;
+    }
+    if config.pad_tail && start < total_frames {
+        positions.push((start, start + window));
+    }
+    positions
+}
+
+// Cut one recording into windows, zero-padding a partial tail window
+pub fn chunk_audio(audio: &AudioBuffer, config: &ChunkConfig) -> Vec<(f64, f64, AudioBuffer)> {
+    let sample_rate = audio.sample_rate as f64;
+    let mut chunks = Vec::new();
+
+    for (start, end) in window_positions(audio.frames(), audio.sample_rate, config) {
+        let mut window = audio.slice_frames(start, end);
+        let missing = end - start - window.frames();
+        window
+            .samples
+            .extend(std::iter::repeat_n(0.0, missing * audio.channels as usize));
+
+        if let Some(min_rms_db) = config.min_rms_db
+            && rms_db(&window.mono()) < min_rms_db
+        {
+            continue;
+        }
+
+        let end_secs = end.min(audio.frames()) as f64 / sample_rate;
+        chunks.push((start as f64 / sample_rate, end_secs, window));
+    }
+
+    chunks
+}
+
+// Split every downloaded recording in the directory into fixed-length clips
+pub fn chunk_directory(output_dir: &str, config: &ChunkConfig) -> Result<(), Box<dyn std::error::Error>> {
+    cut_recordings(output_dir, "chunk", |audio| chunk_audio(audio, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One-second windows at a 10 Hz sample rate keep the frame counts small
+    fn config(pad_tail: bool) -> ChunkConfig {
+        ChunkConfig { window_secs: 1.0, hop_secs: 1.0, pad_tail, min_rms_db: None }
+    }
+
+    #[test]
+    fn exact_multiple_has_no_tail_window() {
+        for pad_tail in [false, true] {
+            assert_eq!(window_positions(30, 10, &config(pad_tail)), vec![(0, 10), (10, 20), (20, 30)]);
+        }
+    }
+
+    #[test]
+    fn partial_tail_is_dropped_or_padded() {
+        assert_eq!(window_positions(25, 10, &config(false)), vec![(0, 10), (10, 20)]);
+        assert_eq!(window_positions(25, 10, &config(true)), vec![(0, 10), (10, 20), (20, 30)]);
+    }
+
+    #[test]
+    fn input_shorter_than_a_window() {
+        assert!(window_positions(5, 10, &config(false)).is_empty());
+        assert_eq!(window_positions(5, 10, &config(true)), vec![(0, 10)]);
+        assert!(window_positions(0, 10, &config(true)).is_empty());
+    }
+
+    #[test]
+    fn overlapping_windows_step_by_the_hop() {
+        let config = ChunkConfig { hop_secs: 0.5, ..config(false) };
+        assert_eq!(window_positions(20, 10, &config), vec![(0, 10), (5, 15), (10, 20)]);
+    }
+
+    #[test]
+    fn padded_tail_is_zero_filled_and_ends_at_the_signal() {
+        let audio = AudioBuffer { sample_rate: 10, channels: 2, samples: vec![0.5; 2 * 15] };
+        let chunks = chunk_audio(&audio, &config(true));
+        assert_eq!(chunks.len(), 2);
+        let (start_secs, end_secs, tail) = &chunks[1];
+        assert_eq!((*start_secs, *end_secs), (1.0, 1.5));
+        assert_eq!(tail.frames(), 10);
+        assert!(tail.samples[..10].iter().all(|&s| s == 0.5));
+        assert!(tail.samples[10..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn quiet_windows_are_skipped() {
+        // A loud second between two near-silent ones
+        let mut samples = vec![0.0001; 10];
+        samples.extend([0.5; 10]);
+        samples.extend([0.0001; 10]);
+        let audio = AudioBuffer { sample_rate: 10, channels: 1, samples };
+        let config = ChunkConfig { min_rms_db: Some(-40.0), ..config(false) };
+        let chunks = chunk_audio(&audio, &config);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!((chunks[0].0, chunks[0].1), (1.0, 2.0));
+    }
+}
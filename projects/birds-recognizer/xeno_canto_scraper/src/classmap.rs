@@ -0,0 +1,207 @@
+use crate::clips::{clips_csv_path, load_clips};
+use crate::mapping::ClassMapping;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+// Class map as the PWA stores it: [[label, index], ...]
+pub type ClassMap = Vec<(String, usize)>;
+
+// Read model/class-map.json, or an empty map if there is none yet
+pub fn load_class_map(path: &Path) -> Result<ClassMap, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let value: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let entries = value.as_array().ok_or("class map is not an array")?;
+    entries
+        .iter()
+        .map(|entry| match (entry.get(0).and_then(Value::as_str), entry.get(1).and_then(Value::as_u64)) {
+            (Some(label), Some(index)) => Ok((label.to_string(), index as usize)),
+            _ => Err(format!("invalid class map entry: {}", entry).into()),
+        })
+        .collect()
+}
+
+// Keep every label of `existing` at its index and append new species in
+// alphabetical order after the highest index, so a model trained on the old
+// map keeps its meaning. Labels that left the catalog keep their slot.
+pub fn extend_class_map(existing: &ClassMap, species: &BTreeSet<String>) -> ClassMap {
+    let mut map = existing.clone();
+    let known: BTreeSet<&str> = existing.iter().map(|(label, _)| label.as_str()).collect();
+    let mut next = existing.iter().map(|(_, index)| index + 1).max().unwrap_or(0);
+    for label in species {
+        if !known.contains(label.as_str()) {
+            map.push((label.clone(), next));
+            next += 1;
+        }
+    }
+    map.sort_by_key(|(_, index)| *index);
+    map
+}
+
+// The value seen most often, ignoring placeholders
+fn most_common<'a>(values: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for value in values {
+        let value = value.trim();
+        if !value.is_empty() && !value.eq_ignore_ascii_case("unknown") {
+            *counts.entry(value).or_insert(0) += 1;
+        }
+    }
+    counts.into_iter().max_by_key(|(_, count)| *count).map(|(value, _)| value.to_string())
+}
+
+// "barn_swallow" -> "Barn Swallow", for species without a scraped name
+fn display_name(label: &str) -> String {
+    label
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn attribution(recordists: &BTreeSet<String>) -> String {
+    const LISTED: usize = 3;
+    let names: Vec<&str> = recordists.iter().map(String::as_str).take(LISTED).collect();
+    match (names.len(), recordists.len() > LISTED) {
+        (0, _) => "Recordings from xeno-canto.org".to_string(),
+        (_, true) => format!("Recordings by {} and others, xeno-canto.org", names.join(", ")),
+        _ => format!("Recordings by {}, xeno-canto.org", names.join(", ")),
+    }
+}
+
+// Write <model_dir>/class-map.json from the species in the catalog and its
+// clips, and <model_dir>/species.json describing each class for display:
+// names, recording and clip counts, recordists and the image under
+// <model_dir>/../birds if there is one.
+pub fn export_class_map(output_dir: &str, model_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+    let mapping = ClassMapping::load(output_dir)?;
+    // Downloaded recordings with the class the mapping gives them
+    let downloaded: Vec<_> = metadata.iter().filter(|m| m.is_downloaded).map(|m| (mapping.recording_class(m), m)).collect();
+    let clips = load_clips(&clips_csv_path(output_dir))?;
+    // Classes that only exist as clips, like the one --build-unknown makes
+    let species: BTreeSet<String> =
+        downloaded.iter().map(|(class, _)| class.clone()).chain(clips.iter().map(|c| c.species.clone())).collect();
+
+    std::fs::create_dir_all(model_dir)?;
+    let class_map_path = model_dir.join("class-map.json");
+    let existing = load_class_map(&class_map_path)?;
+    let class_map = extend_class_map(&existing, &species);
+
+    let entries: Vec<Value> = class_map.iter().map(|(label, index)| json!([label, index])).collect();
+    std::fs::write(&class_map_path, serde_json::to_string(&entries)?)?;
+
+    let mut clip_counts: HashMap<String, usize> = HashMap::new();
+    for clip in clips {
+        *clip_counts.entry(clip.species).or_insert(0) += 1;
+    }
+
+    let birds_dir = model_dir.parent().unwrap_or(Path::new(".")).join("birds");
+    let manifest: Vec<Value> = class_map
+        .iter()
+        .map(|(label, index)| {
+            let recordings: Vec<_> = downloaded.iter().filter(|(class, _)| class == label).map(|(_, m)| *m).collect();
+            let recordists: BTreeSet<String> = recordings
+                .iter()
+                .map(|m| m.recordist.trim().to_string())
+                .filter(|r| !r.is_empty())
+                .collect();
+            let image = format!("{}.png", label.to_lowercase());
+            json!({
+                "label": label,
+                "index": index,
+                "common_name": most_common(recordings.iter().map(|m| m.common_name.as_str()))
+                    .unwrap_or_else(|| display_name(label)),
+                "scientific_name": most_common(recordings.iter().map(|m| m.scientific_name.as_str())),
+                "recordings": recordings.len(),
+                "clips": clip_counts.get(label).copied().unwrap_or(0),
+                "recordists": recordists,
+                "attribution": attribution(&recordists),
+                "image": if birds_dir.join(&image).exists() { Some(format!("birds/{}", image)) } else { None },
+            })
+        })
+        .collect();
+    std::fs::write(
+        model_dir.join("species.json"),
+        serde_json::to_string_pretty(&json!({ "species": manifest }))? + "\n",
+    )?;
+
+    let added = class_map.len() - existing.len();
+    let retired = existing.iter().filter(|(label, _)| !species.contains(label)).count();
+    println!(
+        "Wrote {} classes ({} new, {} no longer in the catalog) to {} and species.json",
+        class_map.len(),
+        added,
+        retired,
+        class_map_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordingMetadata;
+
+    fn labels(map: &ClassMap) -> Vec<(&str, usize)> {
+        map.iter().map(|(label, index)| (label.as_str(), *index)).collect()
+    }
+
+    #[test]
+    fn new_species_are_appended_after_the_existing_indices() {
+        let existing = vec![("wren".to_string(), 0), ("robin".to_string(), 1)];
+        let species: BTreeSet<String> = ["robin", "wren", "finch", "blackbird"].iter().map(|s| s.to_string()).collect();
+        let map = extend_class_map(&existing, &species);
+        assert_eq!(labels(&map), [("wren", 0), ("robin", 1), ("blackbird", 2), ("finch", 3)]);
+
+        // Running again changes nothing, and a species that left keeps its slot
+        assert_eq!(extend_class_map(&map, &species), map);
+        let fewer: BTreeSet<String> = ["finch".to_string()].into();
+        assert_eq!(extend_class_map(&map, &fewer), map);
+    }
+
+    #[test]
+    fn re_exporting_keeps_indices_stable() {
+        let dir = std::env::temp_dir().join(format!("classmap_export_{}", std::process::id()));
+        let model_dir = dir.join("model");
+        std::fs::create_dir_all(&dir).unwrap();
+        let output_dir = dir.to_str().unwrap();
+        let write_catalog = |species: &[&str]| {
+            let metadata: Vec<RecordingMetadata> = species
+                .iter()
+                .enumerate()
+                .map(|(i, species)| RecordingMetadata {
+                    id: i.to_string(),
+                    species: species.to_string(),
+                    is_downloaded: true,
+                    ..Default::default()
+                })
+                .collect();
+            crate::write_metadata_csv(&dir.join("metadata.csv"), &metadata).unwrap();
+        };
+
+        write_catalog(&["wren", "robin"]);
+        export_class_map(output_dir, &model_dir).unwrap();
+        let first = load_class_map(&model_dir.join("class-map.json")).unwrap();
+        assert_eq!(labels(&first), [("robin", 0), ("wren", 1)]);
+
+        write_catalog(&["wren", "robin", "finch", "blackbird"]);
+        export_class_map(output_dir, &model_dir).unwrap();
+        let second = load_class_map(&model_dir.join("class-map.json")).unwrap();
+        assert_eq!(labels(&second), [("robin", 0), ("wren", 1), ("blackbird", 2), ("finch", 3)]);
+
+        let manifest: Value =
+            serde_json::from_str(&std::fs::read_to_string(model_dir.join("species.json")).unwrap()).unwrap();
+        let species = manifest["species"].as_array().unwrap();
+        assert_eq!(species.len(), 4);
+        assert_eq!(species[3]["label"], "finch");
+        assert_eq!(species[3]["common_name"], "Finch");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+use crate::audio::{read_audio, write_wav, AudioBuffer};
+use crate::license::LicenseFilter;
+use crate::mapping::ClassMapping;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// Struct to hold metadata for a clip cut from a downloaded recording
+#[derive(Debug, Clone)]
+pub struct ClipMetadata {
+    pub filename: String,
+    pub species: String,
+    pub parent_id: String,       // ID of the recording in metadata.csv
+    pub parent_filename: String, // WAV the clip was cut from
+    pub start_secs: f64,         // Offset of the clip start in the parent
+    pub end_secs: f64,           // Offset of the clip end in the parent
+    pub source: String,          // Stage that produced the clip, e.g. "segment"
+    pub augmentation: String,    // Transformations applied by the augment stage, empty otherwise
+    pub verified: bool,          // Label confirmed by an annotator (see --apply-review)
+    pub low_hz: Option<f64>,     // Frequency range of an imported selection box
+    pub high_hz: Option<f64>,
+}
+
+// Clips live next to the recordings in a "clips" subdirectory
+pub fn clips_dir(output_dir: &str) -> std::path::PathBuf {
+    Path::new(output_dir).join("clips")
+}
+
+pub fn clips_csv_path(output_dir: &str) -> std::path::PathBuf {
+    Path::new(output_dir).join("clips.csv")
+}
+
+// Load clip metadata, returning an empty list if no clips were cut yet
+pub fn load_clips(clips_path: &Path) -> Result<Vec<ClipMetadata>, Box<dyn std::error::Error>> {
+    let mut clips = Vec::new();
+    if !clips_path.exists() {
+        return Ok(clips);
+    }
+
+    let mut reader = csv::Reader::from_path(clips_path)?;
+    for result in reader.records() {
+        let record = result?;
+        if record.len() >= 7 { // Basic validation
+            clips.push(ClipMetadata {
+                filename: record[0].to_string(),
+                species: record[1].to_string(),
+                parent_id: record[2].to_string(),
+                parent_filename: record[3].to_string(),
+                start_secs: record[4].parse().unwrap_or(0.0),
+                end_secs: record[5].parse().unwrap_or(0.0),
+                source: record[6].to_string(),
+                augmentation: record.get(7).unwrap_or("").to_string(),
+                verified: record.get(8) == Some("true"),
+                low_hz: record.get(9).and_then(|v| v.parse().ok()),
+                high_hz: record.get(10).and_then(|v| v.parse().ok()),
+            });
+        }
+    }
+
+    Ok(clips)
+}
+
+// Save clip metadata to CSV
+pub fn write_clips_csv(
+    clips_path: &Path,
+    clips: &[ClipMetadata]
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_path(clips_path)?;
+
+    writer.write_record([
+        "filename", "species", "parent_id", "parent_filename", "start_secs", "end_secs", "source", "augmentation", "verified",
+        "low_hz", "high_hz"
+    ])?;
+
+    for clip in clips {
+        writer.write_record([
+            &clip.filename,
+            &clip.species,
+            &clip.parent_id,
+            &clip.parent_filename,
+            &format!("{:.3}", clip.start_secs),
+            &format!("{:.3}", clip.end_secs),
+            &clip.source,
+            &clip.augmentation,
+            &clip.verified.to_string(),
+            &clip.low_hz.map(|hz| format!("{:.1}", hz)).unwrap_or_default(),
+            &clip.high_hz.map(|hz| format!("{:.1}", hz)).unwrap_or_default(),
+        ])?;
+    }
+
+    writer.flush()?;
+    println!("Clip metadata saved to {}", clips_path.display());
+    Ok(())
+}
+
+// ASCII spelling of a lowercase letter with diacritics
+fn fold_letter(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' | 'đ' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'ğ' => "g",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'ı' => "i",
+        'ł' | 'ľ' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'ř' => "r",
+        'ś' | 'š' | 'ş' => "s",
+        'ť' | 'ţ' => "t",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        'ß' => "ss",
+        'æ' => "ae",
+        'œ' => "oe",
+        'þ' => "th",
+        _ => return None,
+    })
+}
+
+// A class name reduced to the [a-z_] alphabet modules/training.js matches
+// clip filenames against: letters lose their diacritics, apostrophes and
+// periods are dropped and anything else separates words. None when no
+// letter is left.
+pub fn class_key(name: &str) -> Option<String> {
+    let mut key = String::new();
+    for c in name.to_lowercase().chars() {
+        if c.is_ascii_lowercase() {
+            key.push(c);
+        } else if let Some(folded) = fold_letter(c) {
+            key.push_str(folded);
+        } else if matches!(c, '\'' | '’' | '.') {
+            continue;
+        } else if !key.is_empty() && !key.ends_with('_') {
+            key.push('_');
+        }
+    }
+    let key = key.trim_end_matches('_');
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+// Next free clip filename of a class, class_number.wav with the class
+// reduced by class_key. Returns the class key, which is what the clip's
+// species must be, and the filename.
+pub fn next_clip_filename(
+    class: &str,
+    counters: &mut HashMap<String, usize>,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let key = class_key(class).ok_or_else(|| format!("class \"{}\" has no letters to name clips with", class))?;
+    let counter = counters.entry(key.clone()).or_insert(0);
+    *counter += 1;
+    let filename = format!("{}_{}.wav", key, counter);
+    Ok((key, filename))
+}
+
+// Highest clip number used so far for each species. Clips are numbered per
+// species like recordings are, so "arctic_tern_12.wav" still matches the
+// classname_number.wav pattern the training page expects.
+pub fn clip_counters(clips: &[ClipMetadata]) -> HashMap<String, usize> {
+    let mut counters = HashMap::new();
+    for clip in clips {
+        if let Some(number_str) = clip.filename.strip_prefix(&format!("{}_", clip.species))
+            && let Some(number_str) = number_str.strip_suffix(".wav")
+            && let Ok(number) = number_str.parse::<usize>()
+        {
+            let current_max = counters.entry(clip.species.clone()).or_insert(0);
+            if number > *current_max {
+                *current_max = number;
+            }
+        }
+    }
+    counters
+}
+
+// Give a clip a new species, renaming its file to the next free number of
+// that species so the classname_number.wav pattern keeps holding. Returns
+// the new filename.
+pub fn relabel_clip(
+    output_dir: &str,
+    clip: &mut ClipMetadata,
+    species: &str,
+    counters: &mut HashMap<String, usize>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (species, filename) = next_clip_filename(species, counters)?;
+    std::fs::rename(clips_dir(output_dir).join(&clip.filename), clips_dir(output_dir).join(&filename))?;
+    clip.filename = filename.clone();
+    clip.species = species;
+    Ok(filename)
+}
+
+// Run a clip-cutting stage over every downloaded recording in the directory.
+// `cut` returns (start_secs, end_secs, audio) for each clip of a recording.
+// Recordings that already have clips from this stage are skipped.
+pub fn cut_recordings<F>(output_dir: &str, source: &str, mut cut: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(&AudioBuffer) -> Vec<(f64, f64, AudioBuffer)>,
+{
+    let metadata_path = Path::new(output_dir).join("metadata.csv");
+    let metadata = crate::load_existing_metadata(&metadata_path)?;
+    let mapping = ClassMapping::load(output_dir)?;
+    let licenses = LicenseFilter::load(output_dir)?;
+
+    let clips_path = clips_csv_path(output_dir);
+    let mut clips = load_clips(&clips_path)?;
+    let mut counters = clip_counters(&clips);
+    std::fs::create_dir_all(clips_dir(output_dir))?;
+
+    let processed: HashSet<String> = clips
+        .iter()
+        .filter(|c| c.source == source)
+        .map(|c| c.parent_id.clone())
+        .collect();
+
+    let mut new_clips = 0;
+    let mut excluded = 0;
+    for meta in metadata.iter().filter(|m| m.is_downloaded) {
+        if processed.contains(&meta.id) {
+            continue;
+        }
+        if !licenses.allows_recording(meta) {
+            excluded += 1;
+            continue;
+        }
+
+        let recording_path = Path::new(output_dir).join(&meta.filename);
+        if !recording_path.exists() {
+            println!("Skipping {}: file not found", meta.filename);
+            continue;
+        }
+
+        let audio = match read_audio(&recording_path) {
+            Ok(audio) => audio,
+            Err(e) => {
+                println!("Error reading {}: {}", recording_path.display(), e);
+                continue;
+            }
+        };
+
+        let pieces = cut(&audio);
+        println!("Cut {} clips from {}", pieces.len(), meta.filename);
+
+        let class = mapping.recording_class(meta);
+        for (start_secs, end_secs, clip_audio) in pieces {
+            let (class, filename) = next_clip_filename(&class, &mut counters)?;
+            write_wav(&clips_dir(output_dir).join(&filename), &clip_audio)?;
+
+            clips.push(ClipMetadata {
+                filename,
+                species: class,
+                parent_id: meta.id.clone(),
+                parent_filename: meta.filename.clone(),
+                start_secs,
+                end_secs,
+                source: source.to_string(),
+                augmentation: String::new(),
+                verified: false,
+                low_hz: None,
+                high_hz: None,
+            });
+            new_clips += 1;
+        }
+    }
+
+    write_clips_csv(&clips_path, &clips)?;
+    if excluded > 0 {
+        println!("Skipped {} recordings with an excluded license", excluded);
+    }
+    println!("Wrote {} new {} clips to {}", new_clips, source, clips_dir(output_dir).display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The pattern modules/training.js accepts for clip filenames
+    fn matches_training_pattern(filename: &str) -> bool {
+        let Some((class, number)) = filename.strip_suffix(".wav").and_then(|stem| stem.rsplit_once('_')) else {
+            return false;
+        };
+        !class.is_empty()
+            && class.chars().all(|c| c.is_ascii_alphabetic() || c == '_')
+            && !number.is_empty()
+            && number.chars().all(|c| c.is_ascii_digit())
+    }
+
+    #[test]
+    fn class_key_reduces_names_to_letters_and_underscores() {
+        assert_eq!(class_key("Barn Swallow").as_deref(), Some("barn_swallow"));
+        assert_eq!(class_key("Black-headed Gull").as_deref(), Some("black_headed_gull"));
+        assert_eq!(class_key("Rüppell's Warbler").as_deref(), Some("ruppells_warbler"));
+        assert_eq!(class_key("gull sp.").as_deref(), Some("gull_sp"));
+        assert_eq!(class_key("  Great -- Tit  ").as_deref(), Some("great_tit"));
+        assert_eq!(class_key("comgal1").as_deref(), Some("comgal"));
+        assert_eq!(class_key("barn_swallow").as_deref(), Some("barn_swallow"));
+        assert_eq!(class_key("123 -- '"), None);
+        assert_eq!(class_key("сорока"), None);
+    }
+
+    #[test]
+    fn clip_filenames_match_the_training_pattern() {
+        let mut counters = HashMap::new();
+        for name in ["Black-headed Gull", "Rüppell's Warbler", "gull sp.", "black_headed_gull"] {
+            let (class, filename) = next_clip_filename(name, &mut counters).unwrap();
+            assert!(matches_training_pattern(&filename), "{}", filename);
+            assert_eq!(filename.rsplit_once('_').unwrap().0, class);
+        }
+        // Both spellings of the gull share one class and its numbering
+        assert_eq!(counters["black_headed_gull"], 2);
+        assert!(next_clip_filename("123", &mut counters).is_err());
+    }
+
+    #[test]
+    fn counters_continue_after_existing_clips() {
+        let clip = |filename: &str, species: &str| ClipMetadata {
+            filename: filename.to_string(),
+            species: species.to_string(),
+            parent_id: String::new(),
+            parent_filename: String::new(),
+            start_secs: 0.0,
+            end_secs: 1.0,
+            source: "chunk".to_string(),
+            augmentation: String::new(),
+            verified: false,
+            low_hz: None,
+            high_hz: None,
+        };
+        let mut counters = clip_counters(&[clip("great_tit_7.wav", "great_tit"), clip("great_tit_3.wav", "great_tit")]);
+        let (_, filename) = next_clip_filename("Great Tit", &mut counters).unwrap();
+        assert_eq!(filename, "great_tit_8.wav");
+    }
+}
@@ -0,0 +1,423 @@
+use crate::audio::decode_file_bytes;
+use crate::cache::{hash_bytes, FeatureCache};
+use crate::clips::{clips_csv_path, clips_dir, load_clips};
+use crate::features::FeatureKind;
+use crate::license::LicenseFilter;
+use crate::mapping::ClassMapping;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+// Feature dataset file (.bin). All integers are little-endian and strings
+// are a u16 byte length followed by UTF-8 bytes.
+//
+//   [u8; 4]  magic "BRDS"
+//   u32      format version (2)
+//   string   extractor name, e.g. "mfcc-enhanced"
+//   u32      feature dimension D
+//   u32      number of labels L
+//   L x string  label names; a record's label index points into this list
+//   u32      number of records N
+//   N x record:
+//     u32      label index
+//     string   recording id (the xeno-canto id; for clips, the parent's)
+//     string   file, relative to the catalog ("clips/<name>" for clips)
+//     string   source: "recording", or the clip's source in clips.csv
+//              (segment, chunk, augment, labels or unknown)
+//     D x f32  feature vector
+pub const DATASET_MAGIC: &[u8; 4] = b"BRDS";
+pub const DATASET_VERSION: u32 = 2;
+
+// One feature vector with its label and the recording and file it came from
+#[derive(Debug, Clone)]
+pub struct DatasetRecord {
+    pub label: u32,
+    pub recording_id: String,
+    pub file: String,
+    pub source: String,
+    pub features: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    pub extractor: String,
+    pub dim: usize,
+    pub labels: Vec<String>,
+    pub records: Vec<DatasetRecord>,
+}
+
+// Audio file to extract features from
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub path: PathBuf,
+    pub species: String,
+    pub recording_id: String,
+    pub file: String,   // Relative to the catalog
+    pub source: String, // "recording" or the clip's source
+}
+
+// Length-prefixed UTF-8; strings over 64 KiB cannot be stored
+pub fn write_string(writer: &mut impl Write, value: &str) -> std::io::Result<()> {
+    let length = u16::try_from(value.len()).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("string of {} bytes is too long to store", value.len()))
+    })?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+pub fn write_dataset(path: &Path, dataset: &Dataset) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(DATASET_MAGIC)?;
+    writer.write_all(&DATASET_VERSION.to_le_bytes())?;
+    write_string(&mut writer, &dataset.extractor)?;
+    writer.write_all(&(dataset.dim as u32).to_le_bytes())?;
+
+    writer.write_all(&(dataset.labels.len() as u32).to_le_bytes())?;
+    for label in &dataset.labels {
+        write_string(&mut writer, label)?;
+    }
+
+    writer.write_all(&(dataset.records.len() as u32).to_le_bytes())?;
+    for record in &dataset.records {
+        writer.write_all(&record.label.to_le_bytes())?;
+        write_string(&mut writer, &record.recording_id)?;
+        write_string(&mut writer, &record.file)?;
+        write_string(&mut writer, &record.source)?;
+        for value in &record.features {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_string(reader: &mut impl Read) -> std::io::Result<String> {
+    let mut length = [0; 2];
+    reader.read_exact(&mut length)?;
+    let mut bytes = vec![0; u16::from_le_bytes(length) as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+pub fn read_dataset(path: &Path) -> Result<Dataset, Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != DATASET_MAGIC {
+        return Err(format!("{} is not a feature dataset", path.display()).into());
+    }
+    let version = read_u32(&mut reader)?;
+    if version != DATASET_VERSION {
+        return Err(format!("Unsupported dataset version {} in {}", version, path.display()).into());
+    }
+    let extractor = read_string(&mut reader)?;
+    let dim = read_u32(&mut reader)? as usize;
+
+    let label_count = read_u32(&mut reader)?;
+    let labels = (0..label_count).map(|_| read_string(&mut reader)).collect::<Result<Vec<_>, _>>()?;
+
+    let record_count = read_u32(&mut reader)?;
+    let mut records = Vec::with_capacity(record_count as usize);
+    for _ in 0..record_count {
+        let label = read_u32(&mut reader)?;
+        let recording_id = read_string(&mut reader)?;
+        let file = read_string(&mut reader)?;
+        let source = read_string(&mut reader)?;
+        let mut bytes = vec![0; dim * 4];
+        reader.read_exact(&mut bytes)?;
+        let features = bytes.chunks_exact(4).map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap())).collect();
+        records.push(DatasetRecord { label, recording_id, file, source, features });
+    }
+
+    Ok(Dataset { extractor, dim, labels, records })
+}
+
+// Write a NumPy .npy (format 1.0) array. The header is padded so the data
+// starts on a 64-byte boundary, as numpy itself does.
+pub fn write_npy(path: &Path, descr: &str, shape: &[usize], data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => format!("({})", shape.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    let header_length = u16::try_from(header.len()).map_err(|_| format!("npy header too long for shape {}", shape))?;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&header_length.to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Export the dataset next to `path` as <stem>.features.npy (float32, N x D),
+// <stem>.labels.npy (int32, N), <stem>.ids.txt and <stem>.classes.txt
+// (one entry per line, in record and label index order). Each ids.txt line
+// holds the recording id, file and source, separated by tabs.
+pub fn export_npy(path: &Path, dataset: &Dataset) -> Result<(), Box<dyn std::error::Error>> {
+    let sibling = |suffix: &str| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}.{}", stem, suffix))
+    };
+
+    let features: Vec<u8> = dataset
+        .records
+        .iter()
+        .flat_map(|r| r.features.iter().flat_map(|v| v.to_le_bytes()))
+        .collect();
+    write_npy(&sibling("features.npy"), "<f4", &[dataset.records.len(), dataset.dim], &features)?;
+
+    let labels: Vec<u8> = dataset.records.iter().flat_map(|r| (r.label as i32).to_le_bytes()).collect();
+    write_npy(&sibling("labels.npy"), "<i4", &[dataset.records.len()], &labels)?;
+
+    let ids: String =
+        dataset.records.iter().map(|r| format!("{}\t{}\t{}\n", r.recording_id, r.file, r.source)).collect();
+    std::fs::write(sibling("ids.txt"), ids)?;
+    let classes: String = dataset.labels.iter().map(|l| format!("{}\n", l)).collect();
+    std::fs::write(sibling("classes.txt"), classes)?;
+    Ok(())
+}
+
+// Downloaded recordings, or all clips when `use_clips` is set, leaving
+// out those of recordings whose license the catalog excludes
+pub fn collect_samples(output_dir: &str, use_clips: bool) -> Result<Vec<Sample>, Box<dyn std::error::Error>> {
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+    let excluded = LicenseFilter::load(output_dir)?.excluded_ids(&metadata);
+    if use_clips {
+        let clips = load_clips(&clips_csv_path(output_dir))?;
+        return Ok(clips
+            .into_iter()
+            .filter(|clip| !excluded.contains(&clip.parent_id))
+            .map(|clip| Sample {
+                path: clips_dir(output_dir).join(&clip.filename),
+                species: clip.species,
+                recording_id: clip.parent_id,
+                file: format!("clips/{}", clip.filename),
+                source: clip.source,
+            })
+            .collect());
+    }
+
+    let mapping = ClassMapping::load(output_dir)?;
+    Ok(metadata
+        .into_iter()
+        .filter(|m| m.is_downloaded && !excluded.contains(&m.id))
+        .map(|m| Sample {
+            path: Path::new(output_dir).join(&m.filename),
+            species: mapping.recording_class(&m),
+            recording_id: m.id,
+            file: m.filename,
+            source: "recording".to_string(),
+        })
+        .collect())
+}
+
+// Compute features for every recording (or clip) in the catalog on
+// `threads` workers and write them to a dataset file. With a cache, files
+// whose contents were already processed with the same extractor are not
+// decoded again.
+pub fn extract_features(
+    output_dir: &str,
+    output_path: &Path,
+    kind: FeatureKind,
+    use_clips: bool,
+    threads: usize,
+    npy: bool,
+    cache: Option<FeatureCache>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let samples = collect_samples(output_dir, use_clips)?;
+    if samples.is_empty() {
+        println!("Nothing to extract in {}", output_dir);
+        return Ok(());
+    }
+
+    // Labels are the species in alphabetical order
+    let labels: Vec<String> = samples
+        .iter()
+        .map(|s| s.species.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let pool = ThreadPool::new(threads.max(1));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let cache_hits = Arc::new(AtomicUsize::new(0));
+
+    for (index, sample) in samples.iter().cloned().enumerate() {
+        let results = Arc::clone(&results);
+        let cache_hits = Arc::clone(&cache_hits);
+        let cache = cache.clone();
+        pool.execute(move || {
+            let bytes = match std::fs::read(&sample.path) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    println!("Error reading {}: {}", sample.path.display(), e);
+                    return;
+                }
+            };
+
+            let audio_hash = cache.as_ref().map(|_| hash_bytes(&bytes));
+            if let (Some(cache), Some(audio_hash)) = (&cache, &audio_hash)
+                && let Some(features) = cache.get(audio_hash)
+                && features.len() == kind.dim()
+            {
+                cache_hits.fetch_add(1, Ordering::Relaxed);
+                results.lock().unwrap().push((index, features));
+                return;
+            }
+
+            let features = match decode_file_bytes(&sample.path, &bytes) {
+                Ok(audio) => kind.extract(&audio),
+                Err(e) => {
+                    println!("Error decoding {}: {}", sample.path.display(), e);
+                    return;
+                }
+            };
+            match features {
+                Some(features) => {
+                    if let (Some(cache), Some(audio_hash)) = (&cache, &audio_hash)
+                        && let Err(e) = cache.put(audio_hash, &features)
+                    {
+                        println!("Error caching features for {}: {}", sample.path.display(), e);
+                    }
+                    results.lock().unwrap().push((index, features));
+                }
+                None => println!("Skipping {}: shorter than one analysis frame", sample.path.display()),
+            }
+        });
+    }
+    pool.join();
+
+    // Keep records in catalog order regardless of which worker finished first
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by_key(|(index, _)| *index);
+
+    let records = results
+        .into_iter()
+        .map(|(index, features)| {
+            let sample = &samples[index];
+            DatasetRecord {
+                label: labels.iter().position(|l| *l == sample.species).unwrap() as u32,
+                recording_id: sample.recording_id.clone(),
+                file: sample.file.clone(),
+                source: sample.source.clone(),
+                features: features.into_iter().map(|v| v as f32).collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let dataset = Dataset {
+        extractor: kind.name().to_string(),
+        dim: kind.dim(),
+        labels,
+        records,
+    };
+    write_dataset(output_path, &dataset)?;
+    if npy {
+        export_npy(output_path, &dataset)?;
+    }
+
+    println!(
+        "Wrote {} of {} feature vectors ({}, {} classes, {} from cache) to {}",
+        dataset.records.len(),
+        samples.len(),
+        dataset.extractor,
+        dataset.labels.len(),
+        cache_hits.load(Ordering::Relaxed),
+        output_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_dataset() -> Dataset {
+        Dataset {
+            extractor: "mfcc".to_string(),
+            dim: 2,
+            labels: vec!["robin".to_string(), "wren".to_string()],
+            records: vec![
+                DatasetRecord {
+                    label: 1,
+                    recording_id: "123".to_string(),
+                    file: "wren/XC123.wav".to_string(),
+                    source: "recording".to_string(),
+                    features: vec![0.5, -1.0],
+                },
+                DatasetRecord {
+                    label: 0,
+                    recording_id: "456".to_string(),
+                    file: "clips/robin_3.wav".to_string(),
+                    source: "augment".to_string(),
+                    features: vec![2.0, 0.0],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn records_keep_their_file_and_source() {
+        let path = std::env::temp_dir().join(format!("dataset_round_trip_{}.bin", std::process::id()));
+        let dataset = sample_dataset();
+        write_dataset(&path, &dataset).unwrap();
+        let read = read_dataset(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.labels, dataset.labels);
+        assert_eq!(read.records.len(), 2);
+        for (read, written) in read.records.iter().zip(&dataset.records) {
+            assert_eq!(read.label, written.label);
+            assert_eq!(read.recording_id, written.recording_id);
+            assert_eq!(read.file, written.file);
+            assert_eq!(read.source, written.source);
+            assert_eq!(read.features, written.features);
+        }
+    }
+
+    #[test]
+    fn strings_too_long_for_the_length_prefix_are_an_error() {
+        let mut bytes = Vec::new();
+        assert!(write_string(&mut bytes, &"x".repeat(70_000)).is_err());
+        assert!(bytes.is_empty());
+        write_string(&mut bytes, &"x".repeat(u16::MAX as usize)).unwrap();
+        assert_eq!(read_string(&mut bytes.as_slice()).unwrap().len(), u16::MAX as usize);
+    }
+
+    #[test]
+    fn npy_export_writes_aligned_arrays_and_id_columns() {
+        let path = std::env::temp_dir().join(format!("dataset_npy_{}.bin", std::process::id()));
+        export_npy(&path, &sample_dataset()).unwrap();
+        let sibling = |suffix: &str| path.with_file_name(format!("dataset_npy_{}.{}", std::process::id(), suffix));
+
+        let features = std::fs::read(sibling("features.npy")).unwrap();
+        assert_eq!(&features[..8], b"\x93NUMPY\x01\x00");
+        let header_length = u16::from_le_bytes([features[8], features[9]]) as usize;
+        assert_eq!((10 + header_length) % 64, 0);
+        assert!(String::from_utf8_lossy(&features[10..10 + header_length]).contains("'shape': (2, 2)"));
+        assert_eq!(features.len(), 10 + header_length + 2 * 2 * 4);
+
+        let ids = std::fs::read_to_string(sibling("ids.txt")).unwrap();
+        assert_eq!(ids, "123\twren/XC123.wav\trecording\n456\tclips/robin_3.wav\taugment\n");
+        for suffix in ["features.npy", "labels.npy", "ids.txt", "classes.txt"] {
+            std::fs::remove_file(sibling(suffix)).unwrap();
+        }
+    }
+}
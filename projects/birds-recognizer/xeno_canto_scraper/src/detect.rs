@@ -0,0 +1,237 @@
+use crate::audio::read_audio;
+use crate::chunk::{chunk_audio, ChunkConfig};
+use crate::labels::{write_audacity_labels, write_raven_selections, LabelRegion};
+use crate::model::Classifier;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+#[derive(Debug, Clone)]
+pub struct DetectConfig {
+    pub window: ChunkConfig,  // Same windowing as --chunk
+    pub threshold: f32,       // Minimum score for a window to count as a hit
+    pub merge_gap_secs: f64,  // Hits of a species closer than this are merged
+    pub min_windows: usize,   // Detections built from fewer hits are dropped
+    pub output_dir: PathBuf,
+    pub threads: usize,
+}
+
+impl Default for DetectConfig {
+    fn default() -> Self {
+        DetectConfig {
+            window: ChunkConfig { window_secs: 3.0, hop_secs: 1.5, pad_tail: true, min_rms_db: None },
+            threshold: 0.5,
+            merge_gap_secs: 0.0,
+            min_windows: 1,
+            output_dir: PathBuf::from("detections"),
+            threads: 4,
+        }
+    }
+}
+
+// Duration, sample rate, window count and detections of one recording
+type FileDetections = (f64, u32, usize, Vec<Detection>);
+
+// Consecutive windows in which one species scored above the threshold
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub species: String,
+    pub start_secs: f64,
+    pub end_secs: f64,
+    pub max_score: f32,
+    pub mean_score: f32,
+    pub windows: usize,
+}
+
+// Merge per-window hits (start, end, species, score) into detections.
+// Hits of the same species that overlap or are at most `gap` seconds
+// apart become one detection.
+pub fn merge_hits(hits: &[(f64, f64, String, f32)], gap: f64, min_windows: usize) -> Vec<Detection> {
+    let mut hits = hits.to_vec();
+    hits.sort_by(|a, b| a.2.cmp(&b.2).then(a.0.total_cmp(&b.0)));
+
+    let mut detections: Vec<Detection> = Vec::new();
+    let mut sums: Vec<f32> = Vec::new();
+    for (start, end, species, score) in hits {
+        if let Some(last) = detections.last_mut()
+            && last.species == species
+            && start <= last.end_secs + gap
+        {
+            last.end_secs = last.end_secs.max(end);
+            last.max_score = last.max_score.max(score);
+            last.windows += 1;
+            *sums.last_mut().unwrap() += score;
+            continue;
+        }
+        detections.push(Detection { species, start_secs: start, end_secs: end, max_score: score, mean_score: 0.0, windows: 1 });
+        sums.push(score);
+    }
+    for (detection, sum) in detections.iter_mut().zip(sums) {
+        detection.mean_score = sum / detection.windows as f32;
+    }
+    detections.retain(|d| d.windows >= min_windows);
+    detections.sort_by(|a, b| a.start_secs.total_cmp(&b.start_secs).then(a.species.cmp(&b.species)));
+    detections
+}
+
+// Classify every window of one recording in parallel and merge the hits
+fn detect_file(
+    classifier: &Arc<Classifier>,
+    pool: &ThreadPool,
+    path: &Path,
+    config: &DetectConfig,
+) -> Result<FileDetections, Box<dyn std::error::Error>> {
+    let audio = read_audio(path)?;
+    let duration = audio.frames() as f64 / audio.sample_rate as f64;
+    let windows = chunk_audio(&audio, &config.window);
+    let window_count = windows.len();
+
+    let hits = Arc::new(Mutex::new(Vec::new()));
+    for (start, end, window) in windows {
+        let hits = Arc::clone(&hits);
+        let classifier = Arc::clone(classifier);
+        let threshold = config.threshold;
+        let path = path.to_path_buf();
+        pool.execute(move || match classifier.classify(&window) {
+            Ok(ranked) => {
+                let mut hits = hits.lock().unwrap();
+                for (species, score) in ranked.into_iter().filter(|(_, score)| *score >= threshold) {
+                    hits.push((start, end, species, score));
+                }
+            }
+            Err(e) => println!("Error classifying {} at {:.2}s: {}", path.display(), start, e),
+        });
+    }
+    pool.join();
+
+    let hits = std::mem::take(&mut *hits.lock().unwrap());
+    Ok((duration, audio.sample_rate, window_count, merge_hits(&hits, config.merge_gap_secs, config.min_windows)))
+}
+
+// Run the classifier over overlapping windows of each recording and write
+// timestamped detections to detections.csv and detections.json, plus an
+// Audacity label track and a Raven selection table per recording.
+pub fn detect_files(
+    classifier: &Classifier,
+    files: &[PathBuf],
+    config: &DetectConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(&config.output_dir)?;
+    let classifier = Arc::new(classifier.clone());
+    let pool = ThreadPool::new(config.threads.max(1));
+
+    let mut csv_writer = csv::Writer::from_path(config.output_dir.join("detections.csv"))?;
+    csv_writer.write_record(["file", "species", "start_secs", "end_secs", "max_score", "mean_score", "windows"])?;
+    let mut report = Vec::new();
+    let mut total = 0;
+
+    for path in files {
+        let (duration, sample_rate, window_count, detections) = match detect_file(&classifier, &pool, path, config) {
+            Ok(result) => result,
+            Err(e) => {
+                println!("Error reading {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let file = path.display().to_string();
+        println!("{}: {} detections in {} windows ({:.1}s)", file, detections.len(), window_count, duration);
+        for d in &detections {
+            println!("  {:>8.2}-{:<8.2} {} (max {:.3}, {} windows)", d.start_secs, d.end_secs, d.species, d.max_score, d.windows);
+            csv_writer.write_record([
+                file.clone(),
+                d.species.clone(),
+                format!("{:.3}", d.start_secs),
+                format!("{:.3}", d.end_secs),
+                format!("{:.6}", d.max_score),
+                format!("{:.6}", d.mean_score),
+                d.windows.to_string(),
+            ])?;
+        }
+
+        let regions: Vec<LabelRegion> = detections
+            .iter()
+            .map(|d| LabelRegion {
+                start_secs: d.start_secs,
+                end_secs: d.end_secs,
+                low_hz: None,
+                high_hz: None,
+                label: d.species.clone(),
+                score: Some(d.max_score),
+            })
+            .collect();
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        write_audacity_labels(&config.output_dir.join(format!("{}.labels.txt", stem)), &regions)?;
+        write_raven_selections(
+            &config.output_dir.join(format!("{}.Table.1.selections.txt", stem)),
+            &regions,
+            sample_rate as f64 / 2.0,
+        )?;
+
+        total += detections.len();
+        report.push(json!({
+            "file": file,
+            "duration_secs": duration,
+            "windows": window_count,
+            "detections": detections.iter().map(|d| json!({
+                "species": d.species,
+                "start_secs": d.start_secs,
+                "end_secs": d.end_secs,
+                "max_score": d.max_score,
+                "mean_score": d.mean_score,
+                "windows": d.windows,
+            })).collect::<Vec<_>>(),
+        }));
+    }
+    csv_writer.flush()?;
+
+    let summary = json!({
+        "extractor": classifier.kind.name(),
+        "window_secs": config.window.window_secs,
+        "hop_secs": config.window.hop_secs,
+        "threshold": config.threshold,
+        "files": report,
+    });
+    std::fs::write(config.output_dir.join("detections.json"), serde_json::to_string_pretty(&summary)? + "\n")?;
+    println!("Wrote {} detections from {} files to {}", total, report.len(), config.output_dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(start: f64, species: &str, score: f32) -> (f64, f64, String, f32) {
+        (start, start + 3.0, species.to_string(), score)
+    }
+
+    fn spans(detections: &[Detection]) -> Vec<(&str, f64, f64, usize)> {
+        detections.iter().map(|d| (d.species.as_str(), d.start_secs, d.end_secs, d.windows)).collect()
+    }
+
+    #[test]
+    fn overlapping_and_close_hits_merge() {
+        // Windows at 0 and 1.5 overlap; the one at 6 leaves a 1.5 s gap
+        let hits = [hit(0.0, "wren", 0.6), hit(1.5, "wren", 0.8), hit(6.0, "wren", 0.7)];
+        assert_eq!(spans(&merge_hits(&hits, 0.0, 1)), [("wren", 0.0, 4.5, 2), ("wren", 6.0, 9.0, 1)]);
+        assert_eq!(spans(&merge_hits(&hits, 1.5, 1)), [("wren", 0.0, 9.0, 3)]);
+
+        let merged = &merge_hits(&hits, 0.0, 1)[0];
+        assert_eq!(merged.max_score, 0.8);
+        assert!((merged.mean_score - 0.7).abs() < 1e-6);
+    }
+
+    #[test]
+    fn detections_with_too_few_windows_are_dropped() {
+        let hits = [hit(0.0, "wren", 0.6), hit(1.5, "wren", 0.8), hit(9.0, "wren", 0.9)];
+        assert_eq!(spans(&merge_hits(&hits, 0.0, 2)), [("wren", 0.0, 4.5, 2)]);
+        assert!(merge_hits(&hits, 0.0, 3).is_empty());
+    }
+
+    #[test]
+    fn species_are_merged_separately() {
+        // Interleaved hits of two species in arbitrary order
+        let hits = [hit(1.5, "robin", 0.7), hit(0.0, "wren", 0.6), hit(1.5, "wren", 0.9), hit(0.0, "robin", 0.5)];
+        assert_eq!(spans(&merge_hits(&hits, 0.0, 1)), [("robin", 0.0, 4.5, 2), ("wren", 0.0, 4.5, 2)]);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::jsmath;
+use std::f64::consts::PI;
+
+// Radix-2 FFT of a real-valued signal, returning (real, imag).
+// This mirrors myFft in modules/fft.js step for step, twiddle factors
+// included, so that spectra computed here match the ones the browser
+// computes bit for bit. The length must be a power of 2.
+pub fn fft(signal: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let n = signal.len();
+    if n == 0 {
+        return (Vec::new(), Vec::new());
+    }
+    if n == 1 {
+        return (vec![signal[0]], vec![0.0]);
+    }
+    assert!(n.is_power_of_two(), "Signal length is not a power of 2.");
+
+    // Separate even and odd indexed elements
+    let even: Vec<f64> = signal.iter().step_by(2).copied().collect();
+    let odd: Vec<f64> = signal.iter().skip(1).step_by(2).copied().collect();
+
+    let (even_real, even_imag) = fft(&even);
+    let (odd_real, odd_imag) = fft(&odd);
+
+    let mut real = vec![0.0; n];
+    let mut imag = vec![0.0; n];
+
+    for k in 0..n / 2 {
+        let angle = (-2.0 * PI * k as f64) / n as f64;
+        let cos = jsmath::cos(angle);
+        let sin = jsmath::sin(angle);
+        // Multiply odd[k] by the twiddle factor e^(-j*2pi*k/N)
+        let odd_re = odd_real[k] * cos - odd_imag[k] * sin;
+        let odd_im = odd_real[k] * sin + odd_imag[k] * cos;
+
+        real[k] = even_real[k] + odd_re;
+        imag[k] = even_imag[k] + odd_im;
+
+        real[k + n / 2] = even_real[k] - odd_re;
+        imag[k + n / 2] = even_imag[k] - odd_im;
+    }
+
+    (real, imag)
+}
+
+// Hamming window as applied by applyHammingWindow in modules/mfcc.js.
+// The browser stores the windowed frame in a Float32Array, so we round the
+// same way before handing it to the FFT.
+pub fn hamming_window(frame: &[f32]) -> Vec<f64> {
+    let n = frame.len();
+    frame
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let w = 0.54 - 0.46 * jsmath::cos((2.0 * PI * i as f64) / (n as f64 - 1.0));
+            (x as f64 * w) as f32 as f64
+        })
+        .collect()
+}
+
+// Power spectrum |X[k]|^2 / N for bins [0, N/2] of a windowed frame
+pub fn power_spectrum(windowed: &[f64]) -> Vec<f64> {
+    let n = windowed.len();
+    let (real, imag) = fft(windowed);
+    (0..=n / 2)
+        .map(|k| (real[k] * real[k] + imag[k] * imag[k]) / n as f64)
+        .collect()
+}
+
+// RMS level of a signal in dBFS
+pub fn rms_db(signal: &[f32]) -> f64 {
+    if signal.is_empty() {
+        return f64::NEG_INFINITY;
+    }
+    let mean_square = signal.iter().map(|&x| (x as f64) * (x as f64)).sum::<f64>() / signal.len() as f64;
+    10.0 * (mean_square + 1e-12).log10()
+}
+
+// Second-order IIR filter section (direct form I)
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    pub fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Biquad { b0, b1, b2, a1, a2 }
+    }
+
+    // Filter one channel of samples
+    pub fn process(&self, signal: &[f64]) -> Vec<f64> {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        signal
+            .iter()
+            .map(|&x| {
+                let y = self.b0 * x + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+                x2 = x1;
+                x1 = x;
+                y2 = y1;
+                y1 = y;
+                y
+            })
+            .collect()
+    }
+}
+
+// In-place iterative radix-2 FFT over complex data. With `inverse` set this
+// computes the unscaled inverse transform (divide by N afterwards).
+pub fn fft_complex(real: &mut [f64], imag: &mut [f64], inverse: bool) {
+    let n = real.len();
+    assert!(n.is_power_of_two(), "Signal length is not a power of 2.");
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imag.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut size = 2;
+    while size <= n {
+        let angle = sign * 2.0 * PI / size as f64;
+        for start in (0..n).step_by(size) {
+            for k in 0..size / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + size / 2;
+                let odd_re = real[b] * cos - imag[b] * sin;
+                let odd_im = real[b] * sin + imag[b] * cos;
+                real[b] = real[a] - odd_re;
+                imag[b] = imag[a] - odd_im;
+                real[a] += odd_re;
+                imag[a] += odd_im;
+            }
+        }
+        size *= 2;
+    }
+}
@@ -0,0 +1,407 @@
+use crate::audio::read_audio;
+use crate::clips::{clips_csv_path, load_clips};
+use crate::html::escape_html;
+use crate::model::Classifier;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+// What to evaluate and where to write the reports
+#[derive(Debug, Clone)]
+pub struct EvaluateConfig {
+    pub split: String,     // Manifest to evaluate, usually "test"
+    pub use_clips: bool,   // Clip manifest instead of whole recordings
+    pub top_k: usize,      // Also count a hit when the label is among the k best
+    pub worst: usize,      // Number of misclassified files to list
+    pub report_dir: PathBuf,
+    pub threads: usize,
+}
+
+// One evaluated file with the row it came from in the catalog
+struct Prediction {
+    file: String,          // Relative to the output directory
+    catalog: &'static str, // metadata.csv or clips.csv
+    catalog_row: usize,    // Line in the catalog, counting the header as 1
+    truth: usize,
+    ranked: Vec<(usize, f32)>,
+}
+
+impl Prediction {
+    fn predicted(&self) -> usize {
+        self.ranked[0].0
+    }
+
+    fn score_of(&self, label: usize) -> f32 {
+        self.ranked.iter().find(|(l, _)| *l == label).map_or(0.0, |(_, s)| *s)
+    }
+}
+
+struct ClassMetrics {
+    precision: f64,
+    recall: f64,
+    f1: f64,
+    support: usize,
+}
+
+fn class_metrics(confusion: &[Vec<usize>]) -> Vec<ClassMetrics> {
+    (0..confusion.len())
+        .map(|c| {
+            let true_positives = confusion[c][c] as f64;
+            let support: usize = confusion[c].iter().sum();
+            let predicted: usize = confusion.iter().map(|row| row[c]).sum();
+            let precision = if predicted > 0 { true_positives / predicted as f64 } else { 0.0 };
+            let recall = if support > 0 { true_positives / support as f64 } else { 0.0 };
+            let f1 = if precision + recall > 0.0 { 2.0 * precision * recall / (precision + recall) } else { 0.0 };
+            ClassMetrics { precision, recall, f1, support }
+        })
+        .collect()
+}
+
+// Misclassified predictions, most confidently wrong (highest score for the
+// predicted class) first, at most `count` of them
+fn most_confidently_wrong(predictions: &[Prediction], count: usize) -> Vec<&Prediction> {
+    let mut wrong: Vec<&Prediction> = predictions.iter().filter(|p| p.predicted() != p.truth).collect();
+    wrong.sort_by(|a, b| b.ranked[0].1.total_cmp(&a.ranked[0].1));
+    wrong.truncate(count);
+    wrong
+}
+
+// Manifest row as (file, species, catalog, catalog row)
+type ManifestRow = (String, String, &'static str, usize);
+
+// Rows of the split manifest. The catalog row is looked up so misclassified
+// files can be traced back.
+fn manifest_rows(output_dir: &str, config: &EvaluateConfig) -> Result<Vec<ManifestRow>, Box<dyn std::error::Error>> {
+    let name = if config.use_clips { format!("{}_clips.csv", config.split) } else { format!("{}.csv", config.split) };
+    let manifest_path = Path::new(output_dir).join("splits").join(&name);
+    if !manifest_path.exists() {
+        return Err(format!("{} not found, run --split first", manifest_path.display()).into());
+    }
+
+    let catalog_rows: HashMap<String, usize> = if config.use_clips {
+        load_clips(&clips_csv_path(output_dir))?
+            .into_iter()
+            .enumerate()
+            .map(|(i, clip)| (format!("clips/{}", clip.filename), i + 2))
+            .collect()
+    } else {
+        crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?
+            .into_iter()
+            .enumerate()
+            .map(|(i, meta)| (meta.filename, i + 2))
+            .collect()
+    };
+    let catalog = if config.use_clips { "clips.csv" } else { "metadata.csv" };
+
+    let mut rows = Vec::new();
+    let mut reader = csv::Reader::from_path(&manifest_path)?;
+    for result in reader.records() {
+        let record = result?;
+        let (Some(file), Some(species)) = (record.get(0), record.get(1)) else {
+            continue;
+        };
+        let row = catalog_rows.get(file).copied().unwrap_or(0);
+        rows.push((file.to_string(), species.to_string(), catalog, row));
+    }
+    Ok(rows)
+}
+
+// Classify every file of a split and report accuracy, top-k accuracy,
+// per-class precision/recall/F1 and the confusion matrix, on the console
+// and as metrics.csv, confusion.csv, misclassified.csv and report.html in
+// `config.report_dir`
+pub fn evaluate(
+    output_dir: &str,
+    classifier: &Classifier,
+    config: &EvaluateConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if config.top_k == 0 {
+        return Err("top-k must be at least 1".into());
+    }
+    let rows = manifest_rows(output_dir, config)?;
+    let labels = &classifier.labels;
+    let label_index: HashMap<&str, usize> = labels.iter().enumerate().map(|(i, l)| (l.as_str(), i)).collect();
+
+    // Species the model was not trained on cannot be scored
+    let mut unknown: HashMap<String, usize> = HashMap::new();
+    let mut jobs = Vec::new();
+    for (file, species, catalog, row) in rows {
+        match label_index.get(species.as_str()) {
+            Some(&truth) => jobs.push((file, catalog, row, truth)),
+            None => *unknown.entry(species).or_insert(0) += 1,
+        }
+    }
+
+    let classifier = Arc::new(classifier.clone());
+    let pool = ThreadPool::new(config.threads.max(1));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    for (index, (file, _, _, _)) in jobs.iter().enumerate() {
+        let results = Arc::clone(&results);
+        let classifier = Arc::clone(&classifier);
+        let path = Path::new(output_dir).join(file);
+        pool.execute(move || {
+            let ranked = read_audio(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|audio| classifier.classify(&audio));
+            match ranked {
+                Ok(ranked) => results.lock().unwrap().push((index, ranked)),
+                Err(e) => println!("Error classifying {}: {}", path.display(), e),
+            }
+        });
+    }
+    pool.join();
+
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by_key(|(index, _)| *index);
+    let predictions: Vec<Prediction> = results
+        .into_iter()
+        .map(|(index, ranked)| {
+            let (file, catalog, catalog_row, truth) = jobs[index].clone();
+            Prediction {
+                file,
+                catalog,
+                catalog_row,
+                truth,
+                ranked: ranked.into_iter().map(|(label, score)| (label_index[label.as_str()], score)).collect(),
+            }
+        })
+        .collect();
+    if predictions.is_empty() {
+        return Err(format!("nothing to evaluate in the {} split", config.split).into());
+    }
+
+    let n = labels.len();
+    let mut confusion = vec![vec![0usize; n]; n];
+    let mut top_k_hits = 0;
+    for prediction in &predictions {
+        confusion[prediction.truth][prediction.predicted()] += 1;
+        if prediction.ranked.iter().take(config.top_k).any(|(label, _)| *label == prediction.truth) {
+            top_k_hits += 1;
+        }
+    }
+    let correct: usize = (0..n).map(|c| confusion[c][c]).sum();
+    let accuracy = correct as f64 / predictions.len() as f64;
+    let top_k_accuracy = top_k_hits as f64 / predictions.len() as f64;
+    let metrics = class_metrics(&confusion);
+    let present: Vec<usize> = (0..n).filter(|&c| metrics[c].support > 0).collect();
+    let macro_f1 = present.iter().map(|&c| metrics[c].f1).sum::<f64>() / present.len().max(1) as f64;
+
+    let worst = most_confidently_wrong(&predictions, config.worst);
+
+    // Console report
+    println!(
+        "Evaluated {} files from the {} split with {}",
+        predictions.len(),
+        config.split,
+        classifier.kind.name()
+    );
+    println!("Accuracy: {:.2}%  top-{}: {:.2}%  macro F1: {:.3}", accuracy * 100.0, config.top_k, top_k_accuracy * 100.0, macro_f1);
+    println!();
+    println!("{:<32} {:>9} {:>9} {:>9} {:>8}", "species", "precision", "recall", "f1", "support");
+    for &c in &present {
+        let m = &metrics[c];
+        println!("{:<32} {:>9.3} {:>9.3} {:>9.3} {:>8}", labels[c], m.precision, m.recall, m.f1, m.support);
+    }
+    println!();
+    println!("Confusion matrix (rows: true, columns: predicted)");
+    // Row labels are "<index> <species>"
+    let width = labels.iter().map(|l| l.len()).max().unwrap_or(0).max(6) + 3;
+    print!("{:<width$}", "", width = width);
+    for c in 0..n {
+        print!(" {:>6}", c);
+    }
+    println!();
+    for (c, row) in confusion.iter().enumerate() {
+        print!("{:<width$}", format!("{} {}", c, labels[c]), width = width);
+        for count in row {
+            print!(" {:>6}", count);
+        }
+        println!();
+    }
+    if !worst.is_empty() {
+        println!();
+        println!("Worst misclassifications:");
+        for p in &worst {
+            println!(
+                "  {} ({} row {}): {} predicted as {} {:.3}",
+                p.file,
+                p.catalog,
+                p.catalog_row,
+                labels[p.truth],
+                labels[p.predicted()],
+                p.ranked[0].1
+            );
+        }
+    }
+    for (species, count) in &unknown {
+        println!("Skipped {} files of {}, which the model does not know", count, species);
+    }
+
+    // Files
+    std::fs::create_dir_all(&config.report_dir)?;
+    let mut writer = csv::Writer::from_path(config.report_dir.join("metrics.csv"))?;
+    writer.write_record(["species", "precision", "recall", "f1", "support"])?;
+    for &c in &present {
+        let m = &metrics[c];
+        writer.write_record([
+            labels[c].as_str(),
+            &format!("{:.4}", m.precision),
+            &format!("{:.4}", m.recall),
+            &format!("{:.4}", m.f1),
+            &m.support.to_string(),
+        ])?;
+    }
+    writer.write_record(["accuracy", "", "", "", &format!("{:.4}", accuracy)])?;
+    writer.write_record([&format!("top_{}_accuracy", config.top_k), "", "", "", &format!("{:.4}", top_k_accuracy)])?;
+    writer.write_record(["macro_f1", "", "", "", &format!("{:.4}", macro_f1)])?;
+    writer.flush()?;
+
+    let mut writer = csv::Writer::from_path(config.report_dir.join("confusion.csv"))?;
+    writer.write_record(std::iter::once("true\\predicted").chain(labels.iter().map(String::as_str)))?;
+    for (c, row) in confusion.iter().enumerate() {
+        writer.write_record(std::iter::once(labels[c].clone()).chain(row.iter().map(|v| v.to_string())))?;
+    }
+    writer.flush()?;
+
+    let mut writer = csv::Writer::from_path(config.report_dir.join("misclassified.csv"))?;
+    writer.write_record(["file", "species", "predicted", "score", "true_score", "catalog", "catalog_row"])?;
+    for p in predictions.iter().filter(|p| p.predicted() != p.truth) {
+        writer.write_record([
+            &p.file,
+            &labels[p.truth],
+            &labels[p.predicted()],
+            &format!("{:.4}", p.ranked[0].1),
+            &format!("{:.4}", p.score_of(p.truth)),
+            p.catalog,
+            &p.catalog_row.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+
+    // HTML report with the confusion matrix as a row-normalized heatmap
+    let base = std::fs::canonicalize(output_dir)?;
+    let link = |file: &str| format!("file://{}", escape_html(&base.join(file).to_string_lossy()));
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Evaluation</title>\n<style>\n\
+         body { font-family: sans-serif; }\n\
+         table { border-collapse: collapse; margin-bottom: 24px; }\n\
+         td, th { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }\n\
+         th.label, td.label { text-align: left; }\n\
+         </style>\n</head>\n<body>\n",
+    );
+    writeln!(
+        html,
+        "<h1>{} split</h1>\n<p>{} files, {} &middot; accuracy {:.2}% &middot; top-{} {:.2}% &middot; macro F1 {:.3}</p>",
+        escape_html(&config.split),
+        predictions.len(),
+        classifier.kind.name(),
+        accuracy * 100.0,
+        config.top_k,
+        top_k_accuracy * 100.0,
+        macro_f1
+    )?;
+    html.push_str("<h2>Per species</h2>\n<table>\n<tr><th class=\"label\">species</th><th>precision</th><th>recall</th><th>F1</th><th>support</th></tr>\n");
+    for &c in &present {
+        let m = &metrics[c];
+        writeln!(
+            html,
+            "<tr><td class=\"label\">{}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{}</td></tr>",
+            escape_html(&labels[c]),
+            m.precision,
+            m.recall,
+            m.f1,
+            m.support
+        )?;
+    }
+    html.push_str("</table>\n<h2>Confusion matrix</h2>\n<table>\n<tr><th class=\"label\">true \\ predicted</th>");
+    for label in labels {
+        write!(html, "<th>{}</th>", escape_html(label))?;
+    }
+    html.push_str("</tr>\n");
+    for (c, row) in confusion.iter().enumerate() {
+        let total: usize = row.iter().sum();
+        write!(html, "<tr><td class=\"label\">{}</td>", escape_html(&labels[c]))?;
+        for (p, &count) in row.iter().enumerate() {
+            let share = if total > 0 { count as f64 / total as f64 } else { 0.0 };
+            // Correct predictions in green, confusions in red
+            let (r, g, b) = if p == c { (255.0 - 155.0 * share, 255.0 - 55.0 * share, 255.0 - 155.0 * share) } else { (255.0, 255.0 - 175.0 * share, 255.0 - 175.0 * share) };
+            write!(
+                html,
+                "<td style=\"background: rgb({:.0}, {:.0}, {:.0})\" title=\"{:.1}%\">{}</td>",
+                r,
+                g,
+                b,
+                share * 100.0,
+                count
+            )?;
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+    if !worst.is_empty() {
+        html.push_str("<h2>Worst misclassifications</h2>\n<table>\n<tr><th class=\"label\">file</th><th class=\"label\">species</th><th class=\"label\">predicted</th><th>score</th><th>true score</th><th class=\"label\">catalog row</th></tr>\n");
+        for p in &worst {
+            writeln!(
+                html,
+                "<tr><td class=\"label\"><a href=\"{}\">{}</a></td><td class=\"label\">{}</td><td class=\"label\">{}</td><td>{:.3}</td><td>{:.3}</td><td class=\"label\"><a href=\"{}\">{}</a> row {}</td></tr>",
+                link(&p.file),
+                escape_html(&p.file),
+                escape_html(&labels[p.truth]),
+                escape_html(&labels[p.predicted()]),
+                p.ranked[0].1,
+                p.score_of(p.truth),
+                link(p.catalog),
+                p.catalog,
+                p.catalog_row
+            )?;
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    std::fs::write(config.report_dir.join("report.html"), html)?;
+
+    println!("Reports written to {}", config.report_dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn class_metrics_from_a_confusion_matrix() {
+        // Rows are true classes, columns predicted; class 2 never occurs
+        let confusion = vec![vec![5, 1, 0], vec![2, 3, 0], vec![0, 0, 0]];
+        let metrics = class_metrics(&confusion);
+
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+        assert!(close(metrics[0].precision, 5.0 / 7.0) && close(metrics[0].recall, 5.0 / 6.0));
+        assert!(close(metrics[0].f1, 2.0 * (5.0 / 7.0) * (5.0 / 6.0) / (5.0 / 7.0 + 5.0 / 6.0)));
+        assert!(close(metrics[1].precision, 0.75) && close(metrics[1].recall, 0.6));
+        assert_eq!((metrics[0].support, metrics[1].support, metrics[2].support), (6, 5, 0));
+        assert_eq!((metrics[2].precision, metrics[2].recall, metrics[2].f1), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn most_confidently_wrong_comes_first() {
+        let prediction = |truth: usize, ranked: &[(usize, f32)]| Prediction {
+            file: String::new(),
+            catalog: "metadata.csv",
+            catalog_row: 0,
+            truth,
+            ranked: ranked.to_vec(),
+        };
+        let predictions = [
+            prediction(0, &[(1, 0.6), (0, 0.4)]),
+            prediction(0, &[(0, 0.99), (1, 0.01)]),
+            prediction(1, &[(0, 0.9), (1, 0.1)]),
+            prediction(1, &[(0, 0.7), (1, 0.3)]),
+        ];
+        let worst = most_confidently_wrong(&predictions, 2);
+        let scores: Vec<f32> = worst.iter().map(|p| p.ranked[0].1).collect();
+        assert_eq!(scores, [0.9, 0.7]);
+        assert_eq!(most_confidently_wrong(&predictions, 10).len(), 3);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::audio::{read_audio, AudioBuffer};
+use crate::dsp::{hamming_window, power_spectrum};
+use crate::jsmath;
+use std::path::Path;
+
+// Port of the feature extraction in modules/mfcc.js. Every step follows the
+// JavaScript operation for operation (including the Float32Array rounding of
+// windowed frames and V8's Math functions), so the vectors match what the
+// browser feeds the model bit for bit.
+
+// Bumped whenever a change to the extraction code alters its output
+pub const EXTRACTOR_VERSION: u32 = 1;
+
+// Parameters shared by both extractors
+pub const FRAME_SIZE: usize = 2048; // Must be a power of 2 for FFT
+pub const HOP_SIZE: usize = 512;
+
+// extractEnhancedMfccFeatures
+pub const ENHANCED_MFCC_COUNT: usize = 20;
+pub const ENHANCED_NUM_FILTERS: usize = 40;
+pub const ENHANCED_FEATURE_DIM: usize = 3 * ENHANCED_MFCC_COUNT;
+
+// simpleExtractMfccFeatures (legacy 13-D models)
+pub const SIMPLE_MFCC_COUNT: usize = 13;
+pub const SIMPLE_NUM_FILTERS: usize = 26;
+
+// Triangular Mel filter bank of `num_filters` rows with NFFT/2 + 1 weights each
+pub fn create_mel_filter_bank(
+    num_filters: usize,
+    nfft: usize,
+    sample_rate: f64,
+    low_freq: f64,
+    high_freq: f64,
+) -> Vec<Vec<f64>> {
+    let hz_to_mel = |hz: f64| 1125.0 * jsmath::log(1.0 + hz / 700.0);
+    let mel_to_hz = |mel: f64| 700.0 * (jsmath::exp(mel / 1125.0) - 1.0);
+
+    let low_mel = hz_to_mel(low_freq);
+    let high_mel = hz_to_mel(high_freq);
+    let mel_points: Vec<f64> = (0..num_filters + 2)
+        .map(|i| low_mel + (i as f64 * (high_mel - low_mel)) / (num_filters + 1) as f64)
+        .collect();
+
+    // Convert Hz to nearest FFT bin numbers
+    let bin: Vec<i64> = mel_points
+        .iter()
+        .map(|&mel| ((mel_to_hz(mel) / sample_rate) * nfft as f64).floor() as i64)
+        .collect();
+
+    let num_bins = nfft / 2 + 1;
+    (1..=num_filters)
+        .map(|m| {
+            let mut filter = vec![0.0; num_bins];
+            for k in bin[m - 1]..bin[m] {
+                filter[k as usize] = (k - bin[m - 1]) as f64 / (bin[m] - bin[m - 1]) as f64;
+            }
+            for k in bin[m]..bin[m + 1] {
+                filter[k as usize] = (bin[m + 1] - k) as f64 / (bin[m + 1] - bin[m]) as f64;
+            }
+            filter
+        })
+        .collect()
+}
+
+// Type-II DCT, first `num_coeffs` coefficients only
+pub fn dct(vector: &[f64], num_coeffs: usize) -> Vec<f64> {
+    let n = vector.len();
+    (0..num_coeffs)
+        .map(|k| {
+            let mut sum = 0.0;
+            for (i, &value) in vector.iter().enumerate() {
+                sum += value * jsmath::cos((std::f64::consts::PI * k as f64 * (2 * i + 1) as f64) / (2 * n) as f64);
+            }
+            sum
+        })
+        .collect()
+}
+
+// Delta features by finite differences over +/- `window` frames, repeating
+// the edge frames as padding
+pub fn compute_delta_features(feature_frames: &[Vec<f64>], window: usize) -> Vec<Vec<f64>> {
+    let num_frames = feature_frames.len();
+    let Some(first) = feature_frames.first() else {
+        return Vec::new();
+    };
+    let num_coeffs = first.len();
+
+    (0..num_frames)
+        .map(|t| {
+            (0..num_coeffs)
+                .map(|i| {
+                    let mut numerator = 0.0;
+                    let mut denominator = 0.0;
+                    for n in 1..=window {
+                        let forward = (t + n).min(num_frames - 1);
+                        let backward = t.saturating_sub(n);
+                        numerator += n as f64 * (feature_frames[forward][i] - feature_frames[backward][i]);
+                        denominator += (2 * n * n) as f64;
+                    }
+                    if denominator > 0.0 { numerator / denominator } else { 0.0 }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Subtract the per-coefficient mean over all frames
+pub fn apply_cepstral_mean_normalization(mfcc_frames: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let Some(first) = mfcc_frames.first() else {
+        return Vec::new();
+    };
+    let num_frames = mfcc_frames.len();
+
+    let mut means = vec![0.0; first.len()];
+    for frame in mfcc_frames {
+        for (mean, value) in means.iter_mut().zip(frame) {
+            *mean += value;
+        }
+    }
+    for mean in &mut means {
+        *mean /= num_frames as f64;
+    }
+
+    mfcc_frames
+        .iter()
+        .map(|frame| frame.iter().zip(&means).map(|(value, mean)| value - mean).collect())
+        .collect()
+}
+
+// Static MFCCs for every full frame of the signal
+fn mfcc_frames(signal: &[f32], sample_rate: u32, num_filters: usize, mfcc_count: usize) -> Vec<Vec<f64>> {
+    let nfft = FRAME_SIZE;
+    let mel_filters = create_mel_filter_bank(num_filters, nfft, sample_rate as f64, 0.0, sample_rate as f64 / 2.0);
+
+    let mut frames = Vec::new();
+    let mut start = 0;
+    while start + FRAME_SIZE < signal.len() {
+        let power = power_spectrum(&hamming_window(&signal[start..start + FRAME_SIZE]));
+
+        let filter_energies: Vec<f64> = mel_filters
+            .iter()
+            .map(|filter| {
+                let mut energy = 0.0;
+                for (p, w) in power.iter().zip(filter) {
+                    energy += p * w;
+                }
+                // Log energy to avoid log(0)
+                jsmath::log(energy + 1e-8)
+            })
+            .collect();
+
+        frames.push(dct(&filter_energies, mfcc_count));
+        start += HOP_SIZE;
+    }
+    frames
+}
+
+// Mean of each dimension across frames
+fn mean_vector(frames: &[Vec<f64>], dim: usize) -> Vec<f64> {
+    let mut mean = vec![0.0; dim];
+    for frame in frames {
+        for (m, value) in mean.iter_mut().zip(frame) {
+            *m += value;
+        }
+    }
+    let count = frames.len().max(1) as f64;
+    for m in &mut mean {
+        *m /= count;
+    }
+    mean
+}
+
+// 60-D vector of mean static, delta and delta-delta MFCCs after cepstral
+// mean normalization, as computed by extractEnhancedMfccFeatures. Returns
+// None when the signal is shorter than one frame.
+pub fn extract_enhanced_mfcc_features(signal: &[f32], sample_rate: u32) -> Option<Vec<f64>> {
+    let frames = mfcc_frames(signal, sample_rate, ENHANCED_NUM_FILTERS, ENHANCED_MFCC_COUNT);
+    if frames.is_empty() {
+        return None;
+    }
+
+    let normalized = apply_cepstral_mean_normalization(&frames);
+    let delta = compute_delta_features(&normalized, 2);
+    let delta_delta = compute_delta_features(&delta, 2);
+
+    let combined: Vec<Vec<f64>> = normalized
+        .iter()
+        .zip(&delta)
+        .zip(&delta_delta)
+        .map(|((s, d), dd)| s.iter().chain(d).chain(dd).copied().collect())
+        .collect();
+
+    Some(mean_vector(&combined, ENHANCED_FEATURE_DIM))
+}
+
+// 13-D mean MFCC vector as computed by simpleExtractMfccFeatures
+pub fn simple_extract_mfcc_features(signal: &[f32], sample_rate: u32) -> Vec<f64> {
+    let frames = mfcc_frames(signal, sample_rate, SIMPLE_NUM_FILTERS, SIMPLE_MFCC_COUNT);
+    mean_vector(&frames, SIMPLE_MFCC_COUNT)
+}
+
+// Which of the browser's extractors to reproduce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureKind {
+    Enhanced, // 60-D, extractEnhancedMfccFeatures
+    Simple,   // 13-D, simpleExtractMfccFeatures
+}
+
+impl FeatureKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FeatureKind::Enhanced => "mfcc-enhanced",
+            FeatureKind::Simple => "mfcc-simple",
+        }
+    }
+
+    // Every parameter that affects the output. Feature caches are keyed by
+    // this, so change EXTRACTOR_VERSION whenever the algorithm itself changes.
+    pub fn config_key(&self) -> String {
+        let (num_filters, mfcc_count) = match self {
+            FeatureKind::Enhanced => (ENHANCED_NUM_FILTERS, ENHANCED_MFCC_COUNT),
+            FeatureKind::Simple => (SIMPLE_NUM_FILTERS, SIMPLE_MFCC_COUNT),
+        };
+        format!(
+            "extractor={} version={} frame={} hop={} filters={} coefficients={} dim={}",
+            self.name(),
+            EXTRACTOR_VERSION,
+            FRAME_SIZE,
+            HOP_SIZE,
+            num_filters,
+            mfcc_count,
+            self.dim()
+        )
+    }
+
+    pub fn dim(&self) -> usize {
+        match self {
+            FeatureKind::Enhanced => ENHANCED_FEATURE_DIM,
+            FeatureKind::Simple => SIMPLE_MFCC_COUNT,
+        }
+    }
+
+    // Features of a decoded recording. Like the browser, only the first
+    // channel is analysed.
+    pub fn extract(&self, audio: &AudioBuffer) -> Option<Vec<f64>> {
+        let signal = audio.channel(0);
+        match self {
+            FeatureKind::Enhanced => extract_enhanced_mfcc_features(&signal, audio.sample_rate),
+            FeatureKind::Simple => Some(simple_extract_mfcc_features(&signal, audio.sample_rate)),
+        }
+    }
+}
+
+// Print the feature vector of one recording, one value per line, for
+// comparing against the browser
+pub fn print_features(path: &Path, kind: FeatureKind) -> Result<(), Box<dyn std::error::Error>> {
+    let audio = read_audio(path)?;
+    let features = kind
+        .extract(&audio)
+        .ok_or_else(|| format!("{} is shorter than one {}-sample frame", path.display(), FRAME_SIZE))?;
+    for value in features {
+        println!("{}", value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Fixtures come from tests/fixtures/mfcc/generate.mjs, which runs the
+    // same WAV files through modules/mfcc.js under Node
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mfcc").join(name)
+    }
+
+    fn expected(name: &str) -> Vec<f64> {
+        std::fs::read_to_string(fixture(name))
+            .unwrap()
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect()
+    }
+
+    fn assert_bit_identical(actual: &[f64], expected: &[f64], label: &str) {
+        assert_eq!(actual.len(), expected.len(), "{}: dimension mismatch", label);
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert_eq!(a.to_bits(), e.to_bits(), "{}[{}]: rust {} vs js {}", label, i, a, e);
+        }
+    }
+
+    fn check_parity(name: &str) {
+        let audio = read_audio(&fixture(&format!("{}.wav", name))).unwrap();
+        let signal = audio.channel(0);
+
+        let enhanced = extract_enhanced_mfcc_features(&signal, audio.sample_rate).unwrap();
+        assert_bit_identical(&enhanced, &expected(&format!("{}.enhanced.txt", name)), name);
+
+        let simple = simple_extract_mfcc_features(&signal, audio.sample_rate);
+        assert_bit_identical(&simple, &expected(&format!("{}.simple.txt", name)), name);
+    }
+
+    #[test]
+    fn matches_js_on_mono_44100() {
+        check_parity("chirp_44100");
+    }
+
+    #[test]
+    fn matches_js_on_first_channel_of_stereo_22050() {
+        check_parity("stereo_22050");
+    }
+
+    #[test]
+    fn matches_js_on_single_frame() {
+        check_parity("single_frame");
+    }
+
+    #[test]
+    fn signal_shorter_than_a_frame_has_no_features() {
+        assert!(extract_enhanced_mfcc_features(&[0.0; FRAME_SIZE], 44100).is_none());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::audio::AudioBuffer;
+use crate::dsp::{fft_complex, Biquad};
+use std::f64::consts::PI;
+
+// Q factors of the two sections of a 4th-order Butterworth filter
+const BUTTERWORTH_Q: [f64; 2] = [0.5411961001461969, 1.3065629648763766];
+
+// STFT settings for spectral subtraction (75% overlap Hann windows)
+const STFT_SIZE: usize = 2048;
+const STFT_HOP: usize = STFT_SIZE / 4;
+
+// Fraction of quietest frames used to estimate the stationary noise spectrum
+const NOISE_FRAME_FRACTION: f64 = 0.1;
+
+fn high_pass_section(sample_rate: u32, cutoff_hz: f64, q: f64) -> Biquad {
+    let w0 = 2.0 * PI * cutoff_hz / sample_rate as f64;
+    let alpha = w0.sin() / (2.0 * q);
+    let cos = w0.cos();
+    let a0 = 1.0 + alpha;
+    Biquad::new(
+        (1.0 + cos) / 2.0 / a0,
+        -(1.0 + cos) / a0,
+        (1.0 + cos) / 2.0 / a0,
+        -2.0 * cos / a0,
+        (1.0 - alpha) / a0,
+    )
+}
+
+fn low_pass_section(sample_rate: u32, cutoff_hz: f64, q: f64) -> Biquad {
+    let w0 = 2.0 * PI * cutoff_hz / sample_rate as f64;
+    let alpha = w0.sin() / (2.0 * q);
+    let cos = w0.cos();
+    let a0 = 1.0 + alpha;
+    Biquad::new(
+        (1.0 - cos) / 2.0 / a0,
+        (1.0 - cos) / a0,
+        (1.0 - cos) / 2.0 / a0,
+        -2.0 * cos / a0,
+        (1.0 - alpha) / a0,
+    )
+}
+
+// Run every channel of the audio through a cascade of filter sections
+fn apply_sections(audio: &mut AudioBuffer, sections: &[Biquad]) {
+    let channels = audio.channels.max(1) as usize;
+    for c in 0..channels {
+        let mut channel: Vec<f64> = audio.samples.iter().skip(c).step_by(channels).map(|&x| x as f64).collect();
+        for section in sections {
+            channel = section.process(&channel);
+        }
+        for (i, value) in channel.into_iter().enumerate() {
+            audio.samples[i * channels + c] = value as f32;
+        }
+    }
+}
+
+// 4th-order Butterworth high-pass, e.g. to remove wind and traffic rumble
+pub fn high_pass(audio: &mut AudioBuffer, cutoff_hz: f64) {
+    let nyquist = audio.sample_rate as f64 / 2.0;
+    if cutoff_hz <= 0.0 || cutoff_hz >= nyquist {
+        return;
+    }
+    let sections: Vec<Biquad> = BUTTERWORTH_Q
+        .iter()
+        .map(|&q| high_pass_section(audio.sample_rate, cutoff_hz, q))
+        .collect();
+    apply_sections(audio, &sections);
+}
+
+// 4th-order Butterworth low-pass
+pub fn low_pass(audio: &mut AudioBuffer, cutoff_hz: f64) {
+    let nyquist = audio.sample_rate as f64 / 2.0;
+    if cutoff_hz <= 0.0 || cutoff_hz >= nyquist {
+        return;
+    }
+    let sections: Vec<Biquad> = BUTTERWORTH_Q
+        .iter()
+        .map(|&q| low_pass_section(audio.sample_rate, cutoff_hz, q))
+        .collect();
+    apply_sections(audio, &sections);
+}
+
+// Stationary-noise spectral subtraction. The noise spectrum is the mean
+// magnitude of the quietest frames; `strength` scales how much of it is
+// removed and `floor` is the fraction of the original magnitude always kept,
+// which limits "musical noise" artifacts.
+pub fn spectral_subtraction(audio: &mut AudioBuffer, strength: f64, floor: f64) {
+    let channels = audio.channels.max(1) as usize;
+    let frames = audio.frames();
+    if frames < STFT_SIZE {
+        return;
+    }
+
+    let window: Vec<f64> = (0..STFT_SIZE)
+        .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / STFT_SIZE as f64).cos())
+        .collect();
+
+    for c in 0..channels {
+        let channel: Vec<f64> = audio.samples.iter().skip(c).step_by(channels).map(|&x| x as f64).collect();
+
+        // Forward STFT
+        let mut spectra = Vec::new();
+        let mut start = 0;
+        while start + STFT_SIZE <= frames {
+            let mut real: Vec<f64> = channel[start..start + STFT_SIZE]
+                .iter()
+                .zip(&window)
+                .map(|(x, w)| x * w)
+                .collect();
+            let mut imag = vec![0.0; STFT_SIZE];
+            fft_complex(&mut real, &mut imag, false);
+            spectra.push((real, imag));
+            start += STFT_HOP;
+        }
+
+        // Noise estimate from the quietest frames
+        let magnitudes: Vec<Vec<f64>> = spectra
+            .iter()
+            .map(|(re, im)| re.iter().zip(im).map(|(r, i)| (r * r + i * i).sqrt()).collect())
+            .collect();
+        let mut by_energy: Vec<(f64, usize)> = magnitudes
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (m.iter().map(|x| x * x).sum::<f64>(), i))
+            .collect();
+        by_energy.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let noise_frames = ((by_energy.len() as f64 * NOISE_FRAME_FRACTION).ceil() as usize).max(1);
+        let mut noise = vec![0.0; STFT_SIZE];
+        for &(_, i) in &by_energy[..noise_frames] {
+            for (n, m) in noise.iter_mut().zip(&magnitudes[i]) {
+                *n += m / noise_frames as f64;
+            }
+        }
+
+        // Subtract, keep the phase, and overlap-add the inverse transforms.
+        // The output is divided by the summed analysis windows afterwards.
+        let mut output = vec![0.0; frames];
+        let mut window_sum = vec![0.0; frames];
+        for (index, ((real, imag), magnitude)) in spectra.iter_mut().zip(&magnitudes).enumerate() {
+            for k in 0..STFT_SIZE {
+                if magnitude[k] > 0.0 {
+                    let cleaned = (magnitude[k] - strength * noise[k]).max(floor * magnitude[k]);
+                    let scale = cleaned / magnitude[k];
+                    real[k] *= scale;
+                    imag[k] *= scale;
+                }
+            }
+            fft_complex(real, imag, true);
+            let offset = index * STFT_HOP;
+            for (n, value) in real.iter().enumerate() {
+                output[offset + n] += value / STFT_SIZE as f64;
+                window_sum[offset + n] += window[n];
+            }
+        }
+
+        // Samples barely covered by any window (the very edges and the tail
+        // past the last full frame) keep their original values
+        for i in 0..frames {
+            let value = if window_sum[i] > 0.1 { output[i] / window_sum[i] } else { channel[i] };
+            audio.samples[i * channels + c] = value as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frequency: f64) -> AudioBuffer {
+        let samples = (0..44100)
+            .map(|i| (0.5 * (2.0 * PI * frequency * i as f64 / 44100.0).sin()) as f32)
+            .collect();
+        AudioBuffer { sample_rate: 44100, channels: 1, samples }
+    }
+
+    // Gain of the filtered tone in dB, skipping the start-up transient
+    fn response_db(frequency: f64, filter: fn(&mut AudioBuffer, f64), cutoff_hz: f64) -> f64 {
+        let rms = |audio: &AudioBuffer| {
+            let tail = &audio.samples[4410..];
+            (tail.iter().map(|&x| x as f64 * x as f64).sum::<f64>() / tail.len() as f64).sqrt()
+        };
+        let original = tone(frequency);
+        let mut filtered = original.clone();
+        filter(&mut filtered, cutoff_hz);
+        20.0 * (rms(&filtered) / rms(&original)).log10()
+    }
+
+    #[test]
+    fn high_pass_response() {
+        assert!((response_db(1000.0, high_pass, 1000.0) + 3.01).abs() < 0.2);
+        assert!(response_db(5000.0, high_pass, 1000.0).abs() < 0.1);
+        // 24 dB per octave: about -80 dB a decade below the cutoff
+        assert!(response_db(100.0, high_pass, 1000.0) < -75.0);
+    }
+
+    #[test]
+    fn low_pass_response() {
+        assert!((response_db(4000.0, low_pass, 4000.0) + 3.01).abs() < 0.2);
+        assert!(response_db(500.0, low_pass, 4000.0).abs() < 0.1);
+        assert!(response_db(16000.0, low_pass, 4000.0) < -45.0);
+    }
+
+    #[test]
+    fn cutoffs_outside_the_band_leave_the_audio_alone() {
+        for cutoff in [0.0, -100.0, 22050.0, 30000.0] {
+            let original = tone(1000.0);
+            let mut audio = original.clone();
+            high_pass(&mut audio, cutoff);
+            low_pass(&mut audio, cutoff);
+            assert_eq!(audio.samples, original.samples, "cutoff {}", cutoff);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use crate::audio::AudioBuffer;
+use std::path::Path;
+
+// Minimal FLAC encoder for 16-bit audio: fixed-size blocks, independent
+// channels, the best of the fixed polynomial predictors (orders 0-4) per
+// subframe and partitioned Rice coding of the residual. It typically stores
+// bird recordings in about half the space of 16-bit PCM WAV.
+
+const BLOCK_SIZE: usize = 4096;
+const BITS_PER_SAMPLE: u32 = 16;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 6;
+const MAX_RICE_PARAMETER: u32 = 14;
+
+// Writes big-endian bit fields into a byte buffer
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { bytes: Vec::new(), accumulator: 0, bits: 0 }
+    }
+
+    fn write(&mut self, value: u64, bits: u32) {
+        for shift in (0..bits).rev() {
+            self.accumulator = (self.accumulator << 1) | ((value >> shift) & 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.bytes.push(self.accumulator as u8);
+                self.accumulator = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write((value as u64) & ((1u64 << bits) - 1), bits);
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        for _ in 0..zeros {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    fn pad_to_byte(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// Frame numbers use the same variable-length coding as UTF-8: a lead byte
+// with one marker bit per byte, then 6 payload bits per continuation byte
+fn write_utf8_number(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(value, 8);
+        return;
+    }
+    let mut length = 2;
+    while value >= 1u64 << (5 * length + 1) {
+        length += 1;
+    }
+    let marker = (0xFFu64 << (8 - length)) & 0xFF;
+    writer.write(marker | (value >> (6 * (length - 1))), 8);
+    for i in (0..length - 1).rev() {
+        writer.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+// Residual of the fixed polynomial predictor of the given order
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = samples;
+            match order {
+                0 => s[i],
+                1 => s[i] - s[i - 1],
+                2 => s[i] - 2 * s[i - 1] + s[i - 2],
+                3 => s[i] - 3 * s[i - 1] + 3 * s[i - 2] - s[i - 3],
+                _ => s[i] - 4 * s[i - 1] + 6 * s[i - 2] - 4 * s[i - 3] + s[i - 4],
+            }
+        })
+        .collect()
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+// Best Rice parameter and its cost in bits for a run of residuals
+fn best_rice_parameter(residual: &[u64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let bits: u64 = residual.iter().map(|&u| (u >> k) + 1 + k as u64).sum();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+// Pick the partition order with the smallest total size. Returns the order,
+// the Rice parameter of each partition and the estimated size in bits.
+fn plan_partitions(residual: &[u64], block_size: usize, predictor_order: usize) -> (u32, Vec<u32>, u64) {
+    let mut best: Option<(u32, Vec<u32>, u64)> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << partition_order;
+        if !block_size.is_multiple_of(partitions) || block_size / partitions <= predictor_order {
+            break;
+        }
+        let partition_size = block_size / partitions;
+
+        let mut parameters = Vec::with_capacity(partitions);
+        let mut bits = 0u64;
+        let mut start = 0;
+        for p in 0..partitions {
+            let length = if p == 0 { partition_size - predictor_order } else { partition_size };
+            let (k, cost) = best_rice_parameter(&residual[start..start + length]);
+            parameters.push(k);
+            bits += cost + 4;
+            start += length;
+        }
+
+        if best.as_ref().is_none_or(|(_, _, best_bits)| bits < *best_bits) {
+            best = Some((partition_order, parameters, bits));
+        }
+    }
+    best.unwrap()
+}
+
+// Chosen fixed predictor for a subframe and how its residual is coded
+struct SubframePlan {
+    order: usize,
+    residual: Vec<u64>, // Zigzag-encoded
+    partition_order: u32,
+    parameters: Vec<u32>,
+    total_bits: u64,
+}
+
+fn write_subframe(writer: &mut BitWriter, samples: &[i64]) {
+    let block_size = samples.len();
+
+    // Silence and other constant blocks need a single value
+    if samples.iter().all(|&s| s == samples[0]) {
+        writer.write(0, 1);
+        writer.write(0b000000, 6);
+        writer.write(0, 1);
+        writer.write_signed(samples[0], BITS_PER_SAMPLE);
+        return;
+    }
+
+    let mut best: Option<SubframePlan> = None;
+    for order in 0..=MAX_FIXED_ORDER.min(block_size - 1) {
+        let residual: Vec<u64> = fixed_residual(samples, order).into_iter().map(zigzag).collect();
+        let (partition_order, parameters, bits) = plan_partitions(&residual, block_size, order);
+        let total_bits = bits + (order as u64) * BITS_PER_SAMPLE as u64;
+        if best.as_ref().is_none_or(|b| total_bits < b.total_bits) {
+            best = Some(SubframePlan { order, residual, partition_order, parameters, total_bits });
+        }
+    }
+    let SubframePlan { order, residual, partition_order, parameters, total_bits } = best.unwrap();
+
+    // Fall back to verbatim samples if prediction does not pay off
+    if total_bits >= (block_size as u64) * BITS_PER_SAMPLE as u64 {
+        writer.write(0, 1);
+        writer.write(0b000001, 6);
+        writer.write(0, 1);
+        for &sample in samples {
+            writer.write_signed(sample, BITS_PER_SAMPLE);
+        }
+        return;
+    }
+
+    writer.write(0, 1);
+    writer.write(0b001000 | order as u64, 6);
+    writer.write(0, 1);
+    for &sample in &samples[..order] {
+        writer.write_signed(sample, BITS_PER_SAMPLE);
+    }
+
+    // Rice coding with 4-bit parameters
+    writer.write(0b00, 2);
+    writer.write(partition_order as u64, 4);
+    let partition_size = block_size >> partition_order;
+    let mut start = 0;
+    for (p, &k) in parameters.iter().enumerate() {
+        let length = if p == 0 { partition_size - order } else { partition_size };
+        writer.write(k as u64, 4);
+        for &u in &residual[start..start + length] {
+            writer.write_unary(u >> k);
+            writer.write(u & ((1u64 << k) - 1), k);
+        }
+        start += length;
+    }
+}
+
+// Encode audio as 16-bit FLAC. The format stores up to 8 channels and
+// sample rates below 2^20 Hz.
+pub fn encode_flac(audio: &AudioBuffer) -> Result<Vec<u8>, String> {
+    let channels = audio.channels.max(1) as usize;
+    if channels > 8 {
+        return Err(format!("FLAC supports at most 8 channels, got {}", channels));
+    }
+    if audio.sample_rate == 0 || audio.sample_rate >= 1 << 20 {
+        return Err(format!("Unsupported FLAC sample rate: {} Hz", audio.sample_rate));
+    }
+    let frames = audio.frames();
+    let samples: Vec<i64> = audio
+        .samples
+        .iter()
+        .map(|&x| (x.clamp(-1.0, 1.0) * 32768.0).round().min(32767.0) as i64)
+        .collect();
+
+    let mut writer = BitWriter::new();
+    writer.write(u32::from_be_bytes(*b"fLaC") as u64, 32);
+
+    // STREAMINFO (the only metadata block). Frame sizes and MD5 are left
+    // as zero, which the format defines as "unknown".
+    writer.write(1, 1);
+    writer.write(0, 7);
+    writer.write(34, 24);
+    writer.write(BLOCK_SIZE as u64, 16);
+    writer.write(BLOCK_SIZE as u64, 16);
+    writer.write(0, 24);
+    writer.write(0, 24);
+    writer.write(audio.sample_rate as u64, 20);
+    writer.write(channels as u64 - 1, 3);
+    writer.write(BITS_PER_SAMPLE as u64 - 1, 5);
+    writer.write(frames as u64, 36);
+    writer.write(0, 64);
+    writer.write(0, 64);
+
+    let mut output = writer.bytes;
+
+    for (frame_number, start) in (0..frames).step_by(BLOCK_SIZE).enumerate() {
+        let block_size = BLOCK_SIZE.min(frames - start);
+        let mut frame = BitWriter::new();
+
+        // Frame header: sync code, fixed blocking, block size stored as a
+        // 16-bit value at the end, sample rate from STREAMINFO, independent
+        // channels, 16 bits per sample
+        frame.write(0b11111111111110, 14);
+        frame.write(0, 1);
+        frame.write(0, 1);
+        frame.write(0b0111, 4);
+        frame.write(0b0000, 4);
+        frame.write(channels as u64 - 1, 4);
+        frame.write(0b100, 3);
+        frame.write(0, 1);
+        write_utf8_number(&mut frame, frame_number as u64);
+        frame.write(block_size as u64 - 1, 16);
+        let header_crc = crc8(&frame.bytes);
+        frame.write(header_crc as u64, 8);
+
+        for c in 0..channels {
+            let channel: Vec<i64> = (start..start + block_size).map(|i| samples[i * channels + c]).collect();
+            write_subframe(&mut frame, &channel);
+        }
+
+        frame.pad_to_byte();
+        let frame_crc = crc16(&frame.bytes);
+        frame.write(frame_crc as u64, 16);
+        output.extend_from_slice(&frame.bytes);
+    }
+
+    Ok(output)
+}
+
+pub fn write_flac(path: &Path, audio: &AudioBuffer) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::write(path, encode_flac(audio)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::augment::Rng;
+
+    // 16-bit sample values, so encoding is lossless
+    fn buffer(channels: u16, samples: &[i64]) -> AudioBuffer {
+        AudioBuffer { sample_rate: 22050, channels, samples: samples.iter().map(|&s| s as f32 / 32768.0).collect() }
+    }
+
+    fn tone(frames: usize, period: f64, amplitude: f64) -> Vec<i64> {
+        (0..frames).map(|i| (amplitude * (i as f64 * std::f64::consts::TAU / period).sin()).round() as i64).collect()
+    }
+
+    fn noise(frames: usize, seed: &str) -> Vec<i64> {
+        let mut rng = Rng::for_item(1, seed);
+        (0..frames).map(|_| (rng.next_u64() >> 48) as i64 - 32768).collect()
+    }
+
+    fn interleave(left: &[i64], right: &[i64]) -> Vec<i64> {
+        left.iter().zip(right).flat_map(|(&l, &r)| [l, r]).collect()
+    }
+
+    fn decode(bytes: &[u8]) -> (u32, u32, Vec<i64>) {
+        let mut reader = claxon::FlacReader::new(std::io::Cursor::new(bytes)).unwrap();
+        let info = reader.streaminfo();
+        let samples = reader.samples().map(|s| s.unwrap() as i64).collect();
+        (info.channels, info.sample_rate, samples)
+    }
+
+    fn assert_round_trip(channels: u16, samples: &[i64]) {
+        let (decoded_channels, sample_rate, decoded) = decode(&encode_flac(&buffer(channels, samples)).unwrap());
+        assert_eq!(decoded_channels, channels as u32);
+        assert_eq!(sample_rate, 22050);
+        assert_eq!(decoded.len(), samples.len());
+        assert!(decoded == samples, "decoded samples differ");
+    }
+
+    // Subframe type code (the 6 bits after the zero padding bit)
+    fn subframe_type(samples: &[i64]) -> u8 {
+        let mut writer = BitWriter::new();
+        write_subframe(&mut writer, samples);
+        writer.pad_to_byte();
+        writer.bytes[0] >> 1
+    }
+
+    #[test]
+    fn mono_round_trips_with_a_short_final_block() {
+        let samples = tone(2 * BLOCK_SIZE + 123, 37.0, 12000.0);
+        assert_eq!(subframe_type(&samples[..BLOCK_SIZE]) & 0b111000, 0b001000);
+        assert_round_trip(1, &samples);
+    }
+
+    #[test]
+    fn stereo_channels_round_trip_independently() {
+        let frames = BLOCK_SIZE + 1000;
+        assert_round_trip(2, &interleave(&tone(frames, 50.0, 20000.0), &noise(frames, "right")));
+    }
+
+    #[test]
+    fn constant_blocks_round_trip() {
+        assert_eq!(subframe_type(&[-7; 64]), 0b000000);
+        let mut samples = vec![0; BLOCK_SIZE];
+        samples.extend(vec![-32768; BLOCK_SIZE]);
+        samples.extend(vec![32767; 10]);
+        assert_round_trip(1, &samples);
+    }
+
+    #[test]
+    fn noise_falls_back_to_verbatim_and_round_trips() {
+        let samples = noise(BLOCK_SIZE + 3, "mono");
+        assert_eq!(subframe_type(&samples[..BLOCK_SIZE]), 0b000001);
+        assert_eq!(subframe_type(&samples[BLOCK_SIZE..]), 0b000001);
+        assert_round_trip(1, &samples);
+    }
+
+    #[test]
+    fn more_than_eight_channels_is_an_error() {
+        assert_round_trip(8, &noise(8 * 100, "eight"));
+        assert!(encode_flac(&buffer(9, &noise(9 * 100, "nine"))).is_err());
+
+        let path = std::env::temp_dir().join(format!("flac_nine_channels_{}.flac", std::process::id()));
+        assert!(write_flac(&path, &buffer(9, &[0; 9])).is_err());
+        assert!(!path.exists());
+    }
+}
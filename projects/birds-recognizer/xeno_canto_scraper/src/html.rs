@@ -0,0 +1,5 @@
+// Escape text for HTML element content and double-quoted attributes, as
+// used by the rendered galleries, reports and attribution pages
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
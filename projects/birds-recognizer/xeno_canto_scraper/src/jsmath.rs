@@ -0,0 +1,359 @@
+// Math.sin, Math.cos, Math.log and Math.exp as computed by V8, which uses
+// ports of fdlibm. The platform libm is allowed to round differently in the
+// last bit, so code that must reproduce the browser's numbers exactly
+// (the FFT, window and Mel/DCT steps of modules/mfcc.js) calls these instead.
+#![allow(clippy::excessive_precision)]
+
+fn high_word(x: f64) -> i32 {
+    (x.to_bits() >> 32) as i32
+}
+
+fn low_word(x: f64) -> u32 {
+    x.to_bits() as u32
+}
+
+fn with_high_word(x: f64, high: i32) -> f64 {
+    f64::from_bits(((high as u32 as u64) << 32) | low_word(x) as u64)
+}
+
+fn from_words(high: i32, low: u32) -> f64 {
+    f64::from_bits(((high as u32 as u64) << 32) | low as u64)
+}
+
+// sin on [-pi/4, pi/4]; `y` is the tail of x and `iy` says whether it is used
+fn kernel_sin(x: f64, y: f64, iy: bool) -> f64 {
+    const S1: f64 = -1.66666666666666324348e-01;
+    const S2: f64 = 8.33333333332248946124e-03;
+    const S3: f64 = -1.98412698298579493134e-04;
+    const S4: f64 = 2.75573137070700676789e-06;
+    const S5: f64 = -2.50507602534068634195e-08;
+    const S6: f64 = 1.58969099521155010221e-10;
+
+    let ix = high_word(x) & 0x7fffffff;
+    if ix < 0x3e400000 && x as i32 == 0 {
+        return x;
+    }
+    let z = x * x;
+    let v = z * x;
+    let r = S2 + z * (S3 + z * (S4 + z * (S5 + z * S6)));
+    if !iy {
+        x + v * (S1 + z * r)
+    } else {
+        x - ((z * (0.5 * y - v * r) - y) - v * S1)
+    }
+}
+
+// cos on [-pi/4, pi/4]; `y` is the tail of x
+fn kernel_cos(x: f64, y: f64) -> f64 {
+    const C1: f64 = 4.16666666666666019037e-02;
+    const C2: f64 = -1.38888888888741095749e-03;
+    const C3: f64 = 2.48015872894767294178e-05;
+    const C4: f64 = -2.75573143513906633035e-07;
+    const C5: f64 = 2.08757232129817482790e-09;
+    const C6: f64 = -1.13596475577881948265e-11;
+
+    let ix = high_word(x) & 0x7fffffff;
+    if ix < 0x3e400000 && x as i32 == 0 {
+        return 1.0;
+    }
+    let z = x * x;
+    let r = z * (C1 + z * (C2 + z * (C3 + z * (C4 + z * (C5 + z * C6)))));
+    if ix < 0x3fd33333 {
+        return 1.0 - (0.5 * z - (z * r - x * y));
+    }
+    let qx = if ix > 0x3fe90000 { 0.28125 } else { from_words(ix - 0x00200000, 0) };
+    let hz = 0.5 * z - qx;
+    let a = 1.0 - qx;
+    a - (hz - (z * r - x * y))
+}
+
+// High words of n * pi/2 for n = 1..32
+const NPIO2_HW: [i32; 32] = [
+    0x3ff921fb, 0x400921fb, 0x4012d97c, 0x401921fb, 0x401f6a7a, 0x4022d97c, 0x4025fdbb, 0x402921fb,
+    0x402c463a, 0x402f6a7a, 0x4031475c, 0x4032d97c, 0x40346b9c, 0x4035fdbb, 0x40378fdb, 0x403921fb,
+    0x403ab41b, 0x403c463a, 0x403dd85a, 0x403f6a7a, 0x40407e4c, 0x4041475c, 0x4042106c, 0x4042d97c,
+    0x4043a28c, 0x40446b9c, 0x404534ac, 0x4045fdbb, 0x4046c6cb, 0x40478fdb, 0x404858eb, 0x404921fb,
+];
+
+// Reduce x to y0 + y1 in [-pi/4, pi/4], returning the quadrant. Only the
+// small and medium ranges (|x| up to about 2^19 * pi/2) are ported; nothing
+// in the feature pipeline gets near that.
+fn rem_pio2(x: f64) -> Option<(i32, f64, f64)> {
+    const INVPIO2: f64 = std::f64::consts::FRAC_2_PI;
+    const PIO2_1: f64 = 1.57079632673412561417e+00;
+    const PIO2_1T: f64 = 6.07710050650619224932e-11;
+    const PIO2_2: f64 = 6.07710050630396597660e-11;
+    const PIO2_2T: f64 = 2.02226624879595063154e-21;
+    const PIO2_3: f64 = 2.02226624871116645580e-21;
+    const PIO2_3T: f64 = 8.47842766036889956997e-32;
+
+    let hx = high_word(x);
+    let ix = hx & 0x7fffffff;
+
+    if ix <= 0x3fe921fb {
+        return Some((0, x, 0.0));
+    }
+
+    // |x| < 3pi/4, special case with n = +-1
+    if ix < 0x4002d97c {
+        return Some(if hx > 0 {
+            let mut z = x - PIO2_1;
+            if ix != 0x3ff921fb {
+                let y0 = z - PIO2_1T;
+                (1, y0, (z - y0) - PIO2_1T)
+            } else {
+                z -= PIO2_2;
+                let y0 = z - PIO2_2T;
+                (1, y0, (z - y0) - PIO2_2T)
+            }
+        } else {
+            let mut z = x + PIO2_1;
+            if ix != 0x3ff921fb {
+                let y0 = z + PIO2_1T;
+                (-1, y0, (z - y0) + PIO2_1T)
+            } else {
+                z += PIO2_2;
+                let y0 = z + PIO2_2T;
+                (-1, y0, (z - y0) + PIO2_2T)
+            }
+        });
+    }
+
+    if ix > 0x413921fb {
+        return None;
+    }
+
+    let t = x.abs();
+    let n = (t * INVPIO2 + 0.5) as i32;
+    let f_n = n as f64;
+    let mut r = t - f_n * PIO2_1;
+    let mut w = f_n * PIO2_1T; // 1st round good to 85 bits
+    let mut y0;
+    if n < 32 && ix != NPIO2_HW[n as usize - 1] {
+        y0 = r - w; // Quick check, no cancellation
+    } else {
+        let j = ix >> 20;
+        y0 = r - w;
+        let mut i = j - ((high_word(y0) >> 20) & 0x7ff);
+        if i > 16 {
+            // 2nd iteration needed, good to 118 bits
+            let t = r;
+            w = f_n * PIO2_2;
+            r = t - w;
+            w = f_n * PIO2_2T - ((t - r) - w);
+            y0 = r - w;
+            i = j - ((high_word(y0) >> 20) & 0x7ff);
+            if i > 49 {
+                // 3rd iteration needed, 151 bits
+                let t = r;
+                w = f_n * PIO2_3;
+                r = t - w;
+                w = f_n * PIO2_3T - ((t - r) - w);
+                y0 = r - w;
+            }
+        }
+    }
+    let y1 = (r - y0) - w;
+    if hx < 0 { Some((-n, -y0, -y1)) } else { Some((n, y0, y1)) }
+}
+
+pub fn sin(x: f64) -> f64 {
+    let ix = high_word(x) & 0x7fffffff;
+    if ix <= 0x3fe921fb {
+        return kernel_sin(x, 0.0, false);
+    }
+    if ix >= 0x7ff00000 {
+        return f64::NAN;
+    }
+    match rem_pio2(x) {
+        Some((n, y0, y1)) => match n & 3 {
+            0 => kernel_sin(y0, y1, true),
+            1 => kernel_cos(y0, y1),
+            2 => -kernel_sin(y0, y1, true),
+            _ => -kernel_cos(y0, y1),
+        },
+        None => x.sin(),
+    }
+}
+
+pub fn cos(x: f64) -> f64 {
+    let ix = high_word(x) & 0x7fffffff;
+    if ix <= 0x3fe921fb {
+        return kernel_cos(x, 0.0);
+    }
+    if ix >= 0x7ff00000 {
+        return f64::NAN;
+    }
+    match rem_pio2(x) {
+        Some((n, y0, y1)) => match n & 3 {
+            0 => kernel_cos(y0, y1),
+            1 => -kernel_sin(y0, y1, true),
+            2 => -kernel_cos(y0, y1),
+            _ => kernel_sin(y0, y1, true),
+        },
+        None => x.cos(),
+    }
+}
+
+// Natural logarithm
+pub fn log(x: f64) -> f64 {
+    const LN2_HI: f64 = 6.93147180369123816490e-01;
+    const LN2_LO: f64 = 1.90821492927058770002e-10;
+    const TWO54: f64 = 1.80143985094819840000e+16;
+    const LG1: f64 = 6.666666666666735130e-01;
+    const LG2: f64 = 3.999999999940941908e-01;
+    const LG3: f64 = 2.857142874366239149e-01;
+    const LG4: f64 = 2.222219843214978396e-01;
+    const LG5: f64 = 1.818357216161805012e-01;
+    const LG6: f64 = 1.531383769920937332e-01;
+    const LG7: f64 = 1.479819860511658591e-01;
+
+    let mut x = x;
+    let mut hx = high_word(x);
+    let lx = low_word(x);
+    let mut k = 0;
+
+    // x < 2^-1022
+    if hx < 0x00100000 {
+        if ((hx & 0x7fffffff) as u32 | lx) == 0 {
+            return f64::NEG_INFINITY;
+        }
+        if hx < 0 {
+            return f64::NAN;
+        }
+        k -= 54;
+        x *= TWO54;
+        hx = high_word(x);
+    }
+    if hx >= 0x7ff00000 {
+        return x + x;
+    }
+    k += (hx >> 20) - 1023;
+    hx &= 0x000fffff;
+    let i = (hx + 0x95f64) & 0x100000;
+    x = with_high_word(x, hx | (i ^ 0x3ff00000)); // Normalize x or x/2
+    k += i >> 20;
+    let f = x - 1.0;
+
+    // -2^-20 <= f < 2^-20
+    if (0x000fffff & (2 + hx)) < 3 {
+        if f == 0.0 {
+            if k == 0 {
+                return 0.0;
+            }
+            let dk = k as f64;
+            return dk * LN2_HI + dk * LN2_LO;
+        }
+        let r = f * f * (0.5 - 0.33333333333333333 * f);
+        if k == 0 {
+            return f - r;
+        }
+        let dk = k as f64;
+        return dk * LN2_HI - ((r - dk * LN2_LO) - f);
+    }
+
+    let s = f / (2.0 + f);
+    let dk = k as f64;
+    let z = s * s;
+    let mut i = hx - 0x6147a;
+    let w = z * z;
+    let j = 0x6b851 - hx;
+    let t1 = w * (LG2 + w * (LG4 + w * LG6));
+    let t2 = z * (LG1 + w * (LG3 + w * (LG5 + w * LG7)));
+    i |= j;
+    let r = t2 + t1;
+    if i > 0 {
+        let hfsq = 0.5 * f * f;
+        if k == 0 {
+            f - (hfsq - s * (hfsq + r))
+        } else {
+            dk * LN2_HI - ((hfsq - (s * (hfsq + r) + dk * LN2_LO)) - f)
+        }
+    } else if k == 0 {
+        f - s * (f - r)
+    } else {
+        dk * LN2_HI - ((s * (f - r) - dk * LN2_LO) - f)
+    }
+}
+
+pub fn exp(x: f64) -> f64 {
+    const HALF: [f64; 2] = [0.5, -0.5];
+    const O_THRESHOLD: f64 = 7.09782712893383973096e+02;
+    const U_THRESHOLD: f64 = -7.45133219101941108420e+02;
+    const LN2_HI: [f64; 2] = [6.93147180369123816490e-01, -6.93147180369123816490e-01];
+    const LN2_LO: [f64; 2] = [1.90821492927058770002e-10, -1.90821492927058770002e-10];
+    const INVLN2: f64 = std::f64::consts::LOG2_E;
+    const P1: f64 = 1.66666666666666019037e-01;
+    const P2: f64 = -2.77777777770155933842e-03;
+    const P3: f64 = 6.61375632143793436117e-05;
+    const P4: f64 = -1.65339022054652515390e-06;
+    const P5: f64 = 4.13813679705723846039e-08;
+    const E: f64 = std::f64::consts::E;
+    const HUGE: f64 = 1.0e+300;
+    const TWOM1000: f64 = 9.33263618503218878990e-302;
+    const TWO1023: f64 = 8.988465674311579539e307;
+
+    let mut x = x;
+    let mut hi = 0.0;
+    let mut lo = 0.0;
+    let mut k: i32 = 0;
+    let hx_signed = high_word(x);
+    let xsb = ((hx_signed >> 31) & 1) as usize;
+    let hx = hx_signed & 0x7fffffff;
+
+    // Filter out non-finite and overflowing arguments
+    if hx >= 0x40862e42 {
+        if hx >= 0x7ff00000 {
+            if ((hx & 0xfffff) as u32 | low_word(x)) != 0 {
+                return x + x;
+            }
+            return if xsb == 0 { x } else { 0.0 };
+        }
+        if x > O_THRESHOLD {
+            return f64::INFINITY;
+        }
+        if x < U_THRESHOLD {
+            return 0.0;
+        }
+    }
+
+    // Argument reduction
+    if hx > 0x3fd62e42 {
+        if hx < 0x3ff0a2b2 {
+            if x == 1.0 {
+                return E;
+            }
+            hi = x - LN2_HI[xsb];
+            lo = LN2_LO[xsb];
+            k = 1 - xsb as i32 - xsb as i32;
+        } else {
+            k = (INVLN2 * x + HALF[xsb]) as i32;
+            let t = k as f64;
+            hi = x - t * LN2_HI[0];
+            lo = t * LN2_LO[0];
+        }
+        x = hi - lo;
+    } else if hx < 0x3e300000 && HUGE + x > 1.0 {
+        return 1.0 + x;
+    }
+
+    let t = x * x;
+    let twopk = if k >= -1021 {
+        from_words(0x3ff00000 + (k << 20), 0)
+    } else {
+        from_words(0x3ff00000 + ((k + 1000) << 20), 0)
+    };
+    let c = x - t * (P1 + t * (P2 + t * (P3 + t * (P4 + t * P5))));
+    if k == 0 {
+        return 1.0 - ((x * c) / (c - 2.0) - x);
+    }
+    let y = 1.0 - ((lo - (x * c) / (2.0 - c)) - hi);
+    if k >= -1021 {
+        if k == 1024 {
+            return y * 2.0 * TWO1023;
+        }
+        y * twopk
+    } else {
+        y * twopk * TWOM1000
+    }
+}
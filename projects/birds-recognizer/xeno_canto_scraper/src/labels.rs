@@ -0,0 +1,425 @@
+use crate::audio::{read_audio, write_wav, TARGET_SAMPLE_RATE};
+use crate::clips::{clip_counters, clips_csv_path, clips_dir, load_clips, next_clip_filename, write_clips_csv, ClipMetadata};
+use crate::filters::{high_pass, low_pass};
+use crate::license::LicenseFilter;
+use crate::mapping::ClassMapping;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// A labeled time span, optionally bounded in frequency, as Audacity label
+// tracks and Raven selection tables store them
+#[derive(Debug, Clone)]
+pub struct LabelRegion {
+    pub start_secs: f64,
+    pub end_secs: f64,
+    pub low_hz: Option<f64>,
+    pub high_hz: Option<f64>,
+    pub label: String,
+    pub score: Option<f32>,
+}
+
+// Audacity label track: start<TAB>end<TAB>label per line, with a
+// "\<TAB>low<TAB>high" line after regions that have a frequency range
+pub fn write_audacity_labels(path: &Path, regions: &[LabelRegion]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for region in regions {
+        writeln!(writer, "{:.6}\t{:.6}\t{}", region.start_secs, region.end_secs, region.label)?;
+        if let (Some(low), Some(high)) = (region.low_hz, region.high_hz) {
+            writeln!(writer, "\\\t{:.6}\t{:.6}", low, high)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Raven selection table (tab-separated, one selection per row). Regions
+// without a frequency range span 0 Hz to `nyquist_hz`.
+pub fn write_raven_selections(
+    path: &Path,
+    regions: &[LabelRegion],
+    nyquist_hz: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "Selection\tView\tChannel\tBegin Time (s)\tEnd Time (s)\tLow Freq (Hz)\tHigh Freq (Hz)\tSpecies\tScore"
+    )?;
+    for (i, region) in regions.iter().enumerate() {
+        writeln!(
+            writer,
+            "{}\tSpectrogram 1\t1\t{:.6}\t{:.6}\t{:.1}\t{:.1}\t{}\t{}",
+            i + 1,
+            region.start_secs,
+            region.end_secs,
+            region.low_hz.unwrap_or(0.0),
+            region.high_hz.unwrap_or(nyquist_hz),
+            region.label,
+            region.score.map(|s| format!("{:.4}", s)).unwrap_or_default()
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    value.trim().replace(',', ".").parse().ok()
+}
+
+// Read an Audacity label track. Point labels (start == end) are kept;
+// callers decide what to do with them.
+pub fn read_audacity_labels(path: &Path) -> Result<Vec<LabelRegion>, Box<dyn std::error::Error>> {
+    let mut regions: Vec<LabelRegion> = Vec::new();
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.first() == Some(&"\\") {
+            // Frequency range of the region above
+            if let (Some(region), Some(low), Some(high)) = (
+                regions.last_mut(),
+                fields.get(1).and_then(|v| parse_number(v)),
+                fields.get(2).and_then(|v| parse_number(v)),
+            ) {
+                region.low_hz = Some(low);
+                region.high_hz = Some(high);
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (Some(start), Some(end)) =
+            (fields.first().and_then(|v| parse_number(v)), fields.get(1).and_then(|v| parse_number(v)))
+        else {
+            return Err(format!("{}:{}: expected start<TAB>end<TAB>label", path.display(), number + 1).into());
+        };
+        regions.push(LabelRegion {
+            start_secs: start,
+            end_secs: end,
+            low_hz: None,
+            high_hz: None,
+            label: fields.get(2).map(|l| l.trim().to_string()).unwrap_or_default(),
+            score: None,
+        });
+    }
+    Ok(regions)
+}
+
+// Read a Raven selection table. The label is taken from `label_column`, or
+// the first of the usual annotation columns the table has. Tables listing
+// each selection once per view keep only the first row of a selection.
+pub fn read_raven_selections(
+    path: &Path,
+    label_column: Option<&str>,
+) -> Result<Vec<LabelRegion>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut lines = text.lines();
+    let header: Vec<&str> = lines.next().unwrap_or("").split('\t').map(str::trim).collect();
+    let column = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+
+    let (Some(begin), Some(end)) = (column("Begin Time (s)"), column("End Time (s)")) else {
+        return Err(format!("{}: not a Raven selection table (no Begin/End Time columns)", path.display()).into());
+    };
+    let low = column("Low Freq (Hz)");
+    let high = column("High Freq (Hz)");
+    let selection = column("Selection");
+    let label = match label_column {
+        Some(name) => Some(column(name).ok_or_else(|| format!("{}: no {} column", path.display(), name))?),
+        None => ["Species", "Annotation", "Label", "Class", "Common Name", "Sound Type"]
+            .iter()
+            .find_map(|name| column(name)),
+    };
+
+    let mut seen = HashSet::new();
+    let mut regions = Vec::new();
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let field = |index: Option<usize>| index.and_then(|i| fields.get(i)).map(|v| v.trim());
+        if let Some(id) = field(selection)
+            && !seen.insert(id.to_string())
+        {
+            continue;
+        }
+        let (Some(start), Some(end)) = (field(Some(begin)).and_then(parse_number), field(Some(end)).and_then(parse_number))
+        else {
+            return Err(format!("{}: invalid selection row: {}", path.display(), line).into());
+        };
+        regions.push(LabelRegion {
+            start_secs: start,
+            end_secs: end,
+            low_hz: field(low).and_then(parse_number),
+            high_hz: field(high).and_then(parse_number),
+            label: field(label).unwrap_or("").to_string(),
+            score: None,
+        });
+    }
+    Ok(regions)
+}
+
+// Read either format, telling them apart by the Raven header line
+pub fn read_label_file(path: &Path, label_column: Option<&str>) -> Result<Vec<LabelRegion>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)?;
+    if text.starts_with("Selection\t") || text.lines().next().is_some_and(|l| l.contains("Begin Time (s)")) {
+        read_raven_selections(path, label_column)
+    } else {
+        read_audacity_labels(path)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportConfig {
+    pub labels_dir: PathBuf,          // Where the label files live
+    pub label_column: Option<String>, // Raven column holding the species
+    pub band_limit: bool,             // Filter clips to their selection's frequency range
+    pub min_duration_secs: f64,       // Shorter regions (and point labels) are skipped
+}
+
+// Label files of a recording: <stem>.txt, <stem>.labels.txt,
+// <stem>.Table.1.selections.txt and the like
+fn label_files_for(labels_dir: &Path, stem: &str) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    if !labels_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let prefix = format!("{}.", stem);
+    let mut files: Vec<PathBuf> = std::fs::read_dir(labels_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".txt"))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// Cut a labeled clip for every region of the Raven tables and Audacity
+// label tracks attached to downloaded recordings. Regions without a label
+// take the recording's species. Imported clips count as verified.
+// Recordings that already have imported clips are skipped.
+pub fn import_labels(output_dir: &str, config: &ImportConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+    let known_species: HashSet<&str> = metadata.iter().map(|m| m.species.as_str()).collect();
+    let mapping = ClassMapping::load(output_dir)?;
+    let licenses = LicenseFilter::load(output_dir)?;
+    let clips_path = clips_csv_path(output_dir);
+    let mut clips = load_clips(&clips_path)?;
+    let mut counters = clip_counters(&clips);
+    std::fs::create_dir_all(clips_dir(output_dir))?;
+
+    let processed: HashSet<String> =
+        clips.iter().filter(|c| c.source == "labels").map(|c| c.parent_id.clone()).collect();
+
+    let (mut new_clips, mut skipped) = (0, 0);
+    let mut unknown: BTreeMap<String, usize> = BTreeMap::new();
+    for meta in metadata.iter().filter(|m| m.is_downloaded && licenses.allows_recording(m)) {
+        let stem = Path::new(&meta.filename).file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let files = label_files_for(&config.labels_dir, &stem)?;
+        if files.is_empty() {
+            continue;
+        }
+        if processed.contains(&meta.id) {
+            println!("Skipping {}: labels already imported", meta.filename);
+            continue;
+        }
+
+        let mut regions = Vec::new();
+        for file in &files {
+            regions.extend(read_label_file(file, config.label_column.as_deref())?);
+        }
+        let audio = match read_audio(&Path::new(output_dir).join(&meta.filename)) {
+            Ok(audio) => audio,
+            Err(e) => {
+                println!("Error reading {}: {}", meta.filename, e);
+                continue;
+            }
+        };
+        let sample_rate = audio.sample_rate as f64;
+        let duration = audio.frames() as f64 / sample_rate;
+
+        let mut cut = 0;
+        for region in regions {
+            let start = region.start_secs.max(0.0);
+            let end = region.end_secs.min(duration);
+            if end - start < config.min_duration_secs {
+                skipped += 1;
+                continue;
+            }
+            let species = if region.label.is_empty() {
+                meta.species.clone()
+            } else {
+                crate::format_species_name(&region.label)
+            };
+            if !known_species.contains(species.as_str()) {
+                *unknown.entry(species.clone()).or_insert(0) += 1;
+            }
+            let species = mapping.class_of(&species, meta);
+
+            let mut clip_audio = audio.slice_frames((start * sample_rate).round() as usize, (end * sample_rate).round() as usize);
+            if config.band_limit {
+                if let Some(low) = region.low_hz.filter(|&hz| hz > 0.0 && hz < sample_rate / 2.0) {
+                    high_pass(&mut clip_audio, low);
+                }
+                if let Some(high) = region.high_hz.filter(|&hz| hz > 0.0 && hz < sample_rate / 2.0) {
+                    low_pass(&mut clip_audio, high);
+                }
+            }
+
+            let (species, filename) = next_clip_filename(&species, &mut counters)?;
+            write_wav(&clips_dir(output_dir).join(&filename), &clip_audio)?;
+            clips.push(ClipMetadata {
+                filename,
+                species,
+                parent_id: meta.id.clone(),
+                parent_filename: meta.filename.clone(),
+                start_secs: start,
+                end_secs: end,
+                source: "labels".to_string(),
+                augmentation: String::new(),
+                verified: true,
+                low_hz: region.low_hz,
+                high_hz: region.high_hz,
+            });
+            cut += 1;
+        }
+        println!("Cut {} labeled clips from {} ({} label files)", cut, meta.filename, files.len());
+        new_clips += cut;
+    }
+
+    write_clips_csv(&clips_path, &clips)?;
+    println!("Imported {} labeled clips ({} regions too short or outside the recording)", new_clips, skipped);
+    for (species, count) in unknown {
+        println!("  note: {} clips labeled {}, which is not a species in metadata.csv", count, species);
+    }
+    Ok(())
+}
+
+// Write the catalog's clips back out per recording, as an Audacity label
+// track and a Raven selection table, for review in either tool. Augmented
+// clips have no place in the original recording and are left out.
+pub fn export_labels(
+    output_dir: &str,
+    export_dir: &Path,
+    source: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut by_recording: BTreeMap<String, Vec<LabelRegion>> = BTreeMap::new();
+    for clip in load_clips(&clips_csv_path(output_dir))? {
+        if !clip.augmentation.is_empty() || source.is_some_and(|s| s != clip.source) {
+            continue;
+        }
+        by_recording.entry(clip.parent_filename.clone()).or_default().push(LabelRegion {
+            start_secs: clip.start_secs,
+            end_secs: clip.end_secs,
+            low_hz: clip.low_hz,
+            high_hz: clip.high_hz,
+            label: clip.species,
+            score: None,
+        });
+    }
+
+    std::fs::create_dir_all(export_dir)?;
+    let mut total = 0;
+    for (recording, regions) in by_recording.iter_mut() {
+        regions.sort_by(|a, b| a.start_secs.total_cmp(&b.start_secs));
+        let stem = Path::new(recording).file_stem().unwrap_or_default().to_string_lossy().to_string();
+        write_audacity_labels(&export_dir.join(format!("{}.labels.txt", stem)), regions)?;
+        // Recordings are stored at the catalog rate
+        write_raven_selections(
+            &export_dir.join(format!("{}.Table.1.selections.txt", stem)),
+            regions,
+            TARGET_SAMPLE_RATE as f64 / 2.0,
+        )?;
+        total += regions.len();
+    }
+    println!("Exported {} segments of {} recordings to {}", total, by_recording.len(), export_dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("labels_{}_{}.txt", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn read(name: &str, text: &str, label_column: Option<&str>) -> Result<Vec<LabelRegion>, String> {
+        let path = label_file(name, text);
+        let regions = read_label_file(&path, label_column).map_err(|e| e.to_string());
+        std::fs::remove_file(&path).unwrap();
+        regions
+    }
+
+    #[test]
+    fn audacity_frequency_lines_belong_to_the_region_above() {
+        let regions = read(
+            "audacity",
+            "1.5\t2,25\tBlackbird song\n\\\t1200.0\t6000.5\n\n3.000000\t3.000000\tpoint\n4\t5\n",
+            None,
+        )
+        .unwrap();
+        assert_eq!(regions.len(), 3);
+        assert_eq!((regions[0].start_secs, regions[0].end_secs), (1.5, 2.25));
+        assert_eq!(regions[0].label, "Blackbird song");
+        assert_eq!((regions[0].low_hz, regions[0].high_hz), (Some(1200.0), Some(6000.5)));
+        assert_eq!((regions[1].start_secs, regions[1].end_secs, regions[1].low_hz), (3.0, 3.0, None));
+        assert_eq!(regions[2].label, "");
+    }
+
+    #[test]
+    fn audacity_rows_without_times_are_an_error() {
+        let error = read("audacity_bad", "1.0\t2.0\twren\nwren\n", None).unwrap_err();
+        assert!(error.ends_with(":2: expected start<TAB>end<TAB>label"), "{}", error);
+    }
+
+    #[test]
+    fn raven_views_of_one_selection_are_read_once() {
+        let table = "Selection\tView\tChannel\tBegin Time (s)\tEnd Time (s)\tLow Freq (Hz)\tHigh Freq (Hz)\tAnnotation\tSpecies\n\
+                     1\tWaveform 1\t1\t0.5\t1.5\t\t\tsong\twren\n\
+                     1\tSpectrogram 1\t1\t0.5\t1.5\t2000\t8000\tsong\twren\n\
+                     2\tSpectrogram 1\t1\t2.0\t2.8\t1000.0\t4000.0\tcall\trobin\n";
+        let regions = read("raven", table, None).unwrap();
+        assert_eq!(regions.len(), 2);
+        // Species comes before Annotation among the default label columns
+        assert_eq!(regions[0].label, "wren");
+        assert_eq!((regions[0].start_secs, regions[0].end_secs), (0.5, 1.5));
+        assert_eq!((regions[1].low_hz, regions[1].high_hz), (Some(1000.0), Some(4000.0)));
+
+        let regions = read("raven_column", table, Some("annotation")).unwrap();
+        assert_eq!(regions.iter().map(|r| r.label.as_str()).collect::<Vec<_>>(), ["song", "call"]);
+        assert!(read("raven_missing", table, Some("Notes")).unwrap_err().ends_with("no Notes column"));
+    }
+
+    #[test]
+    fn written_labels_read_back() {
+        let region = |start_secs, end_secs, band: Option<(f64, f64)>, label: &str| LabelRegion {
+            start_secs,
+            end_secs,
+            low_hz: band.map(|(low, _)| low),
+            high_hz: band.map(|(_, high)| high),
+            label: label.to_string(),
+            score: None,
+        };
+        let regions = vec![region(0.25, 1.0, Some((500.0, 9000.0)), "wren"), region(2.0, 3.5, None, "robin")];
+        for raven in [false, true] {
+            let path = label_file(if raven { "written_raven" } else { "written_audacity" }, "");
+            if raven {
+                write_raven_selections(&path, &regions, 11025.0).unwrap();
+            } else {
+                write_audacity_labels(&path, &regions).unwrap();
+            }
+            let read_back = read_label_file(&path, None).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(read_back.len(), 2);
+            for (read, written) in read_back.iter().zip(&regions) {
+                assert_eq!((read.start_secs, read.end_secs), (written.start_secs, written.end_secs));
+                assert_eq!(read.label, written.label);
+                assert_eq!(read.low_hz, written.low_hz.or(raven.then_some(0.0)));
+                assert_eq!(read.high_hz, written.high_hz.or(raven.then_some(11025.0)));
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::html::escape_html;
+use crate::RecordingMetadata;
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+// Terms a license can be excluded by: the Creative Commons elements, CC0
+// and "unknown" for recordings whose license was not scraped
+const TERMS: [&str; 6] = ["by", "nc", "nd", "sa", "cc0", "unknown"];
+
+// "https://creativecommons.org/licenses/by-nc-sa/4.0/" -> "CC BY-NC-SA 4.0"
+pub fn license_from_url(url: &str) -> Option<String> {
+    let (_, path) = url.split_once("creativecommons.org/")?;
+    let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+    match parts.as_slice() {
+        ["licenses", terms, version, ..] => Some(format!("CC {} {}", terms.to_uppercase(), version)),
+        ["licenses", terms] => Some(format!("CC {}", terms.to_uppercase())),
+        ["publicdomain", "zero", version, ..] => Some(format!("CC0 {}", version)),
+        _ => None,
+    }
+}
+
+// Deed of a license in the form license_from_url gives
+pub fn license_url(license: &str) -> Option<String> {
+    let mut words = license.split_whitespace();
+    match (words.next()?, words.next(), words.next()) {
+        ("CC0", Some(version), _) => Some(format!("https://creativecommons.org/publicdomain/zero/{}/", version)),
+        ("CC", Some(terms), Some(version)) => {
+            Some(format!("https://creativecommons.org/licenses/{}/{}/", terms.to_lowercase(), version))
+        }
+        _ => None,
+    }
+}
+
+fn license_terms(license: &str) -> Vec<String> {
+    let mut words = license.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("CC0"), _) => vec!["cc0".to_string()],
+        (Some("CC"), Some(terms)) => terms.split('-').map(str::to_lowercase).collect(),
+        _ => vec!["unknown".to_string()],
+    }
+}
+
+fn filter_path(output_dir: &str) -> PathBuf {
+    Path::new(output_dir).join("excluded_licenses.txt")
+}
+
+// License terms the catalog must not use, kept in <dir>/excluded_licenses.txt
+// (one term per line) so downloads, clip cutting, feature extraction and
+// training all honor them
+#[derive(Debug, Clone, Default)]
+pub struct LicenseFilter {
+    excluded: Vec<String>,
+}
+
+impl std::str::FromStr for LicenseFilter {
+    type Err = String;
+
+    // Comma-separated terms, e.g. "nd,nc"; "none" excludes nothing
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut excluded = Vec::new();
+        for term in s.split([',', '\n']).map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty() && !t.starts_with('#')) {
+            if term == "none" {
+                continue;
+            }
+            if !TERMS.contains(&term.as_str()) {
+                return Err(format!("Unknown license term: {} (expected one of {})", term, TERMS.join(", ")));
+            }
+            if !excluded.contains(&term) {
+                excluded.push(term);
+            }
+        }
+        Ok(LicenseFilter { excluded })
+    }
+}
+
+impl LicenseFilter {
+    // The catalog's filter, or one that allows everything if there is none
+    pub fn load(output_dir: &str) -> Result<LicenseFilter, Box<dyn std::error::Error>> {
+        let path = filter_path(output_dir);
+        if !path.exists() {
+            return Ok(LicenseFilter::default());
+        }
+        Ok(std::fs::read_to_string(&path)?.parse::<LicenseFilter>().map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    pub fn allows(&self, license: &str) -> bool {
+        !license_terms(license).iter().any(|term| self.excluded.contains(term))
+    }
+
+    pub fn allows_recording(&self, meta: &RecordingMetadata) -> bool {
+        self.allows(&meta.license)
+    }
+
+    // IDs of the recordings in `metadata` the filter rules out
+    pub fn excluded_ids(&self, metadata: &[RecordingMetadata]) -> HashSet<String> {
+        metadata.iter().filter(|m| !self.allows_recording(m)).map(|m| m.id.clone()).collect()
+    }
+}
+
+// Print the licenses in the catalog and which the filter excludes; with
+// `exclude`, store a new filter first
+pub fn license_summary(output_dir: &str, exclude: Option<&LicenseFilter>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(filter) = exclude {
+        if filter.excluded.is_empty() {
+            if filter_path(output_dir).exists() {
+                std::fs::remove_file(filter_path(output_dir))?;
+            }
+        } else {
+            std::fs::write(filter_path(output_dir), filter.excluded.join("\n") + "\n")?;
+        }
+    }
+    let filter = LicenseFilter::load(output_dir)?;
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+
+    let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for meta in &metadata {
+        let license = if meta.license.is_empty() { "unknown" } else { meta.license.as_str() };
+        let entry = counts.entry(license).or_insert((0, 0));
+        entry.0 += 1;
+        if meta.is_downloaded {
+            entry.1 += 1;
+        }
+    }
+    println!("{:<24} {:>10} {:>10}", "license", "recordings", "downloaded");
+    for (license, (total, downloaded)) in &counts {
+        let note = if filter.allows(license) { "" } else { "  excluded" };
+        println!("{:<24} {:>10} {:>10}{}", license, total, downloaded, note);
+    }
+    if filter.excluded.is_empty() {
+        println!("No licenses are excluded");
+    } else {
+        println!("Excluding licenses with: {}", filter.excluded.join(", "));
+    }
+    if counts.contains_key("unknown") {
+        println!("Re-run the scraper to fill in unknown licenses");
+    }
+    Ok(())
+}
+
+// Output format of the attribution list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributionFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl std::str::FromStr for AttributionFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(AttributionFormat::Markdown),
+            "html" => Ok(AttributionFormat::Html),
+            "json" => Ok(AttributionFormat::Json),
+            _ => Err(format!("Unknown attribution format: {} (expected markdown, html or json)", s)),
+        }
+    }
+}
+
+impl AttributionFormat {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            AttributionFormat::Markdown => "ATTRIBUTION.md",
+            AttributionFormat::Html => "attribution.html",
+            AttributionFormat::Json => "attribution.json",
+        }
+    }
+}
+
+fn recording_page(meta: &RecordingMetadata) -> String {
+    format!("https://xeno-canto.org/{}", meta.id)
+}
+
+fn escape_markdown(text: &str) -> String {
+    text.replace('|', "\\|").replace('<', "&lt;")
+}
+
+// Write the credits for the recordings with the given IDs: recordist,
+// species, license and a link to each recording on xeno-canto.org
+pub fn write_attribution(
+    output_dir: &str,
+    ids: &HashSet<String>,
+    format: AttributionFormat,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+    let mut used: Vec<&RecordingMetadata> = metadata.iter().filter(|m| ids.contains(&m.id)).collect();
+    used.sort_by(|a, b| (&a.species, &a.recordist, &a.id).cmp(&(&b.species, &b.recordist, &b.id)));
+    let license = |meta: &RecordingMetadata| if meta.license.is_empty() { "unknown".to_string() } else { meta.license.clone() };
+    let recordist = |meta: &RecordingMetadata| if meta.recordist.is_empty() { "unknown".to_string() } else { meta.recordist.clone() };
+
+    let mut licenses: BTreeMap<String, usize> = BTreeMap::new();
+    for meta in &used {
+        *licenses.entry(license(meta)).or_insert(0) += 1;
+    }
+    let summary = licenses.iter().map(|(l, n)| format!("{} {}", n, l)).collect::<Vec<_>>().join(", ");
+
+    let text = match format {
+        AttributionFormat::Json => {
+            let recordings: Vec<_> = used
+                .iter()
+                .map(|m| {
+                    json!({
+                        "id": m.id,
+                        "url": recording_page(m),
+                        "species": m.species,
+                        "common_name": m.common_name,
+                        "scientific_name": m.scientific_name,
+                        "recordist": m.recordist,
+                        "location": m.location,
+                        "license": license(m),
+                        "license_url": license_url(&m.license),
+                    })
+                })
+                .collect();
+            serde_json::to_string_pretty(&json!({ "source": "xeno-canto.org", "licenses": licenses, "recordings": recordings }))? + "\n"
+        }
+        AttributionFormat::Markdown => {
+            let mut text = format!(
+                "# Attribution\n\nThis model was trained on {} recordings from [xeno-canto](https://xeno-canto.org) ({}).\n\n\
+                 | Recording | Species | Recordist | License |\n|---|---|---|---|\n",
+                used.len(),
+                summary
+            );
+            for meta in &used {
+                let license_link = match license_url(&meta.license) {
+                    Some(url) => format!("[{}]({})", license(meta), url),
+                    None => license(meta),
+                };
+                writeln!(
+                    text,
+                    "| [XC{}]({}) | {} (*{}*) | {} | {} |",
+                    meta.id,
+                    recording_page(meta),
+                    escape_markdown(&meta.common_name),
+                    escape_markdown(&meta.scientific_name),
+                    escape_markdown(&recordist(meta)),
+                    license_link
+                )?;
+            }
+            text
+        }
+        AttributionFormat::Html => {
+            let mut text = format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Attribution</title>\n</head>\n<body>\n\
+                 <h1>Attribution</h1>\n<p>This model was trained on {} recordings from <a href=\"https://xeno-canto.org\">xeno-canto</a> ({}).</p>\n\
+                 <table>\n<tr><th>Recording</th><th>Species</th><th>Recordist</th><th>License</th></tr>\n",
+                used.len(),
+                escape_html(&summary)
+            );
+            for meta in &used {
+                let license_link = match license_url(&meta.license) {
+                    Some(url) => format!("<a href=\"{}\">{}</a>", url, escape_html(&license(meta))),
+                    None => escape_html(&license(meta)),
+                };
+                writeln!(
+                    text,
+                    "<tr><td><a href=\"{}\">XC{}</a></td><td>{} (<i>{}</i>)</td><td>{}</td><td>{}</td></tr>",
+                    recording_page(meta),
+                    escape_html(&meta.id),
+                    escape_html(&meta.common_name),
+                    escape_html(&meta.scientific_name),
+                    escape_html(&recordist(meta)),
+                    license_link
+                )?;
+            }
+            text + "</table>\n</body>\n</html>\n"
+        }
+    };
+    std::fs::write(path, text)?;
+
+    let filter = LicenseFilter::load(output_dir)?;
+    let excluded = used.iter().filter(|m| !filter.allows_recording(m)).count();
+    if excluded > 0 {
+        println!("Warning: {} of the recordings have a license excluded in {}", excluded, filter_path(output_dir).display());
+    }
+    println!("Wrote attribution for {} recordings ({}) to {}", used.len(), summary, path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn licenses_are_named_after_their_deed() {
+        assert_eq!(license_from_url("https://creativecommons.org/licenses/by-nc-sa/4.0/").as_deref(), Some("CC BY-NC-SA 4.0"));
+        assert_eq!(license_from_url("//creativecommons.org/licenses/by-nd/2.5").as_deref(), Some("CC BY-ND 2.5"));
+        assert_eq!(license_from_url("https://creativecommons.org/licenses/by").as_deref(), Some("CC BY"));
+        assert_eq!(license_from_url("https://creativecommons.org/publicdomain/zero/1.0/").as_deref(), Some("CC0 1.0"));
+        assert_eq!(license_from_url("https://creativecommons.org/"), None);
+        assert_eq!(license_from_url("https://xeno-canto.org/about/terms"), None);
+    }
+
+    #[test]
+    fn license_names_link_back_to_their_deed() {
+        for url in ["https://creativecommons.org/licenses/by-nc-sa/4.0/", "https://creativecommons.org/publicdomain/zero/1.0/"] {
+            assert_eq!(license_url(&license_from_url(url).unwrap()).as_deref(), Some(url));
+        }
+        assert_eq!(license_url(""), None);
+        assert_eq!(license_url("CC BY"), None);
+    }
+
+    #[test]
+    fn filters_exclude_licenses_by_term() {
+        let filter: LicenseFilter = " ND, nc\n# comment\nnd".parse().unwrap();
+        assert_eq!(filter.excluded, ["nd", "nc"]);
+        assert!(filter.allows("CC BY-SA 4.0"));
+        assert!(filter.allows("CC0 1.0"));
+        assert!(filter.allows(""));
+        assert!(!filter.allows("CC BY-NC-SA 4.0"));
+        assert!(!filter.allows("CC BY-ND 2.5"));
+
+        let filter: LicenseFilter = "cc0,unknown".parse().unwrap();
+        assert!(!filter.allows("CC0 1.0"));
+        assert!(!filter.allows(""));
+        assert!(!filter.allows("All rights reserved"));
+        assert!(filter.allows("CC BY 4.0"));
+    }
+
+    #[test]
+    fn none_excludes_nothing_and_unknown_terms_are_rejected() {
+        let filter: LicenseFilter = "none".parse().unwrap();
+        assert!(filter.excluded.is_empty());
+        assert!(filter.allows(""));
+        let error = "nd,commercial".parse::<LicenseFilter>().unwrap_err();
+        assert!(error.starts_with("Unknown license term: commercial"), "{}", error);
+    }
+
+    #[test]
+    fn excluded_ids_follow_the_recording_licenses() {
+        let recording = |id: &str, license: &str| RecordingMetadata {
+            id: id.to_string(),
+            license: license.to_string(),
+            ..Default::default()
+        };
+        let metadata = [recording("1", "CC BY-NC-SA 4.0"), recording("2", "CC BY-SA 4.0"), recording("3", "")];
+        let filter: LicenseFilter = "nc,unknown".parse().unwrap();
+        assert_eq!(filter.excluded_ids(&metadata), HashSet::from(["1".to_string(), "3".to_string()]));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::audio::AudioBuffer;
+use crate::dsp::Biquad;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// Gating constants from ITU-R BS.1770 / EBU R128
+const BLOCK_SECS: f64 = 0.4;
+const BLOCK_STEP_SECS: f64 = 0.1;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+// True-peak estimation oversamples by 4x with a windowed-sinc interpolator
+const OVERSAMPLING: usize = 4;
+const INTERPOLATOR_HALF_TAPS: isize = 8;
+
+// Limiter timing
+const LIMITER_LOOKAHEAD_SECS: f64 = 0.005;
+const LIMITER_RELEASE_SECS: f64 = 0.05;
+// Overshoot ignored by the limiter, so rounding after a gain change that
+// lands exactly on the ceiling does not trigger it
+const LIMITER_TOLERANCE_DB: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalizeMode {
+    Peak,     // Scale so the true peak hits the target (dBTP)
+    Loudness, // Scale so the integrated loudness hits the target (LUFS)
+}
+
+// Settings for the optional normalization step after conversion
+#[derive(Debug, Clone)]
+pub struct NormalizeConfig {
+    pub mode: NormalizeMode,
+    pub target_db: f64,     // dBTP for peak mode, LUFS for loudness mode
+    pub true_peak_db: f64,  // Ceiling enforced by the limiter (dBTP)
+}
+
+impl NormalizeConfig {
+    pub fn new(mode: NormalizeMode) -> Self {
+        let target_db = match mode {
+            NormalizeMode::Peak => -1.0,
+            NormalizeMode::Loudness => -23.0,
+        };
+        NormalizeConfig {
+            mode,
+            target_db,
+            true_peak_db: -1.0,
+        }
+    }
+}
+
+impl std::str::FromStr for NormalizeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "peak" => Ok(NormalizeMode::Peak),
+            "loudness" | "r128" => Ok(NormalizeMode::Loudness),
+            _ => Err(format!("Unknown normalization mode: {} (expected peak or loudness)", s)),
+        }
+    }
+}
+
+fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+fn linear_to_db(value: f64) -> f64 {
+    20.0 * value.max(1e-12).log10()
+}
+
+// Split interleaved samples into one vector per channel
+fn deinterleave(audio: &AudioBuffer) -> Vec<Vec<f64>> {
+    let channels = audio.channels.max(1) as usize;
+    (0..channels)
+        .map(|c| audio.samples.iter().skip(c).step_by(channels).map(|&x| x as f64).collect())
+        .collect()
+}
+
+// K-weighting pre-filter (high shelf followed by high pass) for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        (vh + vb * k / q + k * k) / a0,
+        2.0 * (k * k - vh) / a0,
+        (vh - vb * k / q + k * k) / a0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    );
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        1.0,
+        -2.0,
+        1.0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    );
+
+    [shelf, high_pass]
+}
+
+// Gated integrated loudness in LUFS
+pub fn integrated_loudness(audio: &AudioBuffer) -> f64 {
+    let [shelf, high_pass] = k_weighting(audio.sample_rate);
+    let weighted: Vec<Vec<f64>> = deinterleave(audio)
+        .iter()
+        .map(|channel| high_pass.process(&shelf.process(channel)))
+        .collect();
+
+    let frames = audio.frames();
+    let block = ((BLOCK_SECS * audio.sample_rate as f64) as usize).min(frames).max(1);
+    let step = ((BLOCK_STEP_SECS * audio.sample_rate as f64) as usize).max(1);
+
+    // Sum of channel mean squares per 400 ms block (all channels weighted 1.0)
+    let mut block_powers = Vec::new();
+    let mut start = 0;
+    while start + block <= frames {
+        let power: f64 = weighted
+            .iter()
+            .map(|channel| channel[start..start + block].iter().map(|x| x * x).sum::<f64>() / block as f64)
+            .sum();
+        block_powers.push(power);
+        start += step;
+    }
+
+    let loudness = |power: f64| -0.691 + 10.0 * power.max(1e-20).log10();
+    let gated_mean = |threshold: f64| {
+        let gated: Vec<f64> = block_powers.iter().copied().filter(|&p| loudness(p) > threshold).collect();
+        if gated.is_empty() {
+            None
+        } else {
+            Some(gated.iter().sum::<f64>() / gated.len() as f64)
+        }
+    };
+
+    let Some(absolute) = gated_mean(ABSOLUTE_GATE_LUFS) else {
+        return f64::NEG_INFINITY;
+    };
+    let relative_gate = loudness(absolute) + RELATIVE_GATE_LU;
+    loudness(gated_mean(relative_gate).unwrap_or(absolute))
+}
+
+// Per-frame true-peak envelope: the largest absolute value among the sample
+// and the interpolated points up to the next sample, over all channels
+fn true_peak_envelope(audio: &AudioBuffer) -> Vec<f64> {
+    let channels = deinterleave(audio);
+    let frames = audio.frames();
+
+    // Hann-windowed sinc taps for each fractional phase
+    let phases: Vec<Vec<f64>> = (1..OVERSAMPLING)
+        .map(|phase| {
+            let fraction = phase as f64 / OVERSAMPLING as f64;
+            (-INTERPOLATOR_HALF_TAPS + 1..=INTERPOLATOR_HALF_TAPS)
+                .map(|tap| {
+                    let t = tap as f64 - fraction;
+                    let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+                    let window = 0.5 + 0.5 * (PI * t / INTERPOLATOR_HALF_TAPS as f64).cos();
+                    sinc * window
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut envelope = vec![0.0f64; frames];
+    for channel in &channels {
+        for (i, peak) in envelope.iter_mut().enumerate() {
+            let mut value = channel[i].abs();
+            for taps in &phases {
+                let mut sum = 0.0;
+                for (j, tap) in (-INTERPOLATOR_HALF_TAPS + 1..=INTERPOLATOR_HALF_TAPS).zip(taps) {
+                    let index = i as isize + j;
+                    if index >= 0 && (index as usize) < frames {
+                        sum += channel[index as usize] * tap;
+                    }
+                }
+                value = value.max(sum.abs());
+            }
+            *peak = peak.max(value);
+        }
+    }
+    envelope
+}
+
+// Look-ahead limiter keeping the true-peak envelope under the ceiling.
+// Returns the number of frames where gain was reduced.
+fn limit(audio: &mut AudioBuffer, ceiling_db: f64) -> usize {
+    let ceiling = db_to_linear(ceiling_db);
+    let tolerance = db_to_linear(ceiling_db + LIMITER_TOLERANCE_DB);
+    let envelope = true_peak_envelope(audio);
+    let required: Vec<f64> = envelope
+        .iter()
+        .map(|&peak| if peak > tolerance { ceiling / peak } else { 1.0 })
+        .collect();
+
+    if required.iter().all(|&g| g >= 1.0) {
+        return 0;
+    }
+
+    // Minimum required gain over the look-ahead window, so gain is already
+    // down when the peak arrives
+    let lookahead = ((LIMITER_LOOKAHEAD_SECS * audio.sample_rate as f64) as usize).max(1);
+    let mut window_min = vec![1.0; required.len()];
+    let mut candidates: VecDeque<usize> = VecDeque::new();
+    for i in (0..required.len()).rev() {
+        while candidates.back().is_some_and(|&j| required[j] >= required[i]) {
+            candidates.pop_back();
+        }
+        candidates.push_back(i);
+        while candidates.front().is_some_and(|&j| j > i + lookahead) {
+            candidates.pop_front();
+        }
+        window_min[i] = required[*candidates.front().unwrap()];
+    }
+
+    // Instant attack, exponential release
+    let release = 1.0 - (-1.0 / (LIMITER_RELEASE_SECS * audio.sample_rate as f64)).exp();
+    let channels = audio.channels.max(1) as usize;
+    let mut gain = 1.0f64;
+    let mut limited_frames = 0;
+    for (frame, &target) in audio.samples.chunks_mut(channels).zip(&window_min) {
+        gain = if target < gain { target } else { gain + (target - gain) * release };
+        if gain < 1.0 {
+            limited_frames += 1;
+        }
+        for sample in frame {
+            *sample = (*sample as f64 * gain) as f32;
+        }
+    }
+    limited_frames
+}
+
+// Normalize audio in place and return the applied gain in dB.
+// The limiter's extra gain reduction on individual peaks is not included.
+pub fn normalize(audio: &mut AudioBuffer, config: &NormalizeConfig) -> f64 {
+    let gain_db = match config.mode {
+        NormalizeMode::Peak => {
+            let peak = true_peak_envelope(audio).into_iter().fold(0.0, f64::max);
+            if peak == 0.0 {
+                return 0.0;
+            }
+            config.target_db - linear_to_db(peak)
+        }
+        NormalizeMode::Loudness => {
+            let loudness = integrated_loudness(audio);
+            if !loudness.is_finite() {
+                return 0.0;
+            }
+            config.target_db - loudness
+        }
+    };
+
+    let gain = db_to_linear(gain_db) as f32;
+    for sample in &mut audio.samples {
+        *sample *= gain;
+    }
+
+    let limited_frames = limit(audio, config.true_peak_db);
+    if limited_frames > 0 {
+        println!("Limited {} frames to {:.1} dBTP", limited_frames, config.true_peak_db);
+    }
+
+    gain_db
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude: f64, seconds: f64) -> AudioBuffer {
+        let sample_rate = 48000;
+        let samples = (0..(seconds * sample_rate as f64) as usize)
+            .map(|i| (amplitude * (2.0 * PI * 1000.0 * i as f64 / sample_rate as f64).sin()) as f32)
+            .collect();
+        AudioBuffer { sample_rate, channels: 1, samples }
+    }
+
+    fn true_peak_db(audio: &AudioBuffer) -> f64 {
+        linear_to_db(true_peak_envelope(audio).into_iter().fold(0.0, f64::max))
+    }
+
+    #[test]
+    fn full_scale_sine_reads_minus_three_lufs() {
+        // BS.1770: a 0 dBFS 1 kHz sine in one channel is -3.01 LUFS
+        assert!((integrated_loudness(&sine(1.0, 3.0)) + 3.01).abs() < 0.1);
+    }
+
+    #[test]
+    fn loudness_normalization_hits_the_target() {
+        for target_db in [-30.0, -23.0, -16.0] {
+            let mut audio = sine(0.05, 3.0);
+            let config = NormalizeConfig { target_db, ..NormalizeConfig::new(NormalizeMode::Loudness) };
+            normalize(&mut audio, &config);
+            let loudness = integrated_loudness(&audio);
+            assert!((loudness - target_db).abs() <= 0.1, "target {} got {}", target_db, loudness);
+        }
+    }
+
+    #[test]
+    fn limiter_keeps_true_peak_under_the_ceiling() {
+        // -3 LUFS needs a full-scale sine, well over the -1 dBTP ceiling
+        let mut audio = sine(0.05, 2.0);
+        let config = NormalizeConfig { target_db: -3.0, ..NormalizeConfig::new(NormalizeMode::Loudness) };
+        normalize(&mut audio, &config);
+        let peak = true_peak_db(&audio);
+        assert!(peak <= config.true_peak_db + LIMITER_TOLERANCE_DB, "true peak {} dBTP", peak);
+
+        let mut audio = sine(0.05, 2.0);
+        normalize(&mut audio, &NormalizeConfig::new(NormalizeMode::Peak));
+        assert!(true_peak_db(&audio) <= -1.0 + LIMITER_TOLERANCE_DB);
+    }
+
+    #[test]
+    fn silence_and_gated_out_input_get_no_gain() {
+        // Silence, and a -100 dBFS tone below the absolute gate
+        for amplitude in [0.0, 1e-5] {
+            for mode in [NormalizeMode::Loudness, NormalizeMode::Peak] {
+                let mut audio = sine(amplitude, 1.0);
+                let gain_db = normalize(&mut audio, &NormalizeConfig::new(mode));
+                assert!(gain_db.is_finite(), "{:?} on amplitude {}: {}", mode, amplitude, gain_db);
+                assert!(audio.samples.iter().all(|s| s.is_finite()));
+            }
+        }
+        assert_eq!(normalize(&mut sine(0.0, 1.0), &NormalizeConfig::new(NormalizeMode::Loudness)), 0.0);
+        assert_eq!(normalize(&mut sine(1e-5, 1.0), &NormalizeConfig::new(NormalizeMode::Loudness)), 0.0);
+    }
+}
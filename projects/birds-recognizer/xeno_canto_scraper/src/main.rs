@@ -1,19 +1,90 @@
+mod audio;
+mod augment;
+mod cache;
+mod chunk;
+mod classmap;
+mod clips;
+mod dataset;
+mod detect;
+mod dsp;
+mod evaluate;
+mod features;
+mod filters;
+mod flac;
+mod html;
+mod jsmath;
+mod labels;
+mod license;
+mod loudness;
+mod mapping;
+mod model;
+mod profile;
+mod quota;
+mod render;
+mod review;
+mod segment;
+mod serve;
+mod spectrogram;
+mod split;
+mod taxonomy;
+mod trainer;
+mod unknown;
+
+use audio::{read_audio, resample, write_audio, AudioBuffer, InputFormat, OutputFormat, TARGET_SAMPLE_RATE};
 use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
 use scraper::{Html, Selector};
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 use threadpool::ThreadPool;
 use url::Url;
-use csv::{Reader, Writer};
+use augment::{augment_directory, AugmentConfig};
+use cache::{cache_gc, FeatureCache};
+use chunk::{chunk_directory, ChunkConfig};
+use classmap::export_class_map;
+use dataset::extract_features;
+use detect::{detect_files, DetectConfig};
+use evaluate::{evaluate, EvaluateConfig};
+use features::{print_features, FeatureKind};
+use labels::{export_labels, import_labels, ImportConfig};
+use license::{license_from_url, license_summary, write_attribution, AttributionFormat, LicenseFilter};
+use loudness::{NormalizeConfig, NormalizeMode};
+use mapping::check_mapping;
+use model::{classify_files, collect_audio_files, Classifier};
+use profile::{apply_profile, build_profile, find_profile, Profile};
+use quota::{load_quota_overrides, plan_downloads, QuotaConfig};
+use render::render_directory;
+use review::{apply_review, build_review_queue, ReviewConfig, Uncertainty};
+use segment::{segment_directory, SegmentConfig};
+use serve::{serve, ServeConfig};
+use spectrogram::{export_log_mel, LogMelConfig, LogMelOutput};
+use split::{split_directory, GroupBy, SplitConfig};
+use taxonomy::{normalize_taxonomy, NormalizeTaxonomyConfig, Taxonomy};
+use trainer::{train_model, TrainConfig};
+use unknown::{build_unknown_class, UnknownConfig};
 
-// Struct to hold metadata for a recording
+// Download link with the details the results page lists for its recording
 #[derive(Debug, Clone)]
+struct DownloadLink {
+    url: String,
+    id: String,
+    common_name: String,
+    scientific_name: String,
+    recordist: String,
+    location: String,
+    background: String,   // Other species heard in the recording, ';'-separated
+    vocalization: String, // Sound type, lowercased
+    license: String,      // Empty when the row links no license
+}
+
+// Struct to hold metadata for a recording
+#[derive(Debug, Clone, Default)]
 struct RecordingMetadata {
     id: String,
     url: String,
@@ -22,6 +93,13 @@ struct RecordingMetadata {
     filename: String,
     species: String, // Normalized species name
     is_downloaded: bool,
+    gain_db: Option<f64>, // Gain applied by normalization after conversion
+    recordist: String,    // Empty when the results page did not list one
+    location: String,
+    split: String,        // "train", "val" or "test" once --split has run
+    background: String,   // Other species heard in the recording, ';'-separated
+    vocalization: String, // Sound type from the results page, e.g. "call, song"
+    license: String,      // e.g. "CC BY-NC-SA 4.0", empty when not scraped
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -32,32 +110,556 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("  {} <start_url> [output_directory] [--delay <ms>]", args[0]);
         eprintln!("  {} --download-only [output_directory] [--delay <ms>]", args[0]);
         eprintln!("  {} --convert <directory>", args[0]);
+        eprintln!("  Conversion options: [--format <wav|flac>] [--profile <name>] [--profiles <file>] [--normalize <peak|loudness>] [--target <db>] [--true-peak <db>]");
+        eprintln!("  Download options: [--min-per-species <n>] [--max-per-species <n>] [--balance] [--quotas <file>]");
+        eprintln!("  Taxonomy options: [--checklist <file>] [--collapse-subspecies] [--key <common|scientific>]");
+        eprintln!("  {} --normalize-taxonomy <directory> --checklist <file> [--collapse-subspecies] [--key <common|scientific>] [--dry-run]", args[0]);
+        eprintln!("  {} --build-profile <directory> <profile> [--profiles <file>]", args[0]);
+        eprintln!("  {} --segment <directory> [--threshold-db <db>] [--flux-ratio <x>] [--min-clip <secs>] [--max-clip <secs>] [--padding <secs>]", args[0]);
+        eprintln!("  {} --chunk <directory> [--window <secs>] [--hop <secs>] [--pad-tail] [--min-rms-db <db>]", args[0]);
+        eprintln!("  {} --import-labels <directory> [--labels-dir <directory>] [--label-column <name>] [--band-limit] [--min-duration <secs>]", args[0]);
+        eprintln!("  {} --export-labels <directory> [--source <segment|chunk|labels>] [--output <directory>]", args[0]);
+        eprintln!("  {} --augment <directory> [--clips] [--copies <n>] [--seed <n>] [--noise-dir <directory>]", args[0]);
+        eprintln!("  {} --build-unknown <directory> [--targets <species,...>] [--label <name>] [--window <secs>] [--per-recording <n>] [--max-noise-db <db>] [--min-rms-db <db>]", args[0]);
+        eprintln!("  {} --licenses <directory> [--exclude <by,nc,nd,sa,cc0,unknown|none>]", args[0]);
+        eprintln!("  {} --attribution <directory> [--clips] [--features <file>] [--attribution-format <markdown|html|json>] [--output <file>]", args[0]);
+        eprintln!("  {} --check-mapping <directory>   (species -> class rules in <directory>/class_mapping.csv)", args[0]);
+        eprintln!("  {} --split <directory> [--val <fraction>] [--test <fraction>] [--group-by <recording|recordist|site>] [--seed <n>] [--reassign]", args[0]);
+        eprintln!("  {} --mfcc <audio_file> [--simple]", args[0]);
+        eprintln!("  {} --extract-features <directory> [--clips] [--simple] [--output <file>] [--threads <n>] [--npy] [--no-cache]", args[0]);
+        eprintln!("  {} --cache-gc <directory>", args[0]);
+        eprintln!("  {} --class-map <directory> [--model-dir <directory>]", args[0]);
+        eprintln!("  {} --train <directory> [--clips] [--features <file>] [--output <model directory>] [--layers <n,n,...>] [--dropout <rate>] [--optimizer <adam|sgd|rmsprop>] [--learning-rate <x>] [--epochs <n>] [--batch-size <n>] [--patience <n>] [--seed <n>] [--exclude-background]", args[0]);
+        eprintln!("  {} --classify <model.json> <audio_file|directory>... [--class-map <file>] [--top <n>] [--output <file>] [--threads <n>]", args[0]);
+        eprintln!("  {} --detect <model.json> <audio_file|directory>... [--class-map <file>] [--window <secs>] [--hop <secs>] [--threshold <score>] [--merge-gap <secs>] [--min-windows <n>] [--min-rms-db <db>] [--output <directory>] [--threads <n>]", args[0]);
+        eprintln!("  {} --serve <model.json> [--class-map <file>] [--address <host:port>] [--top <n>] [--max-body-mb <n>] [--read-timeout <secs>] [--threads <n>]", args[0]);
+        eprintln!("  {} --evaluate <directory> <model.json> [--split <name>] [--clips] [--top-k <n>] [--worst <n>] [--class-map <file>] [--output <directory>] [--threads <n>]", args[0]);
+        eprintln!("  {} --review-queue <directory> <model.json> [--uncertainty <entropy|margin>] [--limit <n>] [--include-verified] [--class-map <file>] [--output <directory>] [--threads <n>]", args[0]);
+        eprintln!("  {} --apply-review <directory> [--queue <file>]", args[0]);
+        eprintln!("  {} --render <directory> [--clips] [--waveform] [--output <directory>] [--threads <n>]", args[0]);
+        eprintln!("  {} --logmel <directory> [--clips] [--n-fft <n>] [--hop-size <n>] [--n-mels <n>] [--fmin <hz>] [--fmax <hz>] [--packed] [--output <path>] [--threads <n>]", args[0]);
         std::process::exit(1);
     }
 
+    // Format converted recordings are stored in
+    let output_format = match flag_value::<String>(&args, "--format") {
+        Some(format) => format.parse::<OutputFormat>()?,
+        None => OutputFormat::Wav,
+    };
+
     // Handle conversion command
     if args[1] == "--convert" {
         if args.len() < 3 {
             eprintln!("Please specify a directory to convert");
             std::process::exit(1);
         }
-        return batch_convert_directory(&args[2]);
+        let profile = load_conversion_profile(&args, &args[2])?;
+        return batch_convert_directory(&args[2], &profile, output_format);
+    }
+
+    // Handle dataset variant command
+    if args[1] == "--build-profile" {
+        if args.len() < 4 {
+            eprintln!("Please specify a directory and a profile name");
+            std::process::exit(1);
+        }
+        return build_profile(&args[2], &args[3], &profiles_path(&args, &args[2]));
+    }
+
+    // Handle segmentation command
+    if args[1] == "--segment" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to segment");
+            std::process::exit(1);
+        }
+        let defaults = SegmentConfig::default();
+        let config = SegmentConfig {
+            threshold_db: flag_value(&args, "--threshold-db").unwrap_or(defaults.threshold_db),
+            flux_ratio: flag_value(&args, "--flux-ratio").unwrap_or(defaults.flux_ratio),
+            min_clip_secs: flag_value(&args, "--min-clip").unwrap_or(defaults.min_clip_secs),
+            max_clip_secs: flag_value(&args, "--max-clip").unwrap_or(defaults.max_clip_secs),
+            padding_secs: flag_value(&args, "--padding").unwrap_or(defaults.padding_secs),
+        };
+        return segment_directory(&args[2], &config);
+    }
+
+    // Handle fixed-length chunking command
+    if args[1] == "--chunk" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to chunk");
+            std::process::exit(1);
+        }
+        let defaults = ChunkConfig::default();
+        let window_secs = flag_value(&args, "--window").unwrap_or(defaults.window_secs);
+        let config = ChunkConfig {
+            window_secs,
+            hop_secs: flag_value(&args, "--hop").unwrap_or(window_secs),
+            pad_tail: args.iter().any(|arg| arg == "--pad-tail"),
+            min_rms_db: flag_value(&args, "--min-rms-db"),
+        };
+        return chunk_directory(&args[2], &config);
+    }
+
+    // Handle Raven / Audacity label import
+    if args[1] == "--import-labels" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to import labels into");
+            std::process::exit(1);
+        }
+        let config = ImportConfig {
+            labels_dir: match flag_value::<String>(&args, "--labels-dir") {
+                Some(path) => PathBuf::from(path),
+                None => Path::new(&args[2]).join("labels"),
+            },
+            label_column: flag_value(&args, "--label-column"),
+            band_limit: args.iter().any(|arg| arg == "--band-limit"),
+            min_duration_secs: flag_value(&args, "--min-duration").unwrap_or(0.05),
+        };
+        return import_labels(&args[2], &config);
+    }
+
+    // Handle Raven / Audacity label export
+    if args[1] == "--export-labels" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory whose segments to export");
+            std::process::exit(1);
+        }
+        let export_dir = match flag_value::<String>(&args, "--output") {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&args[2]).join("exported_labels"),
+        };
+        let source = flag_value::<String>(&args, "--source");
+        return export_labels(&args[2], &export_dir, source.as_deref());
+    }
+
+    // Handle negative class builder
+    if args[1] == "--build-unknown" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to build the unknown class in");
+            std::process::exit(1);
+        }
+        let window_secs = flag_value(&args, "--window").unwrap_or(3.0);
+        let config = UnknownConfig {
+            label: flag_value::<String>(&args, "--label").map_or_else(|| "unknown".to_string(), |l| format_species_name(&l)),
+            targets: flag_value::<String>(&args, "--targets")
+                .map(|list| list.split(',').map(|s| format_species_name(s.trim())).collect()),
+            window: ChunkConfig {
+                window_secs,
+                hop_secs: window_secs,
+                pad_tail: false,
+                min_rms_db: flag_value(&args, "--min-rms-db"),
+            },
+            per_recording: flag_value(&args, "--per-recording").unwrap_or(2),
+            max_noise_db: flag_value(&args, "--max-noise-db"),
+        };
+        return build_unknown_class(&args[2], &config);
+    }
+
+    // Handle license summary and filter command
+    if args[1] == "--licenses" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory whose licenses to list");
+            std::process::exit(1);
+        }
+        let exclude = match flag_value::<String>(&args, "--exclude") {
+            Some(terms) => Some(terms.parse::<LicenseFilter>()?),
+            None => None,
+        };
+        return license_summary(&args[2], exclude.as_ref());
+    }
+
+    // Handle attribution export command
+    if args[1] == "--attribution" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory whose feature dataset to credit");
+            std::process::exit(1);
+        }
+        let use_clips = args.iter().any(|arg| arg == "--clips");
+        let features = match flag_value::<String>(&args, "--features") {
+            Some(path) => PathBuf::from(path),
+            None if use_clips => Path::new(&args[2]).join("clip_features.bin"),
+            None => Path::new(&args[2]).join("features.bin"),
+        };
+        let format = match flag_value::<String>(&args, "--attribution-format") {
+            Some(format) => format.parse::<AttributionFormat>()?,
+            None => AttributionFormat::Markdown,
+        };
+        let output = match flag_value::<String>(&args, "--output") {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&args[2]).join(format.file_name()),
+        };
+        let ids = dataset::read_dataset(&features)?.records.into_iter().map(|r| r.recording_id).collect();
+        return write_attribution(&args[2], &ids, format, &output);
+    }
+
+    // Handle class mapping summary command
+    if args[1] == "--check-mapping" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory whose class mapping to check");
+            std::process::exit(1);
+        }
+        return check_mapping(&args[2]);
+    }
+
+    // Handle taxonomy normalization command
+    if args[1] == "--normalize-taxonomy" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to normalize");
+            std::process::exit(1);
+        }
+        let Some(taxonomy) = load_taxonomy(&args)? else {
+            eprintln!("Please specify a checklist with --checklist <file>");
+            std::process::exit(1);
+        };
+        let config = NormalizeTaxonomyConfig { dry_run: args.iter().any(|arg| arg == "--dry-run") };
+        return normalize_taxonomy(&args[2], &taxonomy, &config);
+    }
+
+    // Handle offline augmentation command
+    if args[1] == "--augment" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to augment");
+            std::process::exit(1);
+        }
+        let defaults = AugmentConfig::default();
+        let config = AugmentConfig {
+            copies: flag_value(&args, "--copies").unwrap_or(defaults.copies),
+            seed: flag_value(&args, "--seed").unwrap_or(defaults.seed),
+            noise_dir: flag_value::<String>(&args, "--noise-dir").map(PathBuf::from),
+            ..defaults
+        };
+        let use_clips = args.iter().any(|arg| arg == "--clips");
+        return augment_directory(&args[2], &config, use_clips);
+    }
+
+    // Handle train/validation/test split command
+    if args[1] == "--split" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to split");
+            std::process::exit(1);
+        }
+        let defaults = SplitConfig::default();
+        let config = SplitConfig {
+            val_fraction: flag_value(&args, "--val").unwrap_or(defaults.val_fraction),
+            test_fraction: flag_value(&args, "--test").unwrap_or(defaults.test_fraction),
+            group_by: match flag_value::<String>(&args, "--group-by") {
+                Some(group_by) => group_by.parse::<GroupBy>()?,
+                None => defaults.group_by,
+            },
+            seed: flag_value(&args, "--seed").unwrap_or(defaults.seed),
+            reassign: args.iter().any(|arg| arg == "--reassign"),
+        };
+        return split_directory(&args[2], &config);
+    }
+
+    // Extractor used by the feature commands
+    let feature_kind = if args.iter().any(|arg| arg == "--simple") {
+        FeatureKind::Simple
+    } else {
+        FeatureKind::Enhanced
+    };
+
+    // Handle single-file feature extraction command
+    if args[1] == "--mfcc" {
+        if args.len() < 3 {
+            eprintln!("Please specify an audio file");
+            std::process::exit(1);
+        }
+        return print_features(Path::new(&args[2]), feature_kind);
+    }
+
+    // Handle dataset feature extraction command
+    if args[1] == "--extract-features" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to extract features from");
+            std::process::exit(1);
+        }
+        let use_clips = args.iter().any(|arg| arg == "--clips");
+        let output_path = match flag_value::<String>(&args, "--output") {
+            Some(path) => PathBuf::from(path),
+            None if use_clips => Path::new(&args[2]).join("clip_features.bin"),
+            None => Path::new(&args[2]).join("features.bin"),
+        };
+        let threads = flag_value(&args, "--threads")
+            .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+        let npy = args.iter().any(|arg| arg == "--npy");
+        let cache = if args.iter().any(|arg| arg == "--no-cache") {
+            None
+        } else {
+            Some(FeatureCache::open(&args[2], feature_kind)?)
+        };
+        return extract_features(&args[2], &output_path, feature_kind, use_clips, threads, npy, cache);
+    }
+
+    // Handle log-mel spectrogram export command
+    if args[1] == "--logmel" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to export spectrograms from");
+            std::process::exit(1);
+        }
+        let defaults = LogMelConfig::default();
+        let config = LogMelConfig {
+            n_fft: flag_value(&args, "--n-fft").unwrap_or(defaults.n_fft),
+            hop: flag_value(&args, "--hop-size").unwrap_or(defaults.hop),
+            n_mels: flag_value(&args, "--n-mels").unwrap_or(defaults.n_mels),
+            fmin: flag_value(&args, "--fmin").unwrap_or(defaults.fmin),
+            fmax: flag_value(&args, "--fmax"),
+        };
+        let use_clips = args.iter().any(|arg| arg == "--clips");
+        let name = if use_clips { "clip_logmel" } else { "logmel" };
+        let output = if args.iter().any(|arg| arg == "--packed") {
+            LogMelOutput::Packed(
+                flag_value::<String>(&args, "--output")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| Path::new(&args[2]).join(format!("{}.bin", name))),
+            )
+        } else {
+            LogMelOutput::Npy(
+                flag_value::<String>(&args, "--output")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| Path::new(&args[2]).join(name)),
+            )
+        };
+        let threads = flag_value(&args, "--threads")
+            .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+        return export_log_mel(&args[2], use_clips, &config, &output, threads);
+    }
+
+    // Handle spectrogram rendering command
+    if args[1] == "--render" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to render");
+            std::process::exit(1);
+        }
+        let use_clips = args.iter().any(|arg| arg == "--clips");
+        let render_dir = match flag_value::<String>(&args, "--output") {
+            Some(path) => PathBuf::from(path),
+            None if use_clips => Path::new(&args[2]).join("clip_render"),
+            None => Path::new(&args[2]).join("render"),
+        };
+        let waveform = args.iter().any(|arg| arg == "--waveform");
+        let threads = flag_value(&args, "--threads")
+            .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+        return render_directory(&args[2], &render_dir, use_clips, waveform, threads);
+    }
+
+    // Handle feature cache cleanup command
+    if args[1] == "--cache-gc" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory whose feature cache to clean");
+            std::process::exit(1);
+        }
+        return cache_gc(&args[2]);
+    }
+
+    // Handle class map and species manifest export
+    if args[1] == "--class-map" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory to build the class map from");
+            std::process::exit(1);
+        }
+        let model_dir = match flag_value::<String>(&args, "--model-dir") {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&args[2]).join("model"),
+        };
+        return export_class_map(&args[2], &model_dir);
+    }
+
+    // Handle classifier training command
+    if args[1] == "--train" {
+        if args.len() < 3 {
+            eprintln!("Please specify a directory with extracted features to train on");
+            std::process::exit(1);
+        }
+        let use_clips = args.iter().any(|arg| arg == "--clips");
+        let features = match flag_value::<String>(&args, "--features") {
+            Some(path) => PathBuf::from(path),
+            None if use_clips => Path::new(&args[2]).join("clip_features.bin"),
+            None => Path::new(&args[2]).join("features.bin"),
+        };
+        let model_dir = match flag_value::<String>(&args, "--output") {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&args[2]).join("model"),
+        };
+        let defaults = TrainConfig::default();
+        let hidden_units = match flag_value::<String>(&args, "--layers") {
+            Some(layers) => layers
+                .split(',')
+                .map(|n| n.trim().parse::<usize>().map_err(|_| format!("Invalid layer size: {}", n)))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => defaults.hidden_units,
+        };
+        let config = TrainConfig {
+            hidden_units,
+            dropout: flag_value(&args, "--dropout").unwrap_or(defaults.dropout),
+            optimizer: match flag_value::<String>(&args, "--optimizer") {
+                Some(name) => name.parse()?,
+                None => defaults.optimizer,
+            },
+            learning_rate: flag_value(&args, "--learning-rate").unwrap_or(defaults.learning_rate),
+            epochs: flag_value(&args, "--epochs").unwrap_or(defaults.epochs),
+            batch_size: flag_value(&args, "--batch-size").unwrap_or(defaults.batch_size),
+            patience: flag_value(&args, "--patience").unwrap_or(defaults.patience),
+            seed: flag_value(&args, "--seed").unwrap_or(defaults.seed),
+            exclude_background: args.iter().any(|arg| arg == "--exclude-background"),
+        };
+        return train_model(&args[2], &features, &model_dir, &config);
+    }
+
+    // Handle batch classification command
+    if args[1] == "--classify" {
+        if args.len() < 4 {
+            eprintln!("Please specify a model and the audio files or directories to classify");
+            std::process::exit(1);
+        }
+        let class_map = flag_value::<String>(&args, "--class-map").map(PathBuf::from);
+        let classifier = Classifier::load(Path::new(&args[2]), class_map.as_deref())?;
+        let inputs: Vec<String> = args[3..].iter().take_while(|arg| !arg.starts_with("--")).cloned().collect();
+        let files = collect_audio_files(&inputs)?;
+        let top = flag_value(&args, "--top").unwrap_or(3);
+        let output = flag_value::<String>(&args, "--output").map(PathBuf::from);
+        let threads = flag_value(&args, "--threads")
+            .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+        return classify_files(&classifier, &files, top, threads, output.as_deref());
+    }
+
+    // Handle sliding-window detection command
+    if args[1] == "--detect" {
+        if args.len() < 4 {
+            eprintln!("Please specify a model and the recordings or directories to scan");
+            std::process::exit(1);
+        }
+        let class_map = flag_value::<String>(&args, "--class-map").map(PathBuf::from);
+        let classifier = Classifier::load(Path::new(&args[2]), class_map.as_deref())?;
+        let inputs: Vec<String> = args[3..].iter().take_while(|arg| !arg.starts_with("--")).cloned().collect();
+        let files = collect_audio_files(&inputs)?;
+        let defaults = DetectConfig::default();
+        let config = DetectConfig {
+            window: ChunkConfig {
+                window_secs: flag_value(&args, "--window").unwrap_or(defaults.window.window_secs),
+                hop_secs: flag_value(&args, "--hop").unwrap_or(defaults.window.hop_secs),
+                pad_tail: defaults.window.pad_tail,
+                min_rms_db: flag_value(&args, "--min-rms-db"),
+            },
+            threshold: flag_value(&args, "--threshold").unwrap_or(defaults.threshold),
+            merge_gap_secs: flag_value(&args, "--merge-gap").unwrap_or(defaults.merge_gap_secs),
+            min_windows: flag_value(&args, "--min-windows").unwrap_or(defaults.min_windows),
+            output_dir: flag_value::<String>(&args, "--output").map_or(defaults.output_dir, PathBuf::from),
+            threads: flag_value(&args, "--threads")
+                .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(defaults.threads)),
+        };
+        return detect_files(&classifier, &files, &config);
     }
 
+    // Handle prediction server command
+    if args[1] == "--serve" {
+        if args.len() < 3 {
+            eprintln!("Please specify a model to serve");
+            std::process::exit(1);
+        }
+        let class_map = flag_value::<String>(&args, "--class-map").map(PathBuf::from);
+        let classifier = Classifier::load(Path::new(&args[2]), class_map.as_deref())?;
+        let defaults = ServeConfig::default();
+        let config = ServeConfig {
+            address: flag_value(&args, "--address").unwrap_or(defaults.address),
+            threads: flag_value(&args, "--threads")
+                .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(defaults.threads)),
+            top: flag_value(&args, "--top").unwrap_or(defaults.top),
+            max_body_bytes: flag_value::<usize>(&args, "--max-body-mb")
+                .map_or(defaults.max_body_bytes, |mb| mb * 1024 * 1024),
+            read_timeout: flag_value::<u64>(&args, "--read-timeout").map_or(defaults.read_timeout, Duration::from_secs),
+        };
+        return serve(classifier, &config);
+    }
+
+    // Handle model evaluation command
+    if args[1] == "--evaluate" {
+        if args.len() < 4 {
+            eprintln!("Please specify a directory and a model to evaluate");
+            std::process::exit(1);
+        }
+        let class_map = flag_value::<String>(&args, "--class-map").map(PathBuf::from);
+        let classifier = Classifier::load(Path::new(&args[3]), class_map.as_deref())?;
+        let config = EvaluateConfig {
+            split: flag_value(&args, "--split").unwrap_or_else(|| "test".to_string()),
+            use_clips: args.iter().any(|arg| arg == "--clips"),
+            top_k: flag_value(&args, "--top-k").unwrap_or(3),
+            worst: flag_value(&args, "--worst").unwrap_or(10),
+            report_dir: match flag_value::<String>(&args, "--output") {
+                Some(path) => PathBuf::from(path),
+                None => Path::new(&args[2]).join("evaluation"),
+            },
+            threads: flag_value(&args, "--threads")
+                .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(4)),
+        };
+        return evaluate(&args[2], &classifier, &config);
+    }
+
+    // Handle active-learning review queue command
+    if args[1] == "--review-queue" {
+        if args.len() < 4 {
+            eprintln!("Please specify a directory and the model to rank its clips with");
+            std::process::exit(1);
+        }
+        let class_map = flag_value::<String>(&args, "--class-map").map(PathBuf::from);
+        let classifier = Classifier::load(Path::new(&args[3]), class_map.as_deref())?;
+        let config = ReviewConfig {
+            uncertainty: match flag_value::<String>(&args, "--uncertainty") {
+                Some(name) => name.parse()?,
+                None => Uncertainty::Entropy,
+            },
+            limit: flag_value(&args, "--limit").unwrap_or(100),
+            include_verified: args.iter().any(|arg| arg == "--include-verified"),
+            review_dir: match flag_value::<String>(&args, "--output") {
+                Some(path) => PathBuf::from(path),
+                None => Path::new(&args[2]).join("review"),
+            },
+            threads: flag_value(&args, "--threads")
+                .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(4)),
+        };
+        return build_review_queue(&args[2], &classifier, &config);
+    }
+
+    // Handle review label write-back command
+    if args[1] == "--apply-review" {
+        if args.len() < 3 {
+            eprintln!("Please specify the directory whose review queue to apply");
+            std::process::exit(1);
+        }
+        let queue = match flag_value::<String>(&args, "--queue") {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&args[2]).join("review").join("queue.csv"),
+        };
+        return apply_review(&args[2], &queue);
+    }
+
+    // Per-species download quotas
+    let quotas = QuotaConfig {
+        min_per_species: flag_value(&args, "--min-per-species"),
+        max_per_species: flag_value(&args, "--max-per-species"),
+        balance: args.iter().any(|arg| arg == "--balance"),
+        overrides: match flag_value::<String>(&args, "--quotas") {
+            Some(path) => load_quota_overrides(Path::new(&path))?,
+            None => HashMap::new(),
+        },
+    };
+
+    // Checklist that new recordings are mapped to class keys with
+    let taxonomy = load_taxonomy(&args)?;
+
     // Rate limiting settings
     let mut page_delay_ms = 2000; // Default: 2 seconds between page requests
     let mut download_delay_ms = 500; // Default: 0.5 seconds between downloads
     
     // Check for custom delay parameter
-    if let Some(delay_index) = args.iter().position(|arg| arg == "--delay") {
-        if delay_index + 1 < args.len() {
-            if let Ok(delay) = args[delay_index + 1].parse::<u64>() {
-                page_delay_ms = delay;
-                download_delay_ms = delay / 4;
-                println!("Using custom delay: {}ms between pages, {}ms between downloads", 
-                         page_delay_ms, download_delay_ms);
-            }
-        }
+    if let Some(delay_index) = args.iter().position(|arg| arg == "--delay")
+        && delay_index + 1 < args.len()
+        && let Ok(delay) = args[delay_index + 1].parse::<u64>()
+    {
+        page_delay_ms = delay;
+        download_delay_ms = delay / 4;
+        println!("Using custom delay: {}ms between pages, {}ms between downloads", 
+                 page_delay_ms, download_delay_ms);
     }
 
     // Check if we're in download-only mode
@@ -79,6 +681,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create output directory if it doesn't exist
     std::fs::create_dir_all(output_dir)?;
+
+    // Processing applied to each download as it is converted
+    let profile = load_conversion_profile(&args, output_dir)?;
     
     // Create HTTP client
     let client = Client::builder()
@@ -105,7 +710,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         write_metadata_csv(&metadata_path, &updated_metadata)?;
         
         println!("4. Downloading missing files...");
-        download_missing_files(&client, &updated_metadata, output_dir, download_delay_ms)?;
+        download_missing_files(&client, &updated_metadata, output_dir, download_delay_ms, &profile, output_format, &quotas)?;
     } else {
         // Normal mode - extract links first
         let start_url = &args[1];
@@ -115,7 +720,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Found {} total download links", download_info.len());
         
         println!("2. Creating/updating metadata CSV...");
-        let metadata = load_or_create_metadata(&metadata_path, &download_info)?;
+        let metadata = load_or_create_metadata(&metadata_path, &download_info, output_format, taxonomy.as_ref())?;
         
         println!("3. Checking for already downloaded files...");
         let updated_metadata = update_download_status(&metadata, output_dir)?;
@@ -124,19 +729,60 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         write_metadata_csv(&metadata_path, &updated_metadata)?;
         
         println!("5. Downloading missing files...");
-        download_missing_files(&client, &updated_metadata, output_dir, download_delay_ms)?;
+        download_missing_files(&client, &updated_metadata, output_dir, download_delay_ms, &profile, output_format, &quotas)?;
     }
     
     println!("Scraping completed!");
     Ok(())
 }
 
+// Parse the value following a command line flag, e.g. "--delay 500"
+fn flag_value<T: std::str::FromStr>(args: &[String], flag: &str) -> Option<T> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    args.get(index + 1)?.parse().ok()
+}
+
+// The --profiles file, by default profiles.ini in the catalog directory
+fn profiles_path(args: &[String], output_dir: &str) -> PathBuf {
+    flag_value::<String>(args, "--profiles").map_or_else(|| Path::new(output_dir).join("profiles.ini"), PathBuf::from)
+}
+
+// The --profile that conversions run decoded audio through, with
+// --normalize taking the place of the profile's own normalization
+fn load_conversion_profile(args: &[String], output_dir: &str) -> Result<Profile, Box<dyn std::error::Error>> {
+    let mut profile = match flag_value::<String>(args, "--profile") {
+        Some(name) => find_profile(&profiles_path(args, output_dir), &name)?,
+        None => Profile::raw("raw"),
+    };
+    if let Some(mode) = flag_value::<String>(args, "--normalize") {
+        let mut config = NormalizeConfig::new(mode.parse::<NormalizeMode>()?);
+        config.target_db = flag_value(args, "--target").unwrap_or(config.target_db);
+        config.true_peak_db = flag_value(args, "--true-peak").unwrap_or(config.true_peak_db);
+        profile.normalize = Some(config);
+    }
+    Ok(profile)
+}
+
+// Load the --checklist taxonomy with its --collapse-subspecies and --key options
+fn load_taxonomy(args: &[String]) -> Result<Option<Taxonomy>, Box<dyn std::error::Error>> {
+    let Some(path) = flag_value::<String>(args, "--checklist") else {
+        return Ok(None);
+    };
+    let mut taxonomy = Taxonomy::load(Path::new(&path))?;
+    taxonomy.collapse_subspecies = args.iter().any(|arg| arg == "--collapse-subspecies");
+    if let Some(key) = flag_value::<String>(args, "--key") {
+        taxonomy.key_style = key.parse()?;
+    }
+    println!("Loaded {} taxa from {}", taxonomy.taxa.len(), path);
+    Ok(Some(taxonomy))
+}
+
 // Extract all download links by crawling pages
 fn extract_all_download_links(
     client: Client, 
     start_url: &str, 
     page_delay_ms: u64
-) -> Result<Vec<(String, String, String, String)>, Box<dyn std::error::Error>> {
+) -> Result<Vec<DownloadLink>, Box<dyn std::error::Error>> {
     let mut current_page_url = start_url.to_string();
     let mut page_num = 1;
     let mut download_info = Vec::new();
@@ -161,6 +807,20 @@ fn extract_all_download_links(
         let selector = Selector::parse("a[href$='/download']").unwrap();
         let mut page_downloads = Vec::new();
 
+        // Recordist and location come from the results table; find their
+        // columns by header so a changed column order does not mix them up
+        let header_selector = Selector::parse("tr th").unwrap();
+        let headers: Vec<String> = document
+            .select(&header_selector)
+            .map(|th| th.text().collect::<String>().trim().to_lowercase())
+            .collect();
+        let recordist_column = headers.iter().position(|h| h.starts_with("recordist"));
+        let location_column = headers.iter().position(|h| h.starts_with("location"));
+        let species_column = headers.iter().position(|h| h.starts_with("common name"));
+        let type_column = headers.iter().position(|h| h == "type");
+        let cell_selector = Selector::parse("td").unwrap();
+        let license_selector = Selector::parse("a[href*='creativecommons.org']").unwrap();
+
         for element in document.select(&selector) {
             if let Some(href) = element.value().attr("href") {
                 let download_url = if href.starts_with("http") {
@@ -216,7 +876,56 @@ fn extract_all_download_links(
                     }
                 }
                 
-                page_downloads.push((download_url, id, common_name.to_string(), scientific_name.to_string()));
+                // Cells of the table row holding the link, if any
+                let row = element
+                    .ancestors()
+                    .filter_map(scraper::ElementRef::wrap)
+                    .find(|ancestor| ancestor.value().name() == "tr");
+                let cells: Vec<String> = row
+                    .map(|row| {
+                        row.select(&cell_selector)
+                            .map(|td| td.text().collect::<Vec<_>>().join(" ").split_whitespace().collect::<Vec<_>>().join(" "))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                // The license is linked to its Creative Commons deed
+                let license = row
+                    .and_then(|row| row.select(&license_selector).next())
+                    .and_then(|link| link.value().attr("href"))
+                    .and_then(license_from_url)
+                    .unwrap_or_default();
+                let cell = |column: Option<usize>| column.and_then(|c| cells.get(c)).cloned().unwrap_or_default();
+
+                // Background species are listed under the species as
+                // "(also: Common Starling, House Sparrow)"
+                let species_cell = match species_column {
+                    Some(_) => cell(species_column),
+                    None => cells.iter().find(|c| c.contains("(also:")).cloned().unwrap_or_default(),
+                };
+                let background = species_cell
+                    .split_once("(also:")
+                    .map(|(_, rest)| rest.split(')').next().unwrap_or(""))
+                    .map(|list| {
+                        list.split(',')
+                            .map(|name| format_species_name(name.trim()))
+                            .filter(|name| !name.is_empty())
+                            .collect::<Vec<_>>()
+                            .join(";")
+                    })
+                    .unwrap_or_default();
+
+                page_downloads.push(DownloadLink {
+                    url: download_url,
+                    id,
+                    common_name: common_name.to_string(),
+                    scientific_name: scientific_name.to_string(),
+                    recordist: cell(recordist_column),
+                    location: cell(location_column),
+                    background,
+                    vocalization: cell(type_column).to_lowercase(),
+                    license,
+                });
             }
         }
         
@@ -251,7 +960,7 @@ fn extract_all_download_links(
                 let parts: Vec<&str> = current_page_url.split("pg=").collect();
                 if parts.len() == 2 {
                     let base = parts[0];
-                    let rest: Vec<&str> = parts[1].splitn(2, |c| c == '&').collect();
+                    let rest: Vec<&str> = parts[1].splitn(2, '&').collect();
                     let page_str = if rest.len() > 1 {
                         format!("pg={}&{}", page_num + 1, rest[1])
                     } else {
@@ -271,10 +980,37 @@ fn extract_all_download_links(
     Ok(download_info)
 }
 
+// Build a metadata entry from a metadata.csv row. Columns after the first
+// six are optional so catalogs written by older versions still load.
+fn metadata_from_record(record: &csv::StringRecord) -> Option<RecordingMetadata> {
+    if record.len() < 6 { // Basic validation
+        return None;
+    }
+
+    Some(RecordingMetadata {
+        filename: record[0].to_string(),
+        species: record[1].to_string(),
+        url: record[2].to_string(),
+        id: record[3].to_string(),
+        common_name: record[4].to_string(),
+        scientific_name: record[5].to_string(),
+        is_downloaded: record.get(6).and_then(|v| v.parse::<bool>().ok()).unwrap_or(false),
+        gain_db: record.get(7).and_then(|v| v.parse::<f64>().ok()),
+        recordist: record.get(8).unwrap_or("").to_string(),
+        location: record.get(9).unwrap_or("").to_string(),
+        split: record.get(10).unwrap_or("").to_string(),
+        background: record.get(11).unwrap_or("").to_string(),
+        vocalization: record.get(12).unwrap_or("").to_string(),
+        license: record.get(13).unwrap_or("").to_string(),
+    })
+}
+
 // Load existing metadata or create new metadata with newly found links
 fn load_or_create_metadata(
     metadata_path: &Path,
-    download_info: &[(String, String, String, String)]
+    download_info: &[DownloadLink],
+    output_format: OutputFormat,
+    taxonomy: Option<&Taxonomy>
 ) -> Result<Vec<RecordingMetadata>, Box<dyn std::error::Error>> {
     let mut metadata = Vec::new();
     let mut existing_ids = HashSet::new();
@@ -285,31 +1021,9 @@ fn load_or_create_metadata(
         
         for result in reader.records() {
             let record = result?;
-            if record.len() >= 6 { // Basic validation
-                let filename = record[0].to_string();
-                let species = record[1].to_string();
-                let url = record[2].to_string();
-                let id = record[3].to_string();
-                let common_name = record[4].to_string();
-                let scientific_name = record[5].to_string();
-                // Parse is_downloaded flag if it exists
-                let is_downloaded = if record.len() >= 7 {
-                    record[6].parse::<bool>().unwrap_or(false)
-                } else {
-                    false
-                };
-                
-                existing_ids.insert(id.clone());
-                
-                metadata.push(RecordingMetadata {
-                    id,
-                    url,
-                    common_name,
-                    scientific_name,
-                    filename,
-                    species,
-                    is_downloaded,
-                });
+            if let Some(meta) = metadata_from_record(&record) {
+                existing_ids.insert(meta.id.clone());
+                metadata.push(meta);
             }
         }
     }
@@ -318,34 +1032,58 @@ fn load_or_create_metadata(
     let mut species_counters = HashMap::new();
     for meta in &metadata {
         let species = &meta.species;
-        if let Some(number_str) = meta.filename.strip_prefix(&format!("{}_", species)) {
-            if let Some(number_str) = number_str.strip_suffix(".wav") {
-                if let Ok(number) = number_str.parse::<usize>() {
-                    let current_max = species_counters.entry(species.clone()).or_insert(0);
-                    if number > *current_max {
-                        *current_max = number;
-                    }
-                }
+        if let Some(number_str) = meta.filename.strip_prefix(&format!("{}_", species))
+            && let Some((number_str, _extension)) = number_str.split_once('.')
+            && let Ok(number) = number_str.parse::<usize>()
+        {
+            let current_max = species_counters.entry(species.clone()).or_insert(0);
+            if number > *current_max {
+                *current_max = number;
             }
         }
     }
     
     // Add new download links to metadata
-    for (url, id, common_name, scientific_name) in download_info {
-        // Skip if already exists in metadata
+    for DownloadLink { url, id, common_name, scientific_name, recordist, location, background, vocalization, license } in download_info {
+        // Skip if already exists in metadata, filling in details that
+        // catalogs from older versions did not record
         if existing_ids.contains(id) {
+            if let Some(meta) = metadata.iter_mut().find(|m| m.id == *id) {
+                if meta.recordist.is_empty() {
+                    meta.recordist = recordist.clone();
+                }
+                if meta.location.is_empty() {
+                    meta.location = location.clone();
+                }
+                if meta.background.is_empty() {
+                    meta.background = background.clone();
+                }
+                if meta.vocalization.is_empty() {
+                    meta.vocalization = vocalization.clone();
+                }
+                if meta.license.is_empty() {
+                    meta.license = license.clone();
+                }
+            }
             continue;
         }
         
-        // Format the species name
-        let species = format_species_name(common_name);
+        // Format the species name, or take the checklist's class key
+        let species = match taxonomy.map(|t| t.class_key(common_name, scientific_name)) {
+            Some(Some(key)) => key,
+            Some(None) => {
+                println!("Note: {} ({}) is not in the checklist", common_name, scientific_name);
+                format_species_name(common_name)
+            }
+            None => format_species_name(common_name),
+        };
         
         // Generate filename with next available number
         let counter = species_counters.entry(species.clone()).or_insert(0);
         *counter += 1;
         let next_number = *counter;
         
-        let filename = format!("{}_{}.wav", species, next_number);
+        let filename = format!("{}_{}.{}", species, next_number, output_format.extension());
         
         // Add to metadata
         metadata.push(RecordingMetadata {
@@ -356,12 +1094,24 @@ fn load_or_create_metadata(
             filename,
             species,
             is_downloaded: false,
+            gain_db: None,
+            recordist: recordist.clone(),
+            location: location.clone(),
+            split: String::new(),
+            background: background.clone(),
+            vocalization: vocalization.clone(),
+            license: license.clone(),
         });
     }
     
     Ok(metadata)
 }
 
+// Filename up to the first dot, shared by a recording in every format
+fn recording_stem(filename: &str) -> &str {
+    filename.split('.').next().unwrap_or(filename)
+}
+
 // Check which files already exist in the directory
 fn update_download_status(
     metadata: &[RecordingMetadata],
@@ -385,60 +1135,75 @@ fn update_download_status(
         let entry = entry?;
         let path = entry.path();
         
-        if let Some(ext) = path.extension() {
-            if ext == "wav" {
-                if let Some(filename) = path.file_name() {
-                    let filename_str = filename.to_string_lossy().to_string();
-                    
-                    // Check if this file is already in our metadata
-                    if existing_filenames.contains(&filename_str) {
-                        // File exists in metadata, mark as downloaded
-                        for meta in &mut updated_metadata {
-                            if meta.filename == filename_str {
-                                meta.is_downloaded = true;
-                                println!("Found existing file: {}", filename_str);
-                                break;
-                            }
-                        }
+        if let Some(ext) = path.extension()
+            && (ext == "wav" || ext == "flac")
+            && let Some(filename) = path.file_name()
+        {
+            let filename_str = filename.to_string_lossy().to_string();
+            let stem = recording_stem(&filename_str);
+            
+            // Check if this file is already in our metadata
+            if existing_filenames.contains(&filename_str) {
+                // File exists in metadata, mark as downloaded
+                for meta in &mut updated_metadata {
+                    if meta.filename == filename_str {
+                        meta.is_downloaded = true;
+                        println!("Found existing file: {}", filename_str);
+                        break;
+                    }
+                }
+            } else if let Some(meta) = updated_metadata.iter_mut().find(|m| recording_stem(&m.filename) == stem) {
+                // Same recording stored in the other output format, e.g. after
+                // converting a directory of WAVs to FLAC
+                if !dir.join(&meta.filename).exists() {
+                    println!("Found existing file: {} (was {})", filename_str, meta.filename);
+                    meta.filename = filename_str;
+                    meta.is_downloaded = true;
+                }
+            } else {
+                // File exists on disk but not in metadata - add it
+                println!("Found file not in metadata: {} - adding to metadata", filename_str);
+                
+                // Try to extract species from filename (format should be species_number.wav)
+                let species = if let Some(underscore_pos) = filename_str.rfind('_') {
+                    filename_str[0..underscore_pos].to_string()
+                } else {
+                    // Can't parse, use filename without extension as species
+                    if let Some(dot_pos) = filename_str.rfind('.') {
+                        filename_str[0..dot_pos].to_string()
                     } else {
-                        // File exists on disk but not in metadata - add it
-                        println!("Found file not in metadata: {} - adding to metadata", filename_str);
-                        
-                        // Try to extract species from filename (format should be species_number.wav)
-                        let species = if let Some(underscore_pos) = filename_str.rfind('_') {
-                            filename_str[0..underscore_pos].to_string()
-                        } else {
-                            // Can't parse, use filename without extension as species
-                            if let Some(dot_pos) = filename_str.rfind('.') {
-                                filename_str[0..dot_pos].to_string()
-                            } else {
-                                filename_str.clone()
-                            }
-                        };
-                        
-                        // Generate placeholder data for the new entry
-                        let common_name = species.replace('_', " ");
-                        
-                        // Create unique ID that won't conflict with existing IDs
-                        let mut unique_id = format!("local_{}", filename_str.replace('.', "_"));
-                        let mut counter = 1;
-                        while existing_ids.contains(&unique_id) {
-                            unique_id = format!("local_{}_{}", filename_str.replace('.', "_"), counter);
-                            counter += 1;
-                        }
-                        
-                        // Create a new metadata entry for this file
-                        updated_metadata.push(RecordingMetadata {
-                            id: unique_id,
-                            url: "file://local".to_string(), // Placeholder URL
-                            common_name: common_name.clone(),
-                            scientific_name: "Unknown".to_string(),
-                            filename: filename_str,
-                            species,
-                            is_downloaded: true, // Mark as downloaded since it exists
-                        });
+                        filename_str.clone()
                     }
+                };
+                
+                // Generate placeholder data for the new entry
+                let common_name = species.replace('_', " ");
+                
+                // Create unique ID that won't conflict with existing IDs
+                let mut unique_id = format!("local_{}", filename_str.replace('.', "_"));
+                let mut counter = 1;
+                while existing_ids.contains(&unique_id) {
+                    unique_id = format!("local_{}_{}", filename_str.replace('.', "_"), counter);
+                    counter += 1;
                 }
+                
+                // Create a new metadata entry for this file
+                updated_metadata.push(RecordingMetadata {
+                    id: unique_id,
+                    url: "file://local".to_string(), // Placeholder URL
+                    common_name: common_name.clone(),
+                    scientific_name: "Unknown".to_string(),
+                    filename: filename_str,
+                    species,
+                    is_downloaded: true, // Mark as downloaded since it exists
+                    gain_db: None,
+                    recordist: String::new(),
+                    location: String::new(),
+                    split: String::new(),
+                    background: String::new(),
+                    vocalization: String::new(),
+                    license: String::new(),
+                });
             }
         }
     }
@@ -465,13 +1230,14 @@ fn write_metadata_csv(
     let mut writer = csv::Writer::from_path(metadata_path)?;
     
     // Write header
-    writer.write_record(&[
-        "filename", "species", "original_url", "id", "common_name", "scientific_name", "is_downloaded"
+    writer.write_record([
+        "filename", "species", "original_url", "id", "common_name", "scientific_name", "is_downloaded", "gain_db",
+        "recordist", "location", "split", "background", "vocalization", "license"
     ])?;
     
     // Write data
     for meta in metadata {
-        writer.write_record(&[
+        writer.write_record([
             &meta.filename,
             &meta.species,
             &meta.url,
@@ -479,6 +1245,13 @@ fn write_metadata_csv(
             &meta.common_name,
             &meta.scientific_name,
             &meta.is_downloaded.to_string(),
+            &meta.gain_db.map(|g| format!("{:.2}", g)).unwrap_or_default(),
+            &meta.recordist,
+            &meta.location,
+            &meta.split,
+            &meta.background,
+            &meta.vocalization,
+            &meta.license,
         ])?;
     }
     
@@ -492,10 +1265,20 @@ fn download_missing_files(
     client: &Client,
     metadata: &[RecordingMetadata],
     output_dir: &str,
-    download_delay_ms: u64
+    download_delay_ms: u64,
+    profile: &Profile,
+    output_format: OutputFormat,
+    quotas: &QuotaConfig
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Count how many files need to be downloaded
-    let to_download = metadata.iter().filter(|m| !m.is_downloaded).count();
+    // Decide which files to download, and in which order, leaving out
+    // recordings whose license the catalog excludes
+    let licenses = LicenseFilter::load(output_dir)?;
+    let unlicensed = metadata.iter().filter(|m| !m.is_downloaded && !licenses.allows_recording(m)).count();
+    if unlicensed > 0 {
+        println!("Skipping {} recordings with an excluded license", unlicensed);
+    }
+    let plan = plan_downloads(metadata, quotas, |m| licenses.allows_recording(m));
+    let to_download = plan.len();
     println!("Need to download {} files", to_download);
     
     if to_download == 0 {
@@ -512,15 +1295,20 @@ fn download_missing_files(
     let downloaded_ids = Arc::new(Mutex::new(Vec::new()));
     
     // Start downloads for non-downloaded files
-    for meta in metadata.iter().filter(|m| !m.is_downloaded) {
+    for meta in plan.iter().map(|&i| &metadata[i]) {
         let client = client.clone();
         let url = meta.url.clone();
         let id = meta.id.clone();
-        let filename = meta.filename.clone();
+        // Entries created for another output format are stored in this one
+        let filename = Path::new(&meta.filename)
+            .with_extension(output_format.extension())
+            .to_string_lossy()
+            .to_string();
         let output_dir = output_dir.to_string();
         let download_delay = Duration::from_millis(download_delay_ms);
         let last_request_time = Arc::clone(&last_request_time);
         let downloaded_ids = Arc::clone(&downloaded_ids);
+        let profile = profile.clone();
         
         pool.execute(move || {
             // Rate limiting within thread
@@ -540,10 +1328,9 @@ fn download_missing_files(
             }
             
             // Download file
-            let mp3_path = Path::new(&output_dir).join(filename.replace(".wav", ".mp3"));
-            let wav_path = Path::new(&output_dir).join(&filename);
+            let output_path = Path::new(&output_dir).join(&filename);
             
-            println!("Downloading: {} -> {}", url, mp3_path.display());
+            println!("Downloading: {} -> {}", url, output_path.display());
             
             // Download with retries
             let max_retries = 3;
@@ -556,27 +1343,39 @@ fn download_missing_files(
                         if !response.status().is_success() {
                             println!("Download failed with status: {}", response.status());
                         } else {
+                            let content_type = response.headers()
+                                .get(CONTENT_TYPE)
+                                .and_then(|value| value.to_str().ok())
+                                .map(|value| value.to_string());
+                            
                             match response.bytes() {
                                 Ok(bytes) => {
-                                    // Save to MP3 file
-                                    match File::create(&mp3_path) {
+                                    // Detect the real format from the content, falling back
+                                    // to the server's content type and then to MP3
+                                    let format = InputFormat::from_magic(&bytes)
+                                        .or_else(|| content_type.as_deref().and_then(InputFormat::from_content_type))
+                                        .unwrap_or(InputFormat::Mp3);
+                                    let source_path = output_path.with_extension(format.extension());
+                                    
+                                    // Save the original file
+                                    match File::create(&source_path) {
                                         Ok(mut file) => {
                                             if let Err(e) = file.write_all(&bytes) {
                                                 println!("Error writing file: {}", e);
                                             } else {
-                                                // Convert to WAV
-                                                match convert_using_ffmpeg(&mp3_path, &wav_path) {
-                                                    Ok(_) => {
+                                                // Convert to the output format, running the profile on the decoded audio
+                                                match convert_audio(&source_path, &output_path, &profile) {
+                                                    Ok(gain_db) => {
                                                         println!("Successfully downloaded and converted: {}", filename);
                                                         
                                                         // Add to successful downloads
                                                         let mut ids = downloaded_ids.lock().unwrap();
-                                                        ids.push(id.clone());
+                                                        ids.push((id.clone(), gain_db, filename.clone()));
                                                         
                                                         break;
                                                     },
                                                     Err(e) => {
-                                                        println!("Error converting to WAV: {}", e);
+                                                        println!("Error converting {}: {}", source_path.display(), e);
                                                     }
                                                 }
                                             }
@@ -616,10 +1415,12 @@ fn download_missing_files(
     let successful_ids = downloaded_ids.lock().unwrap();
     if !successful_ids.is_empty() {
         let mut updated_metadata = metadata.to_vec();
-        for id in successful_ids.iter() {
+        for (id, gain_db, filename) in successful_ids.iter() {
             for meta in &mut updated_metadata {
                 if &meta.id == id {
                     meta.is_downloaded = true;
+                    meta.gain_db = *gain_db;
+                    meta.filename = filename.clone();
                     break;
                 }
             }
@@ -633,26 +1434,70 @@ fn download_missing_files(
     Ok(())
 }
 
-// Convert common name to normalized species name
+// Convert common name to normalized species name, in the [a-z_] alphabet
+// clip filenames need so clips and recordings share class names
 fn format_species_name(name: &str) -> String {
-    name.to_lowercase().replace(' ', "_")
+    clips::class_key(name).unwrap_or_else(|| name.to_lowercase().replace(' ', "_"))
 }
 
-// Convert MP3 to WAV using ffmpeg
-fn convert_using_ffmpeg(mp3_path: &Path, wav_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+// Decode any supported format natively and store it as 44.1 kHz 16-bit WAV
+// or FLAC (chosen by the output extension). Files the built-in decoders
+// cannot read are handed to ffmpeg instead.
+fn convert_audio(
+    input_path: &Path,
+    output_path: &Path,
+    profile: &Profile
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    println!("Converting: {} → {}", input_path.display(), output_path.display());
+    
+    // The profile runs on the decoded audio, so each file is written once
+    let process = |audio: AudioBuffer| -> Result<Option<f64>, Box<dyn std::error::Error>> {
+        let mut audio = resample(&audio, TARGET_SAMPLE_RATE);
+        let gain_db = apply_profile(&mut audio, profile);
+        write_audio(output_path, &audio)?;
+        if let Some(gain_db) = gain_db {
+            println!("Normalized {} ({:+.2} dB)", output_path.display(), gain_db);
+        }
+        Ok(gain_db)
+    };
+    let native = read_audio(input_path).and_then(process);
+    
+    match native {
+        Ok(gain_db) => Ok(gain_db),
+        // ffmpeg cannot overwrite its own input
+        Err(e) if input_path != output_path => {
+            println!("Built-in decoder failed ({}), falling back to ffmpeg", e);
+            convert_using_ffmpeg(input_path, output_path)?;
+            if !profile.changes_audio() {
+                return Ok(None);
+            }
+            read_audio(output_path).and_then(process).map_err(|e| e.to_string().into())
+        }
+        Err(e) => Err(e.to_string().into()),
+    }
+}
+
+// Convert an audio file to WAV or FLAC using ffmpeg
+fn convert_using_ffmpeg(input_path: &Path, output_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     use std::process::Command;
     
-    println!("Converting with ffmpeg: {} → {}", mp3_path.display(), wav_path.display());
+    println!("Converting with ffmpeg: {} → {}", input_path.display(), output_path.display());
+    
+    // 16-bit samples either way
+    let codec_args: [&str; 4] = if output_path.extension().is_some_and(|ext| ext == "flac") {
+        ["-acodec", "flac", "-sample_fmt", "s16"]
+    } else {
+        ["-acodec", "pcm_s16le", "-sample_fmt", "s16"]
+    };
     
     let output = Command::new("C:\\tools\\ffmpeg.exe")
         .arg("-y") // Overwrite existing files
         .arg("-i")
-        .arg(mp3_path)
-        .arg("-acodec")
-        .arg("pcm_s16le") // 16-bit PCM encoding for WAV
+        .arg(input_path)
+        .args(codec_args)
         .arg("-ar")
-        .arg("44100") // Standard sample rate
-        .arg(wav_path)
+        .arg(TARGET_SAMPLE_RATE.to_string()) // Standard sample rate
+        .arg(output_path)
         .output()?;
     
     if output.status.success() {
@@ -665,29 +1510,60 @@ fn convert_using_ffmpeg(mp3_path: &Path, wav_path: &Path) -> Result<(), Box<dyn
 }
 
 // Batch convert directory function
-fn batch_convert_directory(dir_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn batch_convert_directory(
+    dir_path: &str,
+    profile: &Profile,
+    output_format: OutputFormat
+) -> Result<(), Box<dyn std::error::Error>> {
     let dir = Path::new(dir_path);
     if !dir.is_dir() {
         return Err(format!("{} is not a directory", dir_path).into());
     }
     
+    // Converted files and the gain applied by normalization, keyed by recording stem
+    let mut converted = HashMap::new();
+    
     for entry in std::fs::read_dir(dir)? {
         let entry = entry?;
         let path = entry.path();
         
-        if path.extension().map_or(false, |ext| ext == "mp3") {
-            let wav_path = path.with_extension("wav");
-            
-            if !wav_path.exists() {
-                println!("Converting: {}", path.display());
-                match convert_using_ffmpeg(&path, &wav_path) {
-                    Ok(_) => println!("Conversion successful: {}", wav_path.display()),
-                    Err(e) => println!("Error converting {}: {}", path.display(), e),
+        // Any supported format other than the output format is a source
+        let is_source = InputFormat::from_extension(&path)
+            .is_some_and(|format| format.extension() != output_format.extension());
+        if !is_source {
+            continue;
+        }
+        
+        let output_path = path.with_extension(output_format.extension());
+        if output_path.exists() {
+            println!("Skipping: {} ({} already exists)", path.display(), output_path.display());
+            continue;
+        }
+        
+        match convert_audio(&path, &output_path, profile) {
+            Ok(gain_db) => {
+                println!("Conversion successful: {}", output_path.display());
+                let filename = output_path.file_name().unwrap().to_string_lossy().to_string();
+                converted.insert(recording_stem(&filename).to_string(), (filename, gain_db));
+            }
+            Err(e) => println!("Error converting {}: {}", path.display(), e),
+        }
+    }
+    
+    // Point the catalog at the converted files and record applied gains
+    let metadata_path = dir.join("metadata.csv");
+    if !converted.is_empty() && metadata_path.exists() {
+        let mut metadata = load_existing_metadata(&metadata_path)?;
+        for meta in &mut metadata {
+            if let Some((filename, gain_db)) = converted.get(recording_stem(&meta.filename)) {
+                meta.filename = filename.clone();
+                meta.is_downloaded = true;
+                if gain_db.is_some() {
+                    meta.gain_db = *gain_db;
                 }
-            } else {
-                println!("Skipping: {} (WAV already exists)", path.display());
             }
         }
+        write_metadata_csv(&metadata_path, &metadata)?;
     }
     
     Ok(())
@@ -704,29 +1580,8 @@ fn load_existing_metadata(
         
         for result in reader.records() {
             let record = result?;
-            if record.len() >= 6 { // Basic validation
-                let filename = record[0].to_string();
-                let species = record[1].to_string();
-                let url = record[2].to_string();
-                let id = record[3].to_string();
-                let common_name = record[4].to_string();
-                let scientific_name = record[5].to_string();
-                // Parse is_downloaded flag if it exists
-                let is_downloaded = if record.len() >= 7 {
-                    record[6].parse::<bool>().unwrap_or(false)
-                } else {
-                    false
-                };
-                
-                metadata.push(RecordingMetadata {
-                    id,
-                    url,
-                    common_name,
-                    scientific_name,
-                    filename,
-                    species,
-                    is_downloaded,
-                });
+            if let Some(meta) = metadata_from_record(&record) {
+                metadata.push(meta);
             }
         }
     } else {
@@ -0,0 +1,238 @@
+use crate::clips::class_key;
+use crate::RecordingMetadata;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// One line of class_mapping.csv. Empty conditions match every recording;
+// a condition may list alternatives separated by ';'.
+#[derive(Debug, Clone)]
+struct MappingRule {
+    species: String,
+    class: String,
+    vocalization: Vec<String>, // Sound types, matched as whole words ("call" matches "flight call")
+    region: Vec<String>,       // Matched anywhere in the location, ignoring case
+    line: usize,
+}
+
+// User-maintained species -> class mapping of a catalog, used to merge
+// look-alike species or split one by call type or region. It is applied
+// when cutting clips and building class maps, manifests and datasets;
+// metadata.csv keeps the species.
+#[derive(Debug, Clone, Default)]
+pub struct ClassMapping {
+    rules: Vec<MappingRule>,
+}
+
+pub fn mapping_path(output_dir: &str) -> PathBuf {
+    Path::new(output_dir).join("class_mapping.csv")
+}
+
+// Species keys of catalogs scraped before keys were limited to [a-z_]
+// ("black-headed_gull") name the same class as current ones
+fn species_key(species: &str) -> String {
+    class_key(species).unwrap_or_else(|| species.to_string())
+}
+
+fn conditions(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or("")
+        .split(';')
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+impl ClassMapping {
+    // Read <dir>/class_mapping.csv (columns species, class and optionally
+    // vocalization and region), or an empty mapping if there is none
+    pub fn load(output_dir: &str) -> Result<ClassMapping, Box<dyn std::error::Error>> {
+        let path = mapping_path(output_dir);
+        if !path.exists() {
+            return Ok(ClassMapping::default());
+        }
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(&path)?;
+        let headers: Vec<String> = reader.headers()?.iter().map(|h| h.trim().to_lowercase()).collect();
+        let column = |name: &str| headers.iter().position(|h| h == name);
+        let (Some(species_column), Some(class_column)) = (column("species"), column("class")) else {
+            return Err(format!("{} needs species and class columns", path.display()).into());
+        };
+        let (vocalization_column, region_column) = (column("vocalization"), column("region"));
+
+        let mut rules = Vec::new();
+        for (i, result) in reader.records().enumerate() {
+            let record = result?;
+            let species = crate::format_species_name(record.get(species_column).unwrap_or("").trim());
+            let raw_class = record.get(class_column).unwrap_or("").trim();
+            if species.is_empty() || species.starts_with('#') {
+                continue;
+            }
+            if raw_class.is_empty() {
+                return Err(format!("{} line {}: {} has no class", path.display(), i + 2, species).into());
+            }
+            // Classes name clip files, so only letters and word separators
+            // are allowed; "warbler-1" would lose its digit and merge
+            if let Some(c) = raw_class.chars().find(|&c| class_key(&c.to_string()).is_none() && !" -_'’.".contains(c)) {
+                return Err(format!(
+                    "{} line {}: class \"{}\" contains '{}'; classes may only hold letters, spaces, '-', '_', apostrophes and periods",
+                    path.display(),
+                    i + 2,
+                    raw_class,
+                    c
+                )
+                .into());
+            }
+            let Some(class) = class_key(raw_class) else {
+                return Err(format!("{} line {}: class \"{}\" has no letters", path.display(), i + 2, raw_class).into());
+            };
+            rules.push(MappingRule {
+                species,
+                class,
+                vocalization: conditions(vocalization_column.and_then(|c| record.get(c))),
+                region: conditions(region_column.and_then(|c| record.get(c))),
+                line: i + 2,
+            });
+        }
+        Ok(ClassMapping { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Index of the first rule for the species whose conditions hold
+    fn rule(&self, species: &str, vocalization: &str, location: &str) -> Option<usize> {
+        let species = species_key(species);
+        let vocalization = vocalization.to_lowercase();
+        let location = location.to_lowercase();
+        let sound_types: Vec<&str> = vocalization.split(',').map(str::trim).collect();
+        self.rules.iter().position(|rule| {
+            rule.species == species
+                && (rule.vocalization.is_empty()
+                    || rule.vocalization.iter().any(|wanted| {
+                        sound_types.iter().any(|t| t == wanted || t.split_whitespace().any(|word| word == wanted))
+                    }))
+                && (rule.region.is_empty() || rule.region.iter().any(|wanted| location.contains(wanted.as_str())))
+        })
+    }
+
+    // Class of a species heard in a recording; species no rule matches
+    // are their own class
+    pub fn class_of(&self, species: &str, meta: &RecordingMetadata) -> String {
+        self.rule(species, &meta.vocalization, &meta.location)
+            .map_or_else(|| species_key(species), |i| self.rules[i].class.clone())
+    }
+
+    // Class of the recording's own species
+    pub fn recording_class(&self, meta: &RecordingMetadata) -> String {
+        self.class_of(&meta.species, meta)
+    }
+}
+
+// Print the class each species of the catalog maps to, with recording
+// counts, and point out rules that match no recording
+pub fn check_mapping(output_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mapping = ClassMapping::load(output_dir)?;
+    if mapping.is_empty() {
+        println!("No mapping rules in {}; every species is its own class", mapping_path(output_dir).display());
+        return Ok(());
+    }
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+
+    let mut classes: BTreeMap<(String, String), usize> = BTreeMap::new();
+    let mut used = vec![false; mapping.rules.len()];
+    for meta in metadata.iter().filter(|m| m.is_downloaded) {
+        if let Some(i) = mapping.rule(&meta.species, &meta.vocalization, &meta.location) {
+            used[i] = true;
+        }
+        *classes.entry((mapping.recording_class(meta), meta.species.clone())).or_insert(0) += 1;
+    }
+
+    println!("{:<32} {:<32} {:>10}", "class", "species", "recordings");
+    for ((class, species), count) in &classes {
+        println!("{:<32} {:<32} {:>10}", class, species, count);
+    }
+    for (rule, used) in mapping.rules.iter().zip(used) {
+        if !used {
+            println!("Note: line {} ({} -> {}) matches no downloaded recording", rule.line, rule.species, rule.class);
+        }
+    }
+    let split_by_type = |species: &str| mapping.rules.iter().any(|r| r.species == species && !r.vocalization.is_empty());
+    if metadata.iter().any(|m| m.is_downloaded && m.vocalization.is_empty() && split_by_type(&m.species)) {
+        println!("Some recordings have no vocalization type; re-run the scraper to fill them in");
+    }
+    println!("Clips cut before the mapping changed keep their old class; cut them again to apply it");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, csv: &str) -> Result<ClassMapping, String> {
+        let dir = std::env::temp_dir().join(format!("mapping_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("class_mapping.csv"), csv).unwrap();
+        let mapping = ClassMapping::load(&dir.to_string_lossy()).map_err(|e| e.to_string());
+        std::fs::remove_dir_all(&dir).unwrap();
+        mapping
+    }
+
+    fn recording(species: &str, vocalization: &str, location: &str) -> RecordingMetadata {
+        RecordingMetadata {
+            species: species.to_string(),
+            vocalization: vocalization.to_string(),
+            location: location.to_string(),
+            ..Default::default()
+        }
+    }
+
+    const RULES: &str = "\
+species,class,vocalization,region
+Willow Warbler,willow_warbler_song,song,
+willow_warbler,warbler call,call;alarm call,
+chiffchaff,iberian chiffchaff,,Spain; Portugal
+common_gull,gull sp.,,
+black_headed_gull,gull sp.,,
+";
+
+    #[test]
+    fn vocalization_conditions_match_whole_words() {
+        let mapping = load("vocalization", RULES).unwrap();
+        let class = |vocalization: &str| mapping.recording_class(&recording("willow_warbler", vocalization, ""));
+        assert_eq!(class("song"), "willow_warbler_song");
+        assert_eq!(class("Call, song"), "willow_warbler_song");
+        assert_eq!(class("flight call"), "warbler_call");
+        assert_eq!(class("alarm call"), "warbler_call");
+        assert_eq!(class("calls"), "willow_warbler");
+        assert_eq!(class(""), "willow_warbler");
+    }
+
+    #[test]
+    fn region_conditions_match_part_of_the_location() {
+        let mapping = load("region", RULES).unwrap();
+        let class = |location: &str| mapping.recording_class(&recording("chiffchaff", "song", location));
+        assert_eq!(class("Doñana, Huelva, SPAIN"), "iberian_chiffchaff");
+        assert_eq!(class("Serra da Estrela, Portugal"), "iberian_chiffchaff");
+        assert_eq!(class("Berlin, Germany"), "chiffchaff");
+    }
+
+    #[test]
+    fn merged_species_share_one_class_under_any_key_spelling() {
+        let mapping = load("merged", RULES).unwrap();
+        assert_eq!(mapping.recording_class(&recording("common_gull", "", "")), "gull_sp");
+        // Keys from before they were limited to [a-z_]
+        assert_eq!(mapping.recording_class(&recording("black-headed_gull", "", "")), "gull_sp");
+        assert_eq!(mapping.class_of("robin", &recording("common_gull", "", "")), "robin");
+    }
+
+    #[test]
+    fn classes_outside_the_clip_alphabet_are_rejected_with_their_line() {
+        let error = load("digits", "species,class\nwren,wren\nwillow_warbler,warbler-1\n").unwrap_err();
+        assert!(error.contains("line 3: class \"warbler-1\" contains '1'"), "{}", error);
+        let error = load("empty", "species,class\nwren,...\n").unwrap_err();
+        assert!(error.contains("line 2: class \"...\" has no letters"), "{}", error);
+        let error = load("missing", "species,class\nwren,\n").unwrap_err();
+        assert!(error.contains("line 2: wren has no class"), "{}", error);
+        assert!(load("columns", "species,label\nwren,wren\n").unwrap_err().contains("needs species and class columns"));
+    }
+}
@@ -0,0 +1,531 @@
+use crate::audio::{read_audio, AudioBuffer, InputFormat};
+use crate::classmap::load_class_map;
+use crate::features::{FeatureKind, ENHANCED_FEATURE_DIM, SIMPLE_MFCC_COUNT};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+// Inference for Sequential models saved by TensorFlow.js in the layers
+// format (model.json + weights.bin), as trained by the PWA. Computation is
+// in f32 like TF.js, layer by layer on a single feature vector.
+
+#[derive(Debug, Clone, Copy)]
+enum Activation {
+    Linear,
+    Relu,
+    Relu6,
+    Elu,
+    Selu,
+    Sigmoid,
+    HardSigmoid,
+    Tanh,
+    Softplus,
+    Softsign,
+    Softmax,
+}
+
+impl std::str::FromStr for Activation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Activation::Linear),
+            "relu" => Ok(Activation::Relu),
+            "relu6" => Ok(Activation::Relu6),
+            "elu" => Ok(Activation::Elu),
+            "selu" => Ok(Activation::Selu),
+            "sigmoid" => Ok(Activation::Sigmoid),
+            "hardSigmoid" | "hard_sigmoid" => Ok(Activation::HardSigmoid),
+            "tanh" => Ok(Activation::Tanh),
+            "softplus" => Ok(Activation::Softplus),
+            "softsign" => Ok(Activation::Softsign),
+            "softmax" => Ok(Activation::Softmax),
+            _ => Err(format!("Unsupported activation: {}", s)),
+        }
+    }
+}
+
+impl Activation {
+    fn apply(self, values: &mut [f32]) {
+        match self {
+            Activation::Linear => {}
+            Activation::Softmax => {
+                let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let mut sum = 0.0;
+                for v in values.iter_mut() {
+                    *v = (*v - max).exp();
+                    sum += *v;
+                }
+                for v in values.iter_mut() {
+                    *v /= sum;
+                }
+            }
+            _ => {
+                for v in values.iter_mut() {
+                    *v = match self {
+                        Activation::Relu => v.max(0.0),
+                        Activation::Relu6 => v.clamp(0.0, 6.0),
+                        Activation::Elu => if *v > 0.0 { *v } else { v.exp() - 1.0 },
+                        Activation::Selu => {
+                            const ALPHA: f32 = 1.673_263_2;
+                            const SCALE: f32 = 1.050_701;
+                            SCALE * if *v > 0.0 { *v } else { ALPHA * (v.exp() - 1.0) }
+                        }
+                        Activation::Sigmoid => 1.0 / (1.0 + (-*v).exp()),
+                        Activation::HardSigmoid => (0.2 * *v + 0.5).clamp(0.0, 1.0),
+                        Activation::Tanh => v.tanh(),
+                        Activation::Softplus => v.exp().ln_1p(),
+                        Activation::Softsign => *v / (1.0 + v.abs()),
+                        Activation::Linear | Activation::Softmax => unreachable!(),
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Layer {
+    Dense {
+        kernel: Vec<f32>, // inputs x units, row-major
+        bias: Option<Vec<f32>>,
+        units: usize,
+        activation: Activation,
+    },
+    Activation(Activation),
+    // Inference-time batch normalization folded into a scale and offset
+    BatchNormalization { scale: Vec<f32>, offset: Vec<f32> },
+    // LayerNormalization: standardize each vector, then scale and shift
+    Standardize { gamma: Option<Vec<f32>>, beta: Option<Vec<f32>>, epsilon: f32 },
+    Identity, // Dropout, Flatten and the like, which do nothing at inference
+}
+
+impl Layer {
+    fn forward(&self, input: Vec<f32>) -> Result<Vec<f32>, String> {
+        match self {
+            Layer::Dense { kernel, bias, units, activation } => {
+                if kernel.len() != input.len() * units {
+                    return Err(format!("Dense layer expects {} inputs, got {}", kernel.len() / units, input.len()));
+                }
+                let mut output = bias.clone().unwrap_or_else(|| vec![0.0; *units]);
+                for (x, row) in input.iter().zip(kernel.chunks_exact(*units)) {
+                    for (out, w) in output.iter_mut().zip(row) {
+                        *out += x * w;
+                    }
+                }
+                activation.apply(&mut output);
+                Ok(output)
+            }
+            Layer::Activation(activation) => {
+                let mut output = input;
+                activation.apply(&mut output);
+                Ok(output)
+            }
+            Layer::BatchNormalization { scale, offset } => {
+                if scale.len() != input.len() {
+                    return Err(format!("BatchNormalization expects {} inputs, got {}", scale.len(), input.len()));
+                }
+                Ok(input.iter().zip(scale).zip(offset).map(|((x, s), o)| x * s + o).collect())
+            }
+            Layer::Standardize { gamma, beta, epsilon } => {
+                if let Some(size) = gamma.as_ref().or(beta.as_ref()).map(Vec::len)
+                    && size != input.len()
+                {
+                    return Err(format!("LayerNormalization expects {} inputs, got {}", size, input.len()));
+                }
+                let n = input.len() as f32;
+                let mean = input.iter().sum::<f32>() / n;
+                let variance = input.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n;
+                let inv = 1.0 / (variance + epsilon).sqrt();
+                Ok(input
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| {
+                        let normalized = (x - mean) * inv;
+                        normalized * gamma.as_ref().map_or(1.0, |g| g[i]) + beta.as_ref().map_or(0.0, |b| b[i])
+                    })
+                    .collect())
+            }
+            Layer::Identity => Ok(input),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Model {
+    layers: Vec<Layer>,
+    pub input_dim: usize,
+    pub output_dim: usize,
+}
+
+// Read every weight named in the manifest from the binary files next to
+// model.json. Weights are stored back to back in manifest order.
+fn load_weights(model_dir: &Path, manifest: &Value) -> Result<HashMap<String, Vec<f32>>, Box<dyn std::error::Error>> {
+    let mut weights = HashMap::new();
+    for group in manifest.as_array().ok_or("weightsManifest is not an array")? {
+        let mut bytes = Vec::new();
+        for path in group["paths"].as_array().ok_or("weight group without paths")? {
+            let path = path.as_str().ok_or("weight path is not a string")?;
+            bytes.extend(std::fs::read(model_dir.join(path))?);
+        }
+
+        let mut offset = 0;
+        for spec in group["weights"].as_array().ok_or("weight group without weights")? {
+            let name = spec["name"].as_str().ok_or("weight without a name")?;
+            if spec["dtype"].as_str().unwrap_or("float32") != "float32" || spec.get("quantization").is_some() {
+                return Err(format!("Unsupported weight type for {}: only unquantized float32 is supported", name).into());
+            }
+            let count: usize = spec["shape"]
+                .as_array()
+                .ok_or("weight without a shape")?
+                .iter()
+                .map(|d| d.as_u64().unwrap_or(0) as usize)
+                .product();
+            let end = offset + count * 4;
+            if end > bytes.len() {
+                return Err(format!("Weight file too short for {}", name).into());
+            }
+            let values = bytes[offset..end]
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+            weights.insert(name.to_string(), values);
+            offset = end;
+        }
+    }
+    Ok(weights)
+}
+
+// Every per-feature weight of a normalization layer must cover `expected`
+// values, or indexing them at inference would go out of bounds
+fn check_lengths(layer: &str, expected: usize, weights: &[Option<&Vec<f32>>]) -> Result<(), String> {
+    match weights.iter().flatten().find(|w| w.len() != expected) {
+        Some(w) => Err(format!("{} has a weight of {} values, expected {}", layer, w.len(), expected)),
+        None => Ok(()),
+    }
+}
+
+impl Model {
+    // Load model.json and its weight files
+    pub fn load(path: &Path) -> Result<Model, Box<dyn std::error::Error>> {
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let topology = &json["modelTopology"];
+        // Keras-converted models nest the topology one level deeper
+        let topology = if topology.get("model_config").is_some() { &topology["model_config"] } else { topology };
+        if topology["class_name"].as_str() != Some("Sequential") {
+            return Err(format!("Unsupported model class: {} (only Sequential is supported)", topology["class_name"]).into());
+        }
+        let layer_configs = match &topology["config"] {
+            Value::Array(layers) => layers,
+            config => config["layers"].as_array().ok_or("model without layers")?,
+        };
+
+        let model_dir = path.parent().unwrap_or(Path::new("."));
+        let mut weights = load_weights(model_dir, &json["weightsManifest"])?;
+        let mut take = |layer: &str, weight: &str| weights.remove(&format!("{}/{}", layer, weight));
+
+        let mut layers = Vec::new();
+        let mut input_dim = None;
+        let mut width = None; // Output width of the layers so far
+        for layer in layer_configs {
+            let class_name = layer["class_name"].as_str().unwrap_or_default();
+            let config = &layer["config"];
+            let name = config["name"].as_str().unwrap_or_default();
+
+            let shape = config.get("batch_input_shape").or_else(|| config.get("batchInputShape"));
+            if input_dim.is_none()
+                && let Some(shape) = shape.and_then(Value::as_array)
+            {
+                input_dim = shape.last().and_then(Value::as_u64).map(|d| d as usize);
+                width = input_dim;
+            }
+
+            let activation = |key: &str| -> Result<Activation, String> {
+                config[key].as_str().unwrap_or("linear").parse()
+            };
+            let parsed = match class_name {
+                "Dense" => {
+                    let units = config["units"].as_u64().ok_or("Dense layer without units")? as usize;
+                    let kernel = take(name, "kernel").ok_or_else(|| format!("Missing kernel for {}", name))?;
+                    if !kernel.len().is_multiple_of(units) {
+                        return Err(format!("Kernel of {} does not match its {} units", name, units).into());
+                    }
+                    if input_dim.is_none() {
+                        input_dim = Some(kernel.len() / units);
+                    }
+                    let bias = if config["use_bias"].as_bool().unwrap_or(true) { take(name, "bias") } else { None };
+                    width = Some(units);
+                    Layer::Dense { kernel, bias, units, activation: activation("activation")? }
+                }
+                "Activation" => Layer::Activation(activation("activation")?),
+                "ReLU" => Layer::Activation(Activation::Relu),
+                "Softmax" => Layer::Activation(Activation::Softmax),
+                "BatchNormalization" => {
+                    let epsilon = config["epsilon"].as_f64().unwrap_or(1e-3) as f32;
+                    let mean = take(name, "moving_mean").ok_or_else(|| format!("Missing moving_mean for {}", name))?;
+                    let variance =
+                        take(name, "moving_variance").ok_or_else(|| format!("Missing moving_variance for {}", name))?;
+                    let gamma = take(name, "gamma");
+                    let beta = take(name, "beta");
+                    check_lengths(name, mean.len(), &[Some(&variance), gamma.as_ref(), beta.as_ref()])?;
+                    if let Some(width) = width
+                        && width != mean.len()
+                    {
+                        return Err(format!("{} normalizes {} values but its input has {}", name, mean.len(), width).into());
+                    }
+                    let scale: Vec<f32> = (0..mean.len())
+                        .map(|i| gamma.as_ref().map_or(1.0, |g| g[i]) / (variance[i] + epsilon).sqrt())
+                        .collect();
+                    let offset = (0..mean.len())
+                        .map(|i| beta.as_ref().map_or(0.0, |b| b[i]) - mean[i] * scale[i])
+                        .collect();
+                    width = Some(mean.len());
+                    Layer::BatchNormalization { scale, offset }
+                }
+                "LayerNormalization" => {
+                    let gamma = take(name, "gamma");
+                    let beta = take(name, "beta");
+                    // Without a known input width, the weights decide it
+                    let size = width.or(gamma.as_ref().or(beta.as_ref()).map(Vec::len));
+                    if let Some(size) = size {
+                        check_lengths(name, size, &[gamma.as_ref(), beta.as_ref()])?;
+                        width = Some(size);
+                    }
+                    Layer::Standardize { gamma, beta, epsilon: config["epsilon"].as_f64().unwrap_or(1e-3) as f32 }
+                }
+                "Dropout" | "AlphaDropout" | "GaussianDropout" | "GaussianNoise" | "Flatten" | "InputLayer" => {
+                    Layer::Identity
+                }
+                _ => return Err(format!("Unsupported layer type: {} ({})", class_name, name).into()),
+            };
+            layers.push(parsed);
+        }
+
+        Ok(Model {
+            layers,
+            input_dim: input_dim.ok_or("could not determine the model's input size")?,
+            output_dim: width.ok_or("model has no layers")?,
+        })
+    }
+
+    pub fn predict(&self, input: &[f32]) -> Result<Vec<f32>, String> {
+        if input.len() != self.input_dim {
+            return Err(format!("model expects {} inputs, got {}", self.input_dim, input.len()));
+        }
+        self.layers.iter().try_fold(input.to_vec(), |values, layer| layer.forward(values))
+    }
+}
+
+// A model together with its class map and the extractor matching its input
+#[derive(Debug, Clone)]
+pub struct Classifier {
+    pub model: Model,
+    pub labels: Vec<String>, // Class name per output index
+    pub kind: FeatureKind,
+}
+
+impl Classifier {
+    // Load model.json and the class map, by default class-map.json next to it
+    pub fn load(model_path: &Path, class_map_path: Option<&Path>) -> Result<Classifier, Box<dyn std::error::Error>> {
+        let model = Model::load(model_path)?;
+        // Same rule as the PWA: the input size decides the extractor
+        let kind = match model.input_dim {
+            ENHANCED_FEATURE_DIM => FeatureKind::Enhanced,
+            SIMPLE_MFCC_COUNT => FeatureKind::Simple,
+            dim => return Err(format!("Unsupported model input dimension: {}D. Expected 13D or 60D.", dim).into()),
+        };
+
+        let default_path = model_path.with_file_name("class-map.json");
+        let class_map = load_class_map(class_map_path.unwrap_or(&default_path))?;
+        let labels = (0..model.output_dim)
+            .map(|index| {
+                class_map
+                    .iter()
+                    .find(|(_, i)| *i == index)
+                    .map(|(label, _)| label.clone())
+                    .unwrap_or_else(|| format!("class_{}", index))
+            })
+            .collect();
+        Ok(Classifier { model, labels, kind })
+    }
+
+    // Species with their scores, best first
+    pub fn classify(&self, audio: &AudioBuffer) -> Result<Vec<(String, f32)>, String> {
+        let features = self.kind.extract(audio).ok_or("shorter than one analysis frame")?;
+        let input: Vec<f32> = features.iter().map(|&v| v as f32).collect();
+        let scores = self.model.predict(&input)?;
+        let mut ranked: Vec<(String, f32)> = self.labels.iter().cloned().zip(scores).collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Ok(ranked)
+    }
+}
+
+// Audio files given directly or found in the given directories, in order
+pub fn collect_audio_files(paths: &[String]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            let mut found: Vec<PathBuf> = std::fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| InputFormat::from_extension(p).is_some())
+                .collect();
+            found.sort();
+            files.extend(found);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+// Score audio files on `threads` workers, printing the `top` best species
+// for each and optionally writing them to a CSV (file,rank,species,score)
+pub fn classify_files(
+    classifier: &Classifier,
+    files: &[PathBuf],
+    top: usize,
+    threads: usize,
+    output_path: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let classifier = Arc::new(classifier.clone());
+    let pool = ThreadPool::new(threads.max(1));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    for (index, path) in files.iter().cloned().enumerate() {
+        let results = Arc::clone(&results);
+        let classifier = Arc::clone(&classifier);
+        pool.execute(move || {
+            let ranked = read_audio(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|audio| classifier.classify(&audio));
+            match ranked {
+                Ok(ranked) => results.lock().unwrap().push((index, ranked)),
+                Err(e) => println!("Error classifying {}: {}", path.display(), e),
+            }
+        });
+    }
+    pool.join();
+
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by_key(|(index, _)| *index);
+
+    let mut writer = output_path.map(csv::Writer::from_path).transpose()?;
+    if let Some(writer) = writer.as_mut() {
+        writer.write_record(["file", "rank", "species", "score"])?;
+    }
+    for (index, ranked) in &results {
+        let file = files[*index].display().to_string();
+        let best: Vec<String> = ranked
+            .iter()
+            .take(top)
+            .map(|(label, score)| format!("{} {:.3}", label, score))
+            .collect();
+        println!("{}: {}", file, best.join(", "));
+        if let Some(writer) = writer.as_mut() {
+            for (rank, (label, score)) in ranked.iter().take(top).enumerate() {
+                writer.write_record([&file, &(rank + 1).to_string(), label, &format!("{:.6}", score)])?;
+            }
+        }
+    }
+    if let Some(mut writer) = writer {
+        writer.flush()?;
+    }
+
+    println!("Classified {} of {} files ({})", results.len(), files.len(), classifier.kind.name());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mfcc").join(name)
+    }
+
+    fn numbers(name: &str) -> Vec<f32> {
+        std::fs::read_to_string(fixture(name))
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.parse().unwrap())
+            .collect()
+    }
+
+    // Write a one-layer Sequential model with the given weights to a temp dir
+    fn write_model(name: &str, layer: Value, weights: &[(&str, Vec<f32>)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("model_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let specs: Vec<Value> = weights
+            .iter()
+            .map(|(weight, values)| json!({ "name": weight, "shape": [values.len()], "dtype": "float32" }))
+            .collect();
+        let bytes: Vec<u8> = weights.iter().flat_map(|(_, values)| values.iter().flat_map(|v| v.to_le_bytes())).collect();
+        std::fs::write(dir.join("weights.bin"), bytes).unwrap();
+        let model = json!({
+            "modelTopology": { "class_name": "Sequential", "config": { "layers": [layer] } },
+            "weightsManifest": [{ "paths": ["weights.bin"], "weights": specs }],
+        });
+        let path = dir.join("model.json");
+        std::fs::write(&path, model.to_string()).unwrap();
+        path
+    }
+
+    // Reference scores come from tests/fixtures/mfcc/predict.mjs, which runs
+    // the shipped PWA model on the simple MFCC fixtures
+    #[test]
+    fn shipped_model_matches_reference_predictions() {
+        let model = Model::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("../model/mfcc-model.json")).unwrap();
+        assert_eq!((model.input_dim, model.output_dim), (SIMPLE_MFCC_COUNT, 2));
+        for name in ["chirp_44100", "stereo_22050", "single_frame"] {
+            let scores = model.predict(&numbers(&format!("{}.simple.txt", name))).unwrap();
+            let expected = numbers(&format!("{}.predictions.txt", name));
+            assert_eq!(scores.len(), expected.len(), "{}", name);
+            for (actual, expected) in scores.iter().zip(&expected) {
+                assert!((actual - expected).abs() < 1e-5, "{}: rust {} vs reference {}", name, actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn batch_normalization_with_mismatched_weights_is_an_error() {
+        let layer = json!({
+            "class_name": "BatchNormalization",
+            "config": { "name": "bn", "batch_input_shape": [null, 3] },
+        });
+        let path = write_model(
+            "bad_bn",
+            layer,
+            &[
+                ("bn/gamma", vec![1.0; 3]),
+                ("bn/beta", vec![0.0; 2]),
+                ("bn/moving_mean", vec![0.0; 3]),
+                ("bn/moving_variance", vec![1.0; 3]),
+            ],
+        );
+        let error = Model::load(&path).unwrap_err().to_string();
+        assert!(error.contains("bn"), "{}", error);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn layer_normalization_checks_weights_and_keeps_width() {
+        let layer = json!({
+            "class_name": "LayerNormalization",
+            "config": { "name": "ln", "batch_input_shape": [null, 4] },
+        });
+        let path = write_model("ln", layer.clone(), &[("ln/gamma", vec![1.0; 4]), ("ln/beta", vec![0.0; 4])]);
+        let model = Model::load(&path).unwrap();
+        assert_eq!(model.output_dim, 4);
+        let output = model.predict(&[1.0, 2.0, 3.0, 4.0]).unwrap();
+        assert!(output.iter().sum::<f32>().abs() < 1e-5);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let path = write_model("bad_ln", layer, &[("ln/gamma", vec![1.0; 4]), ("ln/beta", vec![0.0; 5])]);
+        assert!(Model::load(&path).is_err());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+use crate::audio::{AudioBuffer, TARGET_SAMPLE_RATE};
+use crate::filters::{high_pass, low_pass, spectral_subtraction};
+use crate::loudness::{normalize, NormalizeConfig, NormalizeMode};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Processing chain applied to decoded recordings to build a dataset variant.
+// Steps run in field order: filtering, noise reduction, then normalization.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub high_pass_hz: Option<f64>,
+    pub low_pass_hz: Option<f64>,
+    pub noise_reduction: bool,
+    pub noise_strength: f64, // Multiple of the noise spectrum to subtract
+    pub noise_floor: f64,    // Fraction of the original magnitude always kept
+    pub normalize: Option<NormalizeConfig>,
+}
+
+impl Profile {
+    // A profile that leaves the audio untouched
+    pub fn raw(name: &str) -> Self {
+        Profile {
+            name: name.to_string(),
+            high_pass_hz: None,
+            low_pass_hz: None,
+            noise_reduction: false,
+            noise_strength: 1.5,
+            noise_floor: 0.05,
+            normalize: None,
+        }
+    }
+
+    // Whether running the profile can change the audio at all
+    pub fn changes_audio(&self) -> bool {
+        self.high_pass_hz.is_some() || self.low_pass_hz.is_some() || self.noise_reduction || self.normalize.is_some()
+    }
+
+    // Cutoffs must lie inside the band of the converted audio, which is
+    // always at TARGET_SAMPLE_RATE, and leave a pass band between them
+    fn validate(&self) -> Result<(), String> {
+        let nyquist = TARGET_SAMPLE_RATE as f64 / 2.0;
+        for (key, cutoff) in [("high_pass_hz", self.high_pass_hz), ("low_pass_hz", self.low_pass_hz)] {
+            if let Some(cutoff) = cutoff
+                && !(cutoff > 0.0 && cutoff < nyquist)
+            {
+                return Err(format!("{} must be between 0 and {} Hz, got {}", key, nyquist, cutoff));
+            }
+        }
+        if let (Some(high_pass), Some(low_pass)) = (self.high_pass_hz, self.low_pass_hz)
+            && low_pass <= high_pass
+        {
+            return Err(format!("low_pass_hz ({}) must be above high_pass_hz ({})", low_pass, high_pass));
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match key {
+            "high_pass_hz" => self.high_pass_hz = Some(value.parse()?),
+            "low_pass_hz" => self.low_pass_hz = Some(value.parse()?),
+            "noise_reduction" => self.noise_reduction = value.parse()?,
+            "noise_strength" => self.noise_strength = value.parse()?,
+            "noise_floor" => self.noise_floor = value.parse()?,
+            "normalize" => self.normalize = Some(NormalizeConfig::new(value.parse::<NormalizeMode>()?)),
+            "normalize_target" | "true_peak" => {
+                let config = self
+                    .normalize
+                    .as_mut()
+                    .ok_or_else(|| format!("{} requires normalize to be set first", key))?;
+                if key == "normalize_target" {
+                    config.target_db = value.parse()?;
+                } else {
+                    config.true_peak_db = value.parse()?;
+                }
+            }
+            _ => return Err(format!("Unknown profile setting: {}", key).into()),
+        }
+        Ok(())
+    }
+}
+
+// Parse a profiles file made of [name] sections with key = value lines:
+//
+//   [cleaned]
+//   high_pass_hz = 1000
+//   low_pass_hz = 12000
+//   noise_reduction = true
+//   normalize = loudness
+//
+// Names may only use letters, digits, '_' and '-', as they become the
+// folder of the dataset variant.
+pub fn load_profiles(path: &Path) -> Result<HashMap<String, Profile>, Box<dyn std::error::Error>> {
+    let mut profiles = HashMap::new();
+    let mut current: Option<(Profile, usize)> = None; // With the line of its [name]
+    let mut finish = |section: Option<(Profile, usize)>| -> Result<(), String> {
+        if let Some((profile, line_number)) = section {
+            profile.validate().map_err(|e| format!("{}:{}: [{}] {}", path.display(), line_number, profile.name, e))?;
+            profiles.insert(profile.name.clone(), profile);
+        }
+        Ok(())
+    };
+
+    for (line_number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                return Err(format!(
+                    "{}:{}: invalid profile name {:?} (use letters, digits, '_' and '-')",
+                    path.display(),
+                    line_number + 1,
+                    name
+                )
+                .into());
+            }
+            finish(current.take())?;
+            current = Some((Profile::raw(name), line_number + 1));
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("{}:{}: expected key = value", path.display(), line_number + 1))?;
+        let (profile, _) = current
+            .as_mut()
+            .ok_or_else(|| format!("{}:{}: setting outside of a [profile] section", path.display(), line_number + 1))?;
+        profile
+            .set(key.trim(), value.trim())
+            .map_err(|e| format!("{}:{}: {}", path.display(), line_number + 1, e))?;
+    }
+
+    finish(current)?;
+    Ok(profiles)
+}
+
+// Run the profile's processing chain in place, returning the normalization gain
+pub fn apply_profile(audio: &mut AudioBuffer, profile: &Profile) -> Option<f64> {
+    if let Some(cutoff) = profile.high_pass_hz {
+        high_pass(audio, cutoff);
+    }
+    if let Some(cutoff) = profile.low_pass_hz {
+        low_pass(audio, cutoff);
+    }
+    if profile.noise_reduction {
+        spectral_subtraction(audio, profile.noise_strength, profile.noise_floor);
+    }
+    profile.normalize.as_ref().map(|config| normalize(audio, config))
+}
+
+// Look up a profile by name; "raw" exists even without a profiles file
+pub fn find_profile(profiles_path: &Path, profile_name: &str) -> Result<Profile, Box<dyn std::error::Error>> {
+    let profiles = if profiles_path.exists() {
+        load_profiles(profiles_path)?
+    } else {
+        HashMap::new()
+    };
+    match profiles.get(profile_name) {
+        Some(profile) => Ok(profile.clone()),
+        None if profile_name == "raw" => Ok(Profile::raw("raw")),
+        None => Err(format!("Profile {} not found in {}", profile_name, profiles_path.display()).into()),
+    }
+}
+
+// Build a dataset variant in <output_dir>/<profile> by converting every
+// downloaded recording again with the profile applied. The variant gets its
+// own metadata.csv, so the other stages can run on it like on the original.
+pub fn build_profile(
+    output_dir: &str,
+    profile_name: &str,
+    profiles_path: &Path
+) -> Result<(), Box<dyn std::error::Error>> {
+    let profile = find_profile(profiles_path, profile_name)?;
+
+    let metadata_path = Path::new(output_dir).join("metadata.csv");
+    let mut metadata = crate::load_existing_metadata(&metadata_path)?;
+
+    let variant_dir = Path::new(output_dir).join(&profile.name);
+    std::fs::create_dir_all(&variant_dir)?;
+    let variant_metadata_path = variant_dir.join("metadata.csv");
+    let previous: HashMap<String, Option<f64>> = if variant_metadata_path.exists() {
+        crate::load_existing_metadata(&variant_metadata_path)?
+            .into_iter()
+            .map(|m| (m.id, m.gain_db))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let mut processed = 0;
+    for meta in &mut metadata {
+        let source_path = Path::new(output_dir).join(&meta.filename);
+        let variant_path = variant_dir.join(&meta.filename);
+
+        if !meta.is_downloaded || !source_path.exists() {
+            meta.is_downloaded = false;
+            continue;
+        }
+
+        // Files built on an earlier run are kept as they are
+        if variant_path.exists() {
+            meta.gain_db = previous.get(&meta.id).copied().flatten();
+            continue;
+        }
+
+        match crate::convert_audio(&source_path, &variant_path, &profile) {
+            Ok(gain_db) => {
+                meta.gain_db = gain_db;
+                processed += 1;
+                println!("Processed {} with profile {}", meta.filename, profile.name);
+            }
+            Err(e) => {
+                println!("Error converting {}: {}", source_path.display(), e);
+                meta.is_downloaded = false;
+            }
+        }
+    }
+
+    crate::write_metadata_csv(&variant_metadata_path, &metadata)?;
+    println!("Built {} new files for profile {} in {}", processed, profile.name, variant_dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write_profiles(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("profiles_{}_{}.ini", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn load_error(name: &str, contents: &str) -> String {
+        let path = write_profiles(name, contents);
+        let error = load_profiles(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        error
+    }
+
+    #[test]
+    fn parses_sections_and_settings() {
+        let path = write_profiles(
+            "valid",
+            "# Variants\n[cleaned]\nhigh_pass_hz = 1000\nlow_pass_hz = 12000\nnoise_reduction = true\n\
+             normalize = loudness\nnormalize_target = -20\n\n[quiet-raw_2]\n; nothing set\n",
+        );
+        let profiles = load_profiles(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let cleaned = &profiles["cleaned"];
+        assert_eq!((cleaned.high_pass_hz, cleaned.low_pass_hz), (Some(1000.0), Some(12000.0)));
+        assert!(cleaned.noise_reduction);
+        let normalize = cleaned.normalize.as_ref().unwrap();
+        assert_eq!((normalize.mode, normalize.target_db), (NormalizeMode::Loudness, -20.0));
+        assert!(!profiles["quiet-raw_2"].changes_audio());
+    }
+
+    #[test]
+    fn rejects_cutoffs_outside_the_band() {
+        for (name, settings) in [
+            ("zero", "high_pass_hz = 0"),
+            ("negative", "low_pass_hz = -50"),
+            ("nyquist", "low_pass_hz = 22050"),
+            ("crossed", "high_pass_hz = 5000\nlow_pass_hz = 4000"),
+        ] {
+            let error = load_error(name, &format!("[p]\n{}\n", settings));
+            assert!(error.contains(":1: [p]"), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn rejects_names_that_are_not_plain_folder_names() {
+        for name in ["../escape", "a/b", "", "with space"] {
+            let error = load_error("name", &format!("[{}]\n", name));
+            assert!(error.contains("invalid profile name"), "{:?}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_setting() {
+        let error = load_error("line", "[p]\nnormalize_target = -20\n");
+        assert!(error.contains(":2: normalize_target requires normalize"), "{}", error);
+        assert!(load_error("unknown", "[p]\ncolour = red\n").contains("Unknown profile setting"));
+    }
+
+    #[test]
+    fn raw_exists_without_a_profiles_file() {
+        let missing = std::env::temp_dir().join(format!("profiles_missing_{}.ini", std::process::id()));
+        assert!(!find_profile(&missing, "raw").unwrap().changes_audio());
+        assert!(find_profile(&missing, "cleaned").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::RecordingMetadata;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::Path;
+
+// Per-species (min, max) quotas, overriding the global ones where set
+pub type QuotaOverrides = HashMap<String, (Option<usize>, Option<usize>)>;
+
+// Limits on how many recordings to download per species
+#[derive(Debug, Clone, Default)]
+pub struct QuotaConfig {
+    pub min_per_species: Option<usize>, // Species below this are downloaded first, and reported
+    pub max_per_species: Option<usize>, // Species at this count are not downloaded further
+    pub balance: bool,                  // Interleave the queue, least represented species first
+    pub overrides: QuotaOverrides,
+}
+
+impl QuotaConfig {
+    fn limits(&self, species: &str) -> (Option<usize>, Option<usize>) {
+        match self.overrides.get(species) {
+            Some(&(min, max)) => (min.or(self.min_per_species), max.or(self.max_per_species)),
+            None => (self.min_per_species, self.max_per_species),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.min_per_species.is_some() || self.max_per_species.is_some() || self.balance || !self.overrides.is_empty()
+    }
+}
+
+// Read per-species quotas from a CSV file with a header and the columns
+// species,min,max. Species may be given by common name or as in
+// metadata.csv; an empty min or max falls back to the global one.
+pub fn load_quota_overrides(path: &Path) -> Result<QuotaOverrides, Box<dyn std::error::Error>> {
+    let mut overrides = HashMap::new();
+    let mut reader = csv::Reader::from_path(path)?;
+    for result in reader.records() {
+        let record = result?;
+        let Some(species) = record.get(0).map(|s| crate::format_species_name(s.trim())) else {
+            continue;
+        };
+        let parse = |index: usize| -> Result<Option<usize>, Box<dyn std::error::Error>> {
+            match record.get(index).map(str::trim) {
+                None | Some("") => Ok(None),
+                Some(value) => Ok(Some(value.parse().map_err(|_| format!("Invalid quota for {}: {}", species, value))?)),
+            }
+        };
+        overrides.insert(species.clone(), (parse(1)?, parse(2)?));
+    }
+    Ok(overrides)
+}
+
+// Choose which of the missing recordings to download, and in what order.
+// Counts start from what metadata.csv already marks as downloaded. Species
+// at their maximum are skipped; species below their minimum come first.
+// With `balance`, the rest of the queue takes one recording at a time from
+// the species with the fewest so far, so an interrupted run still leaves a
+// balanced catalog. Recordings `allowed` rejects (e.g. by license) are
+// never candidates, so they take no quota slots. Returns indices into
+// `metadata`.
+pub fn plan_downloads(
+    metadata: &[RecordingMetadata],
+    config: &QuotaConfig,
+    allowed: impl Fn(&RecordingMetadata) -> bool,
+) -> Vec<usize> {
+    let missing: Vec<usize> = (0..metadata.len()).filter(|&i| !metadata[i].is_downloaded && allowed(&metadata[i])).collect();
+    if !config.is_active() {
+        return missing;
+    }
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for meta in metadata {
+        let count = counts.entry(meta.species.as_str()).or_insert(0);
+        if meta.is_downloaded {
+            *count += 1;
+        }
+    }
+    let downloaded = counts.clone();
+
+    // Candidates per species, in catalog order
+    let mut candidates: BTreeMap<&str, VecDeque<usize>> = BTreeMap::new();
+    for &i in &missing {
+        candidates.entry(metadata[i].species.as_str()).or_default().push_back(i);
+    }
+    let available: BTreeMap<&str, usize> = candidates.iter().map(|(s, c)| (*s, c.len())).collect();
+
+    let below = |count: usize, limit: Option<usize>| limit.is_none_or(|limit| count < limit);
+    let mut plan = Vec::new();
+
+    // Bring every species up to its minimum first
+    let mut queued: BTreeMap<&str, usize> = counts.clone();
+    loop {
+        let next = candidates
+            .iter()
+            .filter(|(species, queue)| {
+                let (min, max) = config.limits(species);
+                !queue.is_empty() && min.is_some_and(|min| queued[*species] < min) && below(queued[*species], max)
+            })
+            .min_by_key(|(species, _)| queued[**species])
+            .map(|(species, _)| *species);
+        let Some(species) = next else { break };
+        plan.push(candidates.get_mut(species).unwrap().pop_front().unwrap());
+        *queued.get_mut(species).unwrap() += 1;
+    }
+
+    if config.balance {
+        loop {
+            let next = candidates
+                .iter()
+                .filter(|(species, queue)| !queue.is_empty() && below(queued[**species], config.limits(species).1))
+                .min_by_key(|(species, _)| queued[**species])
+                .map(|(species, _)| *species);
+            let Some(species) = next else { break };
+            plan.push(candidates.get_mut(species).unwrap().pop_front().unwrap());
+            *queued.get_mut(species).unwrap() += 1;
+        }
+    } else {
+        // Remaining recordings in catalog order, up to each species' maximum
+        let mut rest: Vec<usize> = candidates.values().flatten().copied().collect();
+        rest.sort();
+        for i in rest {
+            let species = metadata[i].species.as_str();
+            if below(queued[species], config.limits(species).1) {
+                plan.push(i);
+                *queued.get_mut(species).unwrap() += 1;
+            }
+        }
+    }
+
+    println!("{:<32} {:>10} {:>8} {:>8}", "species", "downloaded", "queued", "skipped");
+    for (species, &have) in &downloaded {
+        let queued_now = queued[species] - have;
+        let skipped = available.get(species).copied().unwrap_or(0) - queued_now;
+        println!("{:<32} {:>10} {:>8} {:>8}", species, have, queued_now, skipped);
+        if let (Some(min), _) = config.limits(species)
+            && queued[species] < min
+        {
+            println!("  warning: only {} recordings listed for {}, below the minimum of {}", queued[species], species, min);
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog(entries: &[(&str, bool)]) -> Vec<RecordingMetadata> {
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, &(species, is_downloaded))| RecordingMetadata {
+                id: i.to_string(),
+                species: species.to_string(),
+                is_downloaded,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn species_of(metadata: &[RecordingMetadata], plan: &[usize]) -> Vec<String> {
+        plan.iter().map(|&i| metadata[i].species.clone()).collect()
+    }
+
+    #[test]
+    fn without_quotas_every_missing_recording_is_planned_in_order() {
+        let metadata = catalog(&[("wren", true), ("wren", false), ("robin", false)]);
+        assert_eq!(plan_downloads(&metadata, &QuotaConfig::default(), |_| true), vec![1, 2]);
+    }
+
+    #[test]
+    fn species_below_their_minimum_come_first() {
+        let metadata = catalog(&[("wren", false), ("wren", false), ("robin", true), ("robin", false), ("robin", false)]);
+        let config = QuotaConfig { min_per_species: Some(2), ..Default::default() };
+        // robin reaches its minimum with one more, wren needs two
+        assert_eq!(plan_downloads(&metadata, &config, |_| true), vec![0, 3, 1, 4]);
+    }
+
+    #[test]
+    fn species_at_their_maximum_are_skipped() {
+        let metadata = catalog(&[("wren", true), ("wren", true), ("wren", false), ("robin", false), ("robin", false)]);
+        let config = QuotaConfig { max_per_species: Some(2), ..Default::default() };
+        assert_eq!(plan_downloads(&metadata, &config, |_| true), vec![3, 4]);
+    }
+
+    #[test]
+    fn balance_takes_from_the_least_represented_species() {
+        let metadata = catalog(&[
+            ("wren", true),
+            ("wren", true),
+            ("wren", false),
+            ("wren", false),
+            ("robin", false),
+            ("robin", false),
+            ("robin", false),
+        ]);
+        let config = QuotaConfig { balance: true, max_per_species: Some(3), ..Default::default() };
+        let plan = plan_downloads(&metadata, &config, |_| true);
+        assert_eq!(species_of(&metadata, &plan), ["robin", "robin", "robin", "wren"]);
+        assert_eq!(plan, vec![4, 5, 6, 2]);
+    }
+
+    #[test]
+    fn overrides_replace_only_the_limits_they_set() {
+        let metadata = catalog(&[("wren", false), ("wren", false), ("wren", false), ("robin", false), ("robin", false)]);
+        let config = QuotaConfig {
+            max_per_species: Some(2),
+            overrides: HashMap::from([("wren".to_string(), (Some(1), None)), ("robin".to_string(), (None, Some(1)))]),
+            ..Default::default()
+        };
+        // wren keeps the global maximum of 2, robin is capped at 1
+        assert_eq!(species_of(&metadata, &plan_downloads(&metadata, &config, |_| true)), ["wren", "wren", "robin"]);
+    }
+
+    #[test]
+    fn overrides_are_read_by_common_name_with_empty_limits_unset() {
+        let path = std::env::temp_dir().join(format!("quotas_{}.csv", std::process::id()));
+        std::fs::write(&path, "species,min,max\nBarn Swallow, 3,\nrobin,,5\n").unwrap();
+        let overrides = load_quota_overrides(&path).unwrap();
+        std::fs::write(&path, "species,min,max\nrobin,many,\n").unwrap();
+        let invalid = load_quota_overrides(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides["barn_swallow"], (Some(3), None));
+        assert_eq!(overrides["robin"], (None, Some(5)));
+        assert!(invalid.unwrap_err().to_string().contains("Invalid quota for robin"));
+    }
+
+    #[test]
+    fn recordings_that_are_not_allowed_take_no_quota_slots() {
+        let metadata = catalog(&[("wren", false), ("wren", false), ("wren", false), ("wren", false), ("robin", false)]);
+        let config = QuotaConfig { min_per_species: Some(2), max_per_species: Some(2), ..Default::default() };
+        // The first two wrens have an excluded license; the later two fill the quota
+        let plan = plan_downloads(&metadata, &config, |m| m.id != "0" && m.id != "1");
+        assert_eq!(plan, vec![4, 2, 3]);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::audio::read_audio;
+use crate::clips::{clips_csv_path, load_clips, ClipMetadata};
+use crate::dataset::{collect_samples, Sample};
+use crate::dsp::rms_db;
+use crate::html::escape_html;
+use crate::spectrogram::{log_mel_spectrogram, LogMelConfig};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+// Spectrogram image layout
+const MEL_BANDS: usize = 128;
+const BAND_HEIGHT: usize = 2; // Pixels per Mel band
+const MAX_WIDTH: usize = 1600; // Longer recordings are squeezed to this many columns
+const DYNAMIC_RANGE_DB: f64 = 80.0; // Shown below the loudest bin
+const WAVEFORM_HEIGHT: usize = 64;
+const MARKER_HEIGHT: usize = 6; // Bar above the spectrogram marking clip extents
+
+// Recordings quieter than this (RMS, dBFS) are flagged in the index
+const SILENT_RMS_DB: f64 = -60.0;
+
+// Colors of the clip markers, cycled per clip
+const MARKER_COLORS: [[u8; 3]; 3] = [[80, 220, 120], [90, 170, 255], [255, 200, 60]];
+
+// Stops of the spectrogram color map, from quiet to loud
+const COLOR_MAP: [[f64; 3]; 5] = [
+    [0.0, 0.0, 4.0],
+    [80.0, 18.0, 123.0],
+    [182.0, 54.0, 121.0],
+    [251.0, 136.0, 97.0],
+    [252.0, 253.0, 191.0],
+];
+
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>, // RGB
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Self {
+        Image { width, height, pixels: vec![0; width * height * 3] }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let offset = (y * self.width + x) * 3;
+            self.pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+    }
+
+    fn fill_rect(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, color: [u8; 3]) {
+        for y in y0..y1.min(self.height) {
+            for x in x0..x1.min(self.width) {
+                self.set(x, y, color);
+            }
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+fn color_map(level: f64) -> [u8; 3] {
+    let position = level.clamp(0.0, 1.0) * (COLOR_MAP.len() - 1) as f64;
+    let index = (position.floor() as usize).min(COLOR_MAP.len() - 2);
+    let t = position - index as f64;
+    let (a, b) = (COLOR_MAP[index], COLOR_MAP[index + 1]);
+    [0, 1, 2].map(|c| (a[c] + (b[c] - a[c]) * t).round() as u8)
+}
+
+// Per-file numbers shown under each image
+pub struct RenderSummary {
+    duration_secs: f64,
+    rms_db: f64,
+    peak_db: f64,
+}
+
+// Render the spectrogram of one file, with clip extents marked and an
+// optional waveform strip underneath
+pub fn render_file(
+    sample: &Sample,
+    clips: &[ClipMetadata],
+    waveform: bool,
+    image_path: &Path,
+) -> Result<RenderSummary, Box<dyn std::error::Error>> {
+    let audio = read_audio(&sample.path)?;
+    let signal = audio.channel(0);
+    let config = LogMelConfig { n_mels: MEL_BANDS, ..LogMelConfig::default() };
+    let spectrogram = log_mel_spectrogram(&signal, audio.sample_rate, &config);
+    if spectrogram.is_empty() {
+        return Err("shorter than one frame".into());
+    }
+
+    // Squeeze long recordings by keeping the loudest frame per column
+    let frames_per_column = spectrogram.len().div_ceil(MAX_WIDTH);
+    let columns: Vec<Vec<f32>> = spectrogram
+        .chunks(frames_per_column)
+        .map(|group| {
+            (0..MEL_BANDS)
+                .map(|band| group.iter().map(|frame| frame[band]).fold(f32::MIN, f32::max))
+                .collect()
+        })
+        .collect();
+
+    // Log energies are natural logs of power; convert to dB for the range
+    let to_db = |value: f32| value as f64 * 10.0 / std::f64::consts::LN_10;
+    let loudest = columns.iter().flatten().map(|&v| to_db(v)).fold(f64::MIN, f64::max);
+
+    let width = columns.len();
+    let spectrogram_top = MARKER_HEIGHT;
+    let spectrogram_height = MEL_BANDS * BAND_HEIGHT;
+    let height = spectrogram_top + spectrogram_height + if waveform { WAVEFORM_HEIGHT } else { 0 };
+    let mut image = Image::new(width, height);
+
+    for (x, column) in columns.iter().enumerate() {
+        for (band, &value) in column.iter().enumerate() {
+            let level = (to_db(value) - loudest + DYNAMIC_RANGE_DB) / DYNAMIC_RANGE_DB;
+            // Low frequencies at the bottom
+            let y = spectrogram_top + (MEL_BANDS - 1 - band) * BAND_HEIGHT;
+            image.fill_rect(x, y, x + 1, y + BAND_HEIGHT, color_map(level));
+        }
+    }
+
+    // Clip extents: a colored bar on top and white lines at the boundaries
+    let seconds_per_column = (config.hop * frames_per_column) as f64 / audio.sample_rate as f64;
+    let to_x = |secs: f64| (secs / seconds_per_column).round() as usize;
+    for (i, clip) in clips.iter().enumerate() {
+        let (x0, x1) = (to_x(clip.start_secs), to_x(clip.end_secs).max(to_x(clip.start_secs) + 1));
+        image.fill_rect(x0, 0, x1, MARKER_HEIGHT, MARKER_COLORS[i % MARKER_COLORS.len()]);
+        for x in [x0, x1.saturating_sub(1)] {
+            image.fill_rect(x, spectrogram_top, x + 1, spectrogram_top + spectrogram_height, [255, 255, 255]);
+        }
+    }
+
+    if waveform {
+        let top = spectrogram_top + spectrogram_height;
+        image.fill_rect(0, top, width, height, [24, 24, 24]);
+        let samples_per_column = signal.len().div_ceil(width);
+        let middle = (WAVEFORM_HEIGHT / 2) as f64;
+        for (x, group) in signal.chunks(samples_per_column).enumerate() {
+            let (low, high) = group.iter().fold((0.0f32, 0.0f32), |(lo, hi), &s| (lo.min(s), hi.max(s)));
+            let y0 = (middle - high.clamp(-1.0, 1.0) as f64 * middle).floor() as usize;
+            let y1 = (middle - low.clamp(-1.0, 1.0) as f64 * middle).ceil() as usize;
+            image.fill_rect(x, top + y0, x + 1, top + y1.max(y0 + 1), [170, 170, 170]);
+        }
+    }
+
+    image.save(image_path)?;
+
+    let peak = signal.iter().fold(0.0f32, |peak, &s| peak.max(s.abs()));
+    Ok(RenderSummary {
+        duration_secs: audio.frames() as f64 / audio.sample_rate as f64,
+        rms_db: rms_db(&signal),
+        peak_db: 20.0 * (peak as f64 + 1e-12).log10(),
+    })
+}
+
+// Render every recording (or clip) in the catalog into `render_dir` and write
+// an index.html there with the images grouped by species
+pub fn render_directory(
+    output_dir: &str,
+    render_dir: &Path,
+    use_clips: bool,
+    waveform: bool,
+    threads: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let samples = collect_samples(output_dir, use_clips)?;
+    std::fs::create_dir_all(render_dir)?;
+
+    // Clip boundaries are drawn on the recordings they were cut from.
+    // Augmented clips repeat the span of the clip they were made from.
+    let mut clips_by_parent: HashMap<String, Vec<ClipMetadata>> = HashMap::new();
+    if !use_clips {
+        for clip in load_clips(&clips_csv_path(output_dir))?.into_iter().filter(|c| c.source != "augment") {
+            clips_by_parent.entry(clip.parent_id.clone()).or_default().push(clip);
+        }
+    }
+
+    let pool = ThreadPool::new(threads.max(1));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    for (index, sample) in samples.iter().cloned().enumerate() {
+        let results = Arc::clone(&results);
+        let clips = clips_by_parent.get(&sample.recording_id).cloned().unwrap_or_default();
+        let image_name = format!("{}.png", sample.path.file_stem().unwrap_or_default().to_string_lossy());
+        let image_path = render_dir.join(&image_name);
+        pool.execute(move || match render_file(&sample, &clips, waveform, &image_path) {
+            Ok(summary) => results.lock().unwrap().push((index, image_name, summary)),
+            Err(e) => println!("Error rendering {}: {}", sample.path.display(), e),
+        });
+    }
+    pool.join();
+
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by_key(|(index, _, _)| *index);
+
+    let mut by_species: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+    for (index, image_name, summary) in &results {
+        let sample = &samples[*index];
+        by_species.entry(sample.species.as_str()).or_default().push((sample, image_name, summary));
+    }
+
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Recordings</title>\n<style>\n\
+         body { font-family: sans-serif; background: #111; color: #ddd; }\n\
+         figure { display: inline-block; margin: 8px; vertical-align: top; }\n\
+         figure img { max-width: 800px; display: block; }\n\
+         figcaption { font-size: 12px; }\n\
+         .silent figcaption { color: #f66; }\n\
+         </style>\n</head>\n<body>\n",
+    );
+    html.push_str("<ul>\n");
+    for (species, entries) in &by_species {
+        html.push_str(&format!(
+            "<li><a href=\"#{0}\">{0}</a> ({1})</li>\n",
+            escape_html(species),
+            entries.len()
+        ));
+    }
+    html.push_str("</ul>\n");
+    for (species, entries) in &by_species {
+        html.push_str(&format!("<h2 id=\"{0}\">{0} ({1})</h2>\n", escape_html(species), entries.len()));
+        for (sample, image_name, summary) in entries {
+            let silent = summary.rms_db < SILENT_RMS_DB;
+            html.push_str(&format!(
+                "<figure{}><img src=\"{}\" loading=\"lazy\"><figcaption>{} &middot; id {} &middot; {:.1} s &middot; RMS {:.1} dBFS &middot; peak {:.1} dBFS{}</figcaption></figure>\n",
+                if silent { " class=\"silent\"" } else { "" },
+                escape_html(image_name),
+                escape_html(&sample.path.file_name().unwrap_or_default().to_string_lossy()),
+                escape_html(&sample.recording_id),
+                summary.duration_secs,
+                summary.rms_db,
+                summary.peak_db,
+                if silent { " &middot; possibly silent" } else { "" }
+            ));
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    std::fs::write(render_dir.join("index.html"), html)?;
+
+    println!(
+        "Rendered {} of {} files into {}",
+        results.len(),
+        samples.len(),
+        render_dir.join("index.html").display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{write_wav, AudioBuffer};
+
+    #[test]
+    fn renders_a_png_of_the_expected_size() {
+        let dir = std::env::temp_dir().join(format!("render_png_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("XC1.wav");
+        let samples = (0..22050).map(|i| (i as f32 * 0.2).sin() * 0.5).collect();
+        write_wav(&path, &AudioBuffer { sample_rate: 22050, channels: 1, samples }).unwrap();
+        let sample = Sample {
+            path,
+            species: "wren".to_string(),
+            recording_id: "1".to_string(),
+            file: "XC1.wav".to_string(),
+            source: "recording".to_string(),
+        };
+        let clip = ClipMetadata {
+            filename: "wren_1.wav".to_string(),
+            species: "wren".to_string(),
+            parent_id: "1".to_string(),
+            parent_filename: "XC1.wav".to_string(),
+            start_secs: 0.2,
+            end_secs: 0.5,
+            source: "segment".to_string(),
+            augmentation: String::new(),
+            verified: false,
+            low_hz: None,
+            high_hz: None,
+        };
+
+        let image_path = dir.join("XC1.png");
+        let summary = render_file(&sample, &[clip], true, &image_path).unwrap();
+        assert!((summary.duration_secs - 1.0).abs() < 1e-9);
+
+        let bytes = std::fs::read(&image_path).unwrap();
+        assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        let decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        // One column per 2048-sample frame every 512 samples
+        assert_eq!(info.width as usize, (22050 - 2048) / 512 + 1);
+        assert_eq!(info.height as usize, MARKER_HEIGHT + MEL_BANDS * BAND_HEIGHT + WAVEFORM_HEIGHT);
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_shorter_than_a_frame_are_an_error() {
+        let dir = std::env::temp_dir().join(format!("render_short_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("XC2.wav");
+        write_wav(&path, &AudioBuffer { sample_rate: 22050, channels: 1, samples: vec![0.1; 1000] }).unwrap();
+        let sample = Sample {
+            path,
+            species: "wren".to_string(),
+            recording_id: "2".to_string(),
+            file: "XC2.wav".to_string(),
+            source: "recording".to_string(),
+        };
+        let image_path = dir.join("XC2.png");
+        assert!(render_file(&sample, &[], false, &image_path).is_err());
+        assert!(!image_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,352 @@
+use crate::audio::read_audio;
+use crate::clips::{clip_counters, clips_csv_path, clips_dir, load_clips, relabel_clip, write_clips_csv, ClipMetadata};
+use crate::dataset::Sample;
+use crate::html::escape_html;
+use crate::model::Classifier;
+use crate::render::render_file;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+// Label annotators enter to drop a clip from the catalog
+const REJECT_LABEL: &str = "reject";
+
+// How unsure the model is about a clip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Uncertainty {
+    Entropy, // Entropy of the scores, normalized to [0, 1]
+    Margin,  // One minus the gap between the two best scores
+}
+
+impl std::str::FromStr for Uncertainty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "entropy" => Ok(Uncertainty::Entropy),
+            "margin" => Ok(Uncertainty::Margin),
+            _ => Err(format!("Unknown uncertainty measure: {} (expected entropy or margin)", s)),
+        }
+    }
+}
+
+impl Uncertainty {
+    fn score(&self, ranked: &[(String, f32)]) -> f64 {
+        match self {
+            Uncertainty::Entropy => {
+                let entropy: f64 = ranked
+                    .iter()
+                    .map(|(_, p)| *p as f64)
+                    .filter(|&p| p > 0.0)
+                    .map(|p| -p * p.ln())
+                    .sum();
+                entropy / (ranked.len().max(2) as f64).ln()
+            }
+            Uncertainty::Margin => {
+                let best = ranked.first().map_or(0.0, |(_, p)| *p);
+                let second = ranked.get(1).map_or(0.0, |(_, p)| *p);
+                1.0 - (best - second) as f64
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReviewConfig {
+    pub uncertainty: Uncertainty,
+    pub limit: usize,            // Clips in the queue, most uncertain first
+    pub include_verified: bool,  // Also queue clips an annotator already confirmed
+    pub review_dir: PathBuf,
+    pub threads: usize,
+}
+
+// Score the segmented clips with the model and write the ones it is least
+// sure about to queue.csv, with a spectrogram per clip and an index.html
+// to listen to them. Annotators fill the verified_label column and run
+// --apply-review. Augmented clips follow the label of their source and are
+// not queued.
+pub fn build_review_queue(
+    output_dir: &str,
+    classifier: &Classifier,
+    config: &ReviewConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let candidates: Vec<ClipMetadata> = load_clips(&clips_csv_path(output_dir))?
+        .into_iter()
+        .filter(|clip| clip.augmentation.is_empty() && (config.include_verified || !clip.verified))
+        .collect();
+    if candidates.is_empty() {
+        println!("No clips to review in {} (run --segment or --chunk first)", output_dir);
+        return Ok(());
+    }
+
+    let classifier = Arc::new(classifier.clone());
+    let pool = ThreadPool::new(config.threads.max(1));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    for (index, clip) in candidates.iter().enumerate() {
+        let results = Arc::clone(&results);
+        let classifier = Arc::clone(&classifier);
+        let path = clips_dir(output_dir).join(&clip.filename);
+        let uncertainty = config.uncertainty;
+        pool.execute(move || {
+            let ranked = read_audio(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|audio| classifier.classify(&audio));
+            match ranked {
+                Ok(ranked) => {
+                    let score = uncertainty.score(&ranked);
+                    results.lock().unwrap().push((index, score, ranked));
+                }
+                Err(e) => println!("Error classifying {}: {}", path.display(), e),
+            }
+        });
+    }
+    pool.join();
+
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    results.truncate(config.limit);
+
+    // Spectrograms of the queued clips
+    let images_dir = config.review_dir.join("images");
+    std::fs::create_dir_all(&images_dir)?;
+    for (index, _, _) in &results {
+        let clip = &candidates[*index];
+        let sample = Sample {
+            path: clips_dir(output_dir).join(&clip.filename),
+            species: clip.species.clone(),
+            recording_id: clip.parent_id.clone(),
+            file: format!("clips/{}", clip.filename),
+            source: clip.source.clone(),
+        };
+        let image_path = images_dir.join(Path::new(&clip.filename).with_extension("png"));
+        pool.execute(move || {
+            if let Err(e) = render_file(&sample, &[], true, &image_path) {
+                println!("Error rendering {}: {}", sample.path.display(), e);
+            }
+        });
+    }
+    pool.join();
+
+    let mut writer = csv::Writer::from_path(config.review_dir.join("queue.csv"))?;
+    writer.write_record([
+        "rank", "clip", "species", "predicted", "score", "runner_up", "runner_up_score", "uncertainty", "image", "verified_label",
+    ])?;
+    let base = std::fs::canonicalize(clips_dir(output_dir)).unwrap_or_else(|_| clips_dir(output_dir));
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Review queue</title>\n<style>\n\
+         body { font-family: sans-serif; background: #111; color: #ddd; }\n\
+         figure { margin: 12px 8px; }\n\
+         figure img { max-width: 800px; display: block; }\n\
+         figcaption { font-size: 13px; margin: 4px 0; }\n\
+         .disagree { color: #fc6; }\n\
+         </style>\n</head>\n<body>\n",
+    );
+    writeln!(
+        html,
+        "<h1>Review queue</h1>\n<p>{} clips, most uncertain first ({:?}). Enter the correct species, or \"{}\", in the verified_label column of queue.csv and run --apply-review.</p>",
+        results.len(),
+        config.uncertainty,
+        REJECT_LABEL
+    )?;
+
+    for (rank, (index, uncertainty, ranked)) in results.iter().enumerate() {
+        let clip = &candidates[*index];
+        let image = format!("images/{}", Path::new(&clip.filename).with_extension("png").display());
+        let (predicted, score) = ranked.first().cloned().unwrap_or_default();
+        let (runner_up, runner_up_score) = ranked.get(1).cloned().unwrap_or_default();
+        writer.write_record([
+            &(rank + 1).to_string(),
+            &clip.filename,
+            &clip.species,
+            &predicted,
+            &format!("{:.4}", score),
+            &runner_up,
+            &format!("{:.4}", runner_up_score),
+            &format!("{:.4}", uncertainty),
+            &image,
+            &String::new(),
+        ])?;
+        writeln!(
+            html,
+            "<figure><figcaption>#{} {} &middot; labeled <b>{}</b> &middot; <span{}>predicted {} {:.3}, {} {:.3}</span> &middot; uncertainty {:.3} &middot; {:.1}-{:.1} s of {}</figcaption>\
+             <img src=\"{}\" loading=\"lazy\"><audio controls preload=\"none\" src=\"file://{}\"></audio></figure>",
+            rank + 1,
+            escape_html(&clip.filename),
+            escape_html(&clip.species),
+            if predicted != clip.species { " class=\"disagree\"" } else { "" },
+            escape_html(&predicted),
+            score,
+            escape_html(&runner_up),
+            runner_up_score,
+            uncertainty,
+            clip.start_secs,
+            clip.end_secs,
+            escape_html(&clip.parent_filename),
+            escape_html(&image),
+            escape_html(&base.join(&clip.filename).to_string_lossy())
+        )?;
+    }
+    writer.flush()?;
+    html.push_str("</body>\n</html>\n");
+    std::fs::write(config.review_dir.join("index.html"), html)?;
+
+    println!(
+        "Queued {} of {} clips for review in {}",
+        results.len(),
+        candidates.len(),
+        config.review_dir.join("index.html").display()
+    );
+    Ok(())
+}
+
+// Write the verified labels of a review queue back to clips.csv. Clips
+// whose label changed are renamed, together with the augmented copies made
+// from them; rejected clips and their copies are moved to clips/rejected.
+pub fn apply_review(output_dir: &str, queue_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let clips_path = clips_csv_path(output_dir);
+    let mut clips = load_clips(&clips_path)?;
+    let mut counters = clip_counters(&clips);
+    let known_species: HashSet<String> = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?
+        .into_iter()
+        .map(|m| m.species)
+        .collect();
+
+    let mut reader = csv::Reader::from_path(queue_path)?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name).ok_or(format!("{} has no {} column", queue_path.display(), name));
+    let (clip_column, label_column) = (column("clip")?, column("verified_label")?);
+
+    let (mut confirmed, mut relabeled, mut rejected) = (0, 0, HashSet::new());
+    for result in reader.records() {
+        let record = result?;
+        let (Some(filename), Some(label)) = (record.get(clip_column), record.get(label_column).map(str::trim)) else {
+            continue;
+        };
+        if label.is_empty() {
+            continue;
+        }
+        let Some(index) = clips.iter().position(|c| c.filename == filename) else {
+            println!("Skipping {}: not in clips.csv", filename);
+            continue;
+        };
+        if label.eq_ignore_ascii_case(REJECT_LABEL) {
+            rejected.insert(format!("clips/{}", filename));
+            continue;
+        }
+
+        let species = crate::format_species_name(label);
+        if !known_species.contains(&species) {
+            println!("Note: {} is labeled {}, which is not a species in metadata.csv", filename, species);
+        }
+        clips[index].verified = true;
+        if clips[index].species == species {
+            confirmed += 1;
+            continue;
+        }
+
+        let old_source = format!("clips/{}", filename);
+        let new_filename = relabel_clip(output_dir, &mut clips[index], &species, &mut counters)?;
+        println!("{} -> {}", filename, new_filename);
+        relabeled += 1;
+        for child in clips.iter_mut().filter(|c| c.parent_filename == old_source) {
+            relabel_clip(output_dir, child, &species, &mut counters)?;
+            child.parent_filename = format!("clips/{}", new_filename);
+        }
+    }
+
+    if !rejected.is_empty() {
+        let rejected_dir = clips_dir(output_dir).join("rejected");
+        std::fs::create_dir_all(&rejected_dir)?;
+        let mut kept = Vec::new();
+        for clip in clips {
+            let source = format!("clips/{}", clip.filename);
+            if rejected.contains(&source) || rejected.contains(&clip.parent_filename) {
+                std::fs::rename(clips_dir(output_dir).join(&clip.filename), rejected_dir.join(&clip.filename))?;
+            } else {
+                kept.push(clip);
+            }
+        }
+        clips = kept;
+    }
+
+    write_clips_csv(&clips_path, &clips)?;
+    println!("Confirmed {}, relabeled {}, rejected {} clips", confirmed, relabeled, rejected.len());
+    if relabeled > 0 || !rejected.is_empty() {
+        println!("Re-run --split and --extract-features --clips to bring the manifests and features up to date");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordingMetadata;
+
+    fn clip(filename: &str, parent_filename: &str, source: &str) -> ClipMetadata {
+        ClipMetadata {
+            filename: filename.to_string(),
+            species: filename.split('_').next().unwrap().to_string(),
+            parent_id: "1".to_string(),
+            parent_filename: parent_filename.to_string(),
+            start_secs: 0.0,
+            end_secs: 1.0,
+            source: source.to_string(),
+            augmentation: String::new(),
+            verified: false,
+            low_hz: None,
+            high_hz: None,
+        }
+    }
+
+    #[test]
+    fn applied_labels_confirm_relabel_and_reject_clips() {
+        let dir = std::env::temp_dir().join(format!("review_apply_{}", std::process::id()));
+        std::fs::create_dir_all(clips_dir(dir.to_str().unwrap())).unwrap();
+        let output_dir = dir.to_str().unwrap();
+
+        let species = |species: &str| RecordingMetadata { species: species.to_string(), ..Default::default() };
+        crate::write_metadata_csv(&dir.join("metadata.csv"), &[species("wren"), species("robin")]).unwrap();
+        let clips = [
+            clip("wren_1.wav", "XC1.wav", "segment"),
+            clip("wren_2.wav", "XC1.wav", "segment"),
+            clip("wren_3.wav", "clips/wren_2.wav", "augment"),
+            clip("robin_1.wav", "XC2.wav", "segment"),
+            clip("robin_2.wav", "clips/robin_1.wav", "augment"),
+        ];
+        for clip in &clips {
+            std::fs::write(clips_dir(output_dir).join(&clip.filename), b"").unwrap();
+        }
+        write_clips_csv(&clips_csv_path(output_dir), &clips).unwrap();
+
+        let queue = dir.join("queue.csv");
+        std::fs::write(
+            &queue,
+            "clip,predicted,verified_label\nwren_1.wav,wren,Wren\nwren_2.wav,wren,Robin\nrobin_1.wav,robin,reject\nwren_3.wav,wren,\n",
+        )
+        .unwrap();
+        apply_review(output_dir, &queue).unwrap();
+
+        let updated = load_clips(&clips_csv_path(output_dir)).unwrap();
+        let summary: Vec<(&str, &str, &str, bool)> = updated
+            .iter()
+            .map(|c| (c.filename.as_str(), c.species.as_str(), c.parent_filename.as_str(), c.verified))
+            .collect();
+        // The relabelled clip takes the next robin number and its augmented
+        // copy follows it; the rejected clip and its copy are gone
+        assert_eq!(
+            summary,
+            [
+                ("wren_1.wav", "wren", "XC1.wav", true),
+                ("robin_3.wav", "robin", "XC1.wav", true),
+                ("robin_4.wav", "robin", "clips/robin_3.wav", false),
+            ]
+        );
+        for name in ["wren_1.wav", "robin_3.wav", "robin_4.wav", "rejected/robin_1.wav", "rejected/robin_2.wav"] {
+            assert!(clips_dir(output_dir).join(name).exists(), "{}", name);
+        }
+        assert!(!clips_dir(output_dir).join("wren_2.wav").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
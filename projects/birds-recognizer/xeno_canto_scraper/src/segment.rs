@@ -0,0 +1,223 @@
+use crate::clips::cut_recordings;
+use crate::dsp::{fft_complex, rms_db};
+use crate::features::{FRAME_SIZE, HOP_SIZE};
+use std::f64::consts::PI;
+
+// Percentile of frame energies taken as the recording's noise floor
+const NOISE_FLOOR_PERCENTILE: f64 = 0.2;
+
+// Settings for detecting vocalizations in a recording
+#[derive(Debug, Clone)]
+pub struct SegmentConfig {
+    pub threshold_db: f64,  // Energy above the noise floor for a frame to count as active
+    pub flux_ratio: f64,    // Spectral flux over the median flux that also counts as active (0 disables)
+    pub min_clip_secs: f64, // Shorter regions are dropped
+    pub max_clip_secs: f64, // Longer regions are split into several clips
+    pub padding_secs: f64,  // Context kept before and after each region
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        SegmentConfig {
+            threshold_db: 10.0,
+            flux_ratio: 3.0,
+            min_clip_secs: 0.5,
+            max_clip_secs: 5.0,
+            padding_secs: 0.25,
+        }
+    }
+}
+
+// Find vocalization regions in a mono signal, returned as (start, end) in seconds
+pub fn detect_regions(signal: &[f32], sample_rate: u32, config: &SegmentConfig) -> Vec<(f64, f64)> {
+    if signal.len() < FRAME_SIZE {
+        return Vec::new();
+    }
+
+    let window: Vec<f64> = (0..FRAME_SIZE)
+        .map(|n| 0.54 - 0.46 * (2.0 * PI * n as f64 / (FRAME_SIZE - 1) as f64).cos())
+        .collect();
+
+    // Per-frame energy (dB) and positive spectral flux
+    let mut energies = Vec::new();
+    let mut fluxes = Vec::new();
+    let mut previous_magnitudes: Option<Vec<f64>> = None;
+    let mut start = 0;
+    while start + FRAME_SIZE <= signal.len() {
+        let frame = &signal[start..start + FRAME_SIZE];
+        energies.push(rms_db(frame));
+
+        let magnitudes = magnitude_spectrum(frame, &window);
+        let flux = match &previous_magnitudes {
+            Some(previous) => magnitudes
+                .iter()
+                .zip(previous)
+                .map(|(m, p)| (m - p).max(0.0))
+                .sum(),
+            None => 0.0,
+        };
+        fluxes.push(flux);
+        previous_magnitudes = Some(magnitudes);
+
+        start += HOP_SIZE;
+    }
+
+    let noise_floor = percentile(&energies, NOISE_FLOOR_PERCENTILE);
+    let median_flux = percentile(&fluxes, 0.5);
+
+    // A frame is active when it is clearly louder than the floor, or when it
+    // is moderately loud and its spectrum changes sharply (onsets of calls)
+    let active: Vec<bool> = energies
+        .iter()
+        .zip(&fluxes)
+        .map(|(&energy, &flux)| {
+            let loud = energy >= noise_floor + config.threshold_db;
+            let onset = config.flux_ratio > 0.0
+                && median_flux > 0.0
+                && flux >= median_flux * config.flux_ratio
+                && energy >= noise_floor + config.threshold_db / 2.0;
+            loud || onset
+        })
+        .collect();
+
+    // Group consecutive active frames into padded regions
+    let duration = signal.len() as f64 / sample_rate as f64;
+    let mut regions: Vec<(f64, f64)> = Vec::new();
+    let mut frame_index = 0;
+    while frame_index < active.len() {
+        if !active[frame_index] {
+            frame_index += 1;
+            continue;
+        }
+        let first = frame_index;
+        while frame_index < active.len() && active[frame_index] {
+            frame_index += 1;
+        }
+        let region_start = (first * HOP_SIZE) as f64 / sample_rate as f64 - config.padding_secs;
+        let region_end = ((frame_index - 1) * HOP_SIZE + FRAME_SIZE) as f64 / sample_rate as f64
+            + config.padding_secs;
+        let region = (region_start.max(0.0), region_end.min(duration));
+
+        // Merge regions whose padding overlaps
+        match regions.last_mut() {
+            Some(last) if region.0 <= last.1 => last.1 = last.1.max(region.1),
+            _ => regions.push(region),
+        }
+    }
+
+    // Enforce clip length limits
+    let mut clips = Vec::new();
+    for (start, end) in regions {
+        let length = end - start;
+        if length < config.min_clip_secs {
+            continue;
+        }
+        if config.max_clip_secs > 0.0 && length > config.max_clip_secs {
+            let pieces = (length / config.max_clip_secs).ceil();
+            let piece_length = length / pieces;
+            for i in 0..pieces as usize {
+                let piece_start = start + i as f64 * piece_length;
+                clips.push((piece_start, (piece_start + piece_length).min(end)));
+            }
+        } else {
+            clips.push((start, end));
+        }
+    }
+
+    clips
+}
+
+// Magnitudes of bins [0, N/2] of a Hamming-windowed frame. Only relative
+// changes matter here, so the iterative FFT is used rather than the slower
+// one that matches the browser bit for bit.
+fn magnitude_spectrum(frame: &[f32], window: &[f64]) -> Vec<f64> {
+    let mut real: Vec<f64> = frame.iter().zip(window).map(|(&x, w)| x as f64 * w).collect();
+    let mut imag = vec![0.0; real.len()];
+    fft_complex(&mut real, &mut imag, false);
+    (0..=real.len() / 2).map(|k| real[k].hypot(imag[k])).collect()
+}
+
+fn percentile(values: &[f64], fraction: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let index = ((sorted.len() - 1) as f64 * fraction).round() as usize;
+    sorted[index]
+}
+
+// Cut every downloaded recording in the directory into vocalization clips
+pub fn segment_directory(output_dir: &str, config: &SegmentConfig) -> Result<(), Box<dyn std::error::Error>> {
+    cut_recordings(output_dir, "segment", |audio| {
+        detect_regions(&audio.mono(), audio.sample_rate, config)
+            .into_iter()
+            .map(|(start_secs, end_secs)| {
+                let start_frame = (start_secs * audio.sample_rate as f64) as usize;
+                let end_frame = (end_secs * audio.sample_rate as f64) as usize;
+                (start_secs, end_secs, audio.slice_frames(start_frame, end_frame))
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 22050;
+
+    // Quiet noise with 3 kHz tone bursts at the given (start, end) seconds
+    fn bursts(seconds: f64, bursts: &[(f64, f64)]) -> Vec<f32> {
+        let mut state = 1u32;
+        (0..(seconds * RATE as f64) as usize)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = 0.001 * ((state >> 16) as f64 / 32768.0 - 1.0);
+                let t = i as f64 / RATE as f64;
+                let tone = if bursts.iter().any(|&(start, end)| t >= start && t < end) {
+                    0.5 * (2.0 * PI * 3000.0 * t).sin()
+                } else {
+                    0.0
+                };
+                (tone + noise) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_tone_bursts_in_silence() {
+        let regions = detect_regions(&bursts(4.0, &[(0.5, 1.2), (2.5, 3.3)]), RATE, &SegmentConfig::default());
+        assert_eq!(regions.len(), 2, "{:?}", regions);
+        // Padded by 0.25 s, plus up to a frame where a burst only partly fills it
+        let frame_secs = FRAME_SIZE as f64 / RATE as f64;
+        for (&(start, end), (burst_start, burst_end)) in regions.iter().zip([(0.5, 1.2), (2.5, 3.3)]) {
+            assert!(start <= burst_start - 0.25 && start >= burst_start - 0.25 - frame_secs, "{:?}", regions);
+            assert!(end >= burst_end + 0.25 && end <= burst_end + 0.25 + frame_secs, "{:?}", regions);
+        }
+    }
+
+    #[test]
+    fn merges_regions_whose_padding_overlaps() {
+        let regions = detect_regions(&bursts(4.0, &[(0.5, 1.2), (1.5, 2.0)]), RATE, &SegmentConfig::default());
+        assert_eq!(regions.len(), 1, "{:?}", regions);
+        assert!(regions[0].0 < 0.5 && regions[0].1 > 2.0);
+    }
+
+    #[test]
+    fn drops_regions_shorter_than_the_minimum() {
+        let config = SegmentConfig { padding_secs: 0.0, min_clip_secs: 0.5, ..SegmentConfig::default() };
+        let regions = detect_regions(&bursts(4.0, &[(0.5, 0.6), (2.0, 3.0)]), RATE, &config);
+        assert_eq!(regions.len(), 1, "{:?}", regions);
+        assert!(regions[0].0 > 1.5);
+    }
+
+    #[test]
+    fn splits_regions_longer_than_the_maximum() {
+        let config = SegmentConfig { padding_secs: 0.0, max_clip_secs: 1.0, ..SegmentConfig::default() };
+        let regions = detect_regions(&bursts(4.0, &[(0.5, 3.0)]), RATE, &config);
+        assert_eq!(regions.len(), 3, "{:?}", regions);
+        assert!(regions.iter().all(|(start, end)| end - start <= 1.0));
+        assert!(regions.windows(2).all(|pair| (pair[0].1 - pair[1].0).abs() < 1e-9));
+    }
+}
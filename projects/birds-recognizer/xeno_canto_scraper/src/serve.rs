@@ -0,0 +1,492 @@
+use crate::audio::{decode_bytes, AudioBuffer, InputFormat};
+use crate::chunk::{chunk_audio, ChunkConfig};
+use crate::model::Classifier;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
+
+// Local HTTP API around the classifier, for scoring recordings without the
+// PWA. Everything runs in-process, so it works fully offline:
+//
+//   GET  /health          model and extractor in use
+//   GET  /classes         class labels by output index
+//   POST /predict         one audio file as the body -> ranked species
+//   POST /predict/batch   multipart/form-data with several files
+//   POST /predict/stream  one audio file -> newline-delimited JSON, one
+//                         line per window as it is classified
+//
+// Query parameters: top=<n> for all predictions, window=<secs> and
+// hop=<secs> for /predict/stream.
+
+#[derive(Debug, Clone)]
+pub struct ServeConfig {
+    pub address: String,
+    pub threads: usize,         // Connections handled at once
+    pub top: usize,             // Species returned per prediction unless ?top= is given
+    pub max_body_bytes: usize,  // Larger uploads are rejected with 413
+    pub read_timeout: Duration, // Connections idle this long are answered with 408
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            address: "127.0.0.1:8080".to_string(),
+            threads: 4,
+            top: 5,
+            max_body_bytes: 200 * 1024 * 1024,
+            read_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    fn query_value<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, HttpError> {
+        match self.query.iter().find(|(n, _)| n == name) {
+            Some((_, value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| HttpError(400, format!("Invalid value for {}: {}", name, value))),
+            None => Ok(None),
+        }
+    }
+}
+
+// Status code and message, sent as {"error": message}
+#[derive(Debug)]
+struct HttpError(u16, String);
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        _ => "Internal Server Error",
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1e6).round() / 1e3
+}
+
+// Reads that hit the socket timeout are 408, anything else 400
+fn read_error(e: std::io::Error) -> HttpError {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => HttpError(408, "Timed out reading the request".to_string()),
+        _ => HttpError(400, e.to_string()),
+    }
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, HttpError> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(read_error)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// Read the request line, headers and body (Content-Length or chunked)
+fn read_request(stream: &TcpStream, max_body_bytes: usize) -> Result<Request, HttpError> {
+    let mut reader = BufReader::new(stream);
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(HttpError(400, format!("Malformed request line: {}", request_line)));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (name.to_string(), value.to_string())
+        })
+        .collect();
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let mut request = Request { method: method.to_string(), path: path.to_string(), query, headers, body: Vec::new() };
+
+    if request.header("Expect").is_some_and(|v| v.eq_ignore_ascii_case("100-continue")) {
+        let mut stream = stream;
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").map_err(|e| HttpError(400, e.to_string()))?;
+    }
+
+    let too_large = || HttpError(413, format!("Body larger than {} bytes", max_body_bytes));
+    if request.header("Transfer-Encoding").is_some_and(|v| v.to_ascii_lowercase().contains("chunked")) {
+        loop {
+            let size_line = read_line(&mut reader)?;
+            let size = usize::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16)
+                .map_err(|_| HttpError(400, format!("Invalid chunk size: {}", size_line)))?;
+            if size == 0 {
+                // Trailers, if any, end with an empty line
+                while !read_line(&mut reader)?.is_empty() {}
+                break;
+            }
+            let start = request.body.len();
+            let end = start.checked_add(size).ok_or_else(|| HttpError(400, format!("Invalid chunk size: {}", size_line)))?;
+            if end > max_body_bytes {
+                return Err(too_large());
+            }
+            request.body.resize(end, 0);
+            reader.read_exact(&mut request.body[start..]).map_err(read_error)?;
+            read_line(&mut reader)?;
+        }
+    } else if let Some(length) = request.header("Content-Length") {
+        let length: usize = length.parse().map_err(|_| HttpError(400, format!("Invalid Content-Length: {}", length)))?;
+        if length > max_body_bytes {
+            return Err(too_large());
+        }
+        request.body.resize(length, 0);
+        reader.read_exact(&mut request.body).map_err(read_error)?;
+    } else if request.method == "POST" {
+        return Err(HttpError(411, "Content-Length or chunked transfer encoding required".to_string()));
+    }
+    Ok(request)
+}
+
+fn write_response(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        status,
+        status_text(status),
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn write_json(stream: &mut TcpStream, status: u16, value: &Value) -> std::io::Result<()> {
+    write_response(stream, status, "application/json", (value.to_string() + "\n").as_bytes())
+}
+
+// One chunk of a chunked response body
+fn write_chunk(stream: &mut TcpStream, data: &[u8]) -> std::io::Result<()> {
+    write!(stream, "{:x}\r\n", data.len())?;
+    stream.write_all(data)?;
+    stream.write_all(b"\r\n")?;
+    stream.flush()
+}
+
+// Decode an upload, trusting the bytes over the declared content type
+fn decode_upload(bytes: &[u8], content_type: Option<&str>, filename: Option<&str>) -> Result<AudioBuffer, HttpError> {
+    if bytes.is_empty() {
+        return Err(HttpError(400, "Empty body".to_string()));
+    }
+    let format = InputFormat::from_magic(bytes)
+        .or_else(|| content_type.and_then(InputFormat::from_content_type))
+        .or_else(|| filename.and_then(|name| InputFormat::from_extension(std::path::Path::new(name))))
+        .ok_or_else(|| HttpError(415, "Unrecognized audio format (expected MP3, WAV, FLAC or Ogg)".to_string()))?;
+    decode_bytes(bytes, format).map_err(|e| HttpError(422, format!("Could not decode {}: {}", format.extension(), e)))
+}
+
+fn ranked_json(ranked: &[(String, f32)], top: usize) -> Value {
+    ranked.iter().take(top).map(|(species, score)| json!({"species": species, "score": score})).collect()
+}
+
+// Decode and classify one file, with timings
+fn predict(
+    classifier: &Classifier,
+    bytes: &[u8],
+    content_type: Option<&str>,
+    filename: Option<&str>,
+    top: usize,
+) -> Result<Value, HttpError> {
+    let started = Instant::now();
+    let audio = decode_upload(bytes, content_type, filename)?;
+    let decoded = Instant::now();
+    let ranked = classifier.classify(&audio).map_err(|e| HttpError(422, e))?;
+    let finished = Instant::now();
+    Ok(json!({
+        "predictions": ranked_json(&ranked, top),
+        "duration_secs": audio.frames() as f64 / audio.sample_rate.max(1) as f64,
+        "sample_rate": audio.sample_rate,
+        "timing_ms": {
+            "decode": milliseconds(decoded - started),
+            "classify": milliseconds(finished - decoded),
+            "total": milliseconds(finished - started),
+        }
+    }))
+}
+
+struct Part {
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack.get(from..)?.windows(needle.len()).position(|w| w == needle).map(|i| i + from)
+}
+
+// Split a multipart/form-data body into its parts
+fn parse_multipart(body: &[u8], content_type: &str) -> Result<Vec<Part>, HttpError> {
+    let boundary = content_type
+        .split(';')
+        .filter_map(|param| param.trim().strip_prefix("boundary="))
+        .next()
+        .map(|b| b.trim_matches('"'))
+        .ok_or_else(|| HttpError(400, "multipart body without a boundary".to_string()))?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    let mut parts = Vec::new();
+    let mut position = find(body, &delimiter, 0).ok_or_else(|| HttpError(400, "multipart boundary not found".to_string()))?;
+    loop {
+        position += delimiter.len();
+        if body.get(position..position + 2) == Some(b"--") {
+            break;
+        }
+        let header_start = position + 2;
+        let header_end =
+            find(body, b"\r\n\r\n", header_start).ok_or_else(|| HttpError(400, "truncated multipart part".to_string()))?;
+        let data_start = header_end + 4;
+        let next = find(body, &delimiter, data_start).ok_or_else(|| HttpError(400, "unterminated multipart body".to_string()))?;
+        let headers = String::from_utf8_lossy(&body[header_start..header_end]);
+        // The data ends with the CRLF before the next delimiter
+        let data = next
+            .checked_sub(2)
+            .and_then(|data_end| body.get(data_start..data_end))
+            .ok_or_else(|| HttpError(400, "malformed multipart part".to_string()))?;
+
+        let mut part = Part { filename: None, content_type: None, data: data.to_vec() };
+        for line in headers.lines() {
+            let Some((name, value)) = line.split_once(':') else { continue };
+            if name.trim().eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("content-disposition") {
+                part.filename = value
+                    .split(';')
+                    .filter_map(|param| param.trim().strip_prefix("filename="))
+                    .next()
+                    .map(|name| name.trim_matches('"').to_string());
+            }
+        }
+        // Plain form fields carry no file
+        if part.filename.is_some() || part.content_type.as_deref().is_some_and(|t| !t.starts_with("text/")) {
+            parts.push(part);
+        }
+        position = next;
+    }
+    Ok(parts)
+}
+
+fn handle_batch(classifier: &Classifier, request: &Request, top: usize) -> Result<Value, HttpError> {
+    let content_type = request.header("Content-Type").unwrap_or("");
+    if !content_type.starts_with("multipart/form-data") {
+        return Err(HttpError(415, "Expected multipart/form-data with one or more files".to_string()));
+    }
+    let started = Instant::now();
+    let parts = parse_multipart(&request.body, content_type)?;
+    if parts.is_empty() {
+        return Err(HttpError(400, "No files in the upload".to_string()));
+    }
+    let results: Vec<Value> = parts
+        .iter()
+        .map(|part| {
+            let mut result =
+                match predict(classifier, &part.data, part.content_type.as_deref(), part.filename.as_deref(), top) {
+                    Ok(result) => result,
+                    Err(HttpError(_, message)) => json!({ "error": message }),
+                };
+            result["filename"] = json!(part.filename);
+            result
+        })
+        .collect();
+    Ok(json!({ "results": results, "timing_ms": { "total": milliseconds(started.elapsed()) } }))
+}
+
+// Classify overlapping windows, sending each result as soon as it is ready
+fn handle_stream(classifier: &Classifier, request: &Request, stream: &mut TcpStream, top: usize) -> Result<(), HttpError> {
+    let defaults = ChunkConfig::default();
+    let config = ChunkConfig {
+        window_secs: request.query_value("window")?.unwrap_or(defaults.window_secs),
+        hop_secs: request.query_value("hop")?.unwrap_or(defaults.hop_secs),
+        pad_tail: true,
+        min_rms_db: None,
+    };
+    if config.window_secs <= 0.0 || config.hop_secs <= 0.0 {
+        return Err(HttpError(400, "window and hop must be positive".to_string()));
+    }
+    let started = Instant::now();
+    let audio = decode_upload(&request.body, request.header("Content-Type"), None)?;
+    let windows = chunk_audio(&audio, &config);
+
+    let send = |stream: &mut TcpStream, data: &[u8]| -> Result<(), HttpError> {
+        // The client went away; there is no one left to answer
+        write_chunk(stream, data).map_err(|e| HttpError(500, e.to_string()))
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n"
+    )
+    .map_err(|e| HttpError(500, e.to_string()))?;
+
+    let mut classified = 0;
+    for (start, end, window) in &windows {
+        let window_started = Instant::now();
+        let line = match classifier.classify(window) {
+            Ok(ranked) => {
+                classified += 1;
+                json!({
+                    "start_secs": start,
+                    "end_secs": end,
+                    "predictions": ranked_json(&ranked, top),
+                    "timing_ms": milliseconds(window_started.elapsed()),
+                })
+            }
+            Err(e) => json!({ "start_secs": start, "end_secs": end, "error": e }),
+        };
+        send(stream, (line.to_string() + "\n").as_bytes())?;
+    }
+    let summary = json!({
+        "done": true,
+        "windows": windows.len(),
+        "classified": classified,
+        "duration_secs": audio.frames() as f64 / audio.sample_rate as f64,
+        "timing_ms": { "total": milliseconds(started.elapsed()) },
+    });
+    send(stream, (summary.to_string() + "\n").as_bytes())?;
+    send(stream, b"")
+}
+
+fn handle_connection(classifier: &Classifier, config: &ServeConfig, mut stream: TcpStream) {
+    let started = Instant::now();
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    // A client that stops sending would otherwise hold a worker forever
+    if let Err(e) = stream.set_read_timeout(Some(config.read_timeout)) {
+        eprintln!("Error setting a read timeout for {}: {}", peer, e);
+        return;
+    }
+    let request = match read_request(&stream, config.max_body_bytes) {
+        Ok(request) => request,
+        Err(HttpError(status, message)) => {
+            let _ = write_json(&mut stream, status, &json!({ "error": message }));
+            return;
+        }
+    };
+
+    let response = (|| -> Result<Option<Value>, HttpError> {
+        let top = request.query_value("top")?.unwrap_or(config.top);
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/health") => Ok(Some(json!({
+                "status": "ok",
+                "extractor": classifier.kind.name(),
+                "input_dim": classifier.model.input_dim,
+                "classes": classifier.labels.len(),
+            }))),
+            ("GET", "/classes") => Ok(Some(json!({ "classes": classifier.labels }))),
+            ("POST", "/predict") => {
+                predict(classifier, &request.body, request.header("Content-Type"), None, top).map(Some)
+            }
+            ("POST", "/predict/batch") => handle_batch(classifier, &request, top).map(Some),
+            ("POST", "/predict/stream") => handle_stream(classifier, &request, &mut stream, top).map(|_| None),
+            (_, "/health" | "/classes" | "/predict" | "/predict/batch" | "/predict/stream") => {
+                Err(HttpError(405, format!("{} not allowed on {}", request.method, request.path)))
+            }
+            _ => Err(HttpError(404, format!("No such endpoint: {}", request.path))),
+        }
+    })();
+
+    let status = match response {
+        Ok(Some(value)) => {
+            let _ = write_json(&mut stream, 200, &value);
+            200
+        }
+        Ok(None) => 200,
+        Err(HttpError(status, message)) => {
+            let _ = write_json(&mut stream, status, &json!({ "error": message }));
+            status
+        }
+    };
+    println!(
+        "{} {} {} {} ({} bytes, {:.1} ms)",
+        peer,
+        request.method,
+        request.path,
+        status,
+        request.body.len(),
+        milliseconds(started.elapsed())
+    );
+}
+
+// Serve predictions until the process is stopped
+pub fn serve(classifier: Classifier, config: &ServeConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(&config.address)?;
+    println!(
+        "Serving {} classes ({}) on http://{}",
+        classifier.labels.len(),
+        classifier.kind.name(),
+        listener.local_addr()?
+    );
+    println!("  POST /predict, /predict/batch, /predict/stream; GET /health, /classes");
+
+    let classifier = Arc::new(classifier);
+    let config = Arc::new(config.clone());
+    let pool = ThreadPool::new(config.threads.max(1));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        let classifier = Arc::clone(&classifier);
+        let config = Arc::clone(&config);
+        pool.execute(move || handle_connection(&classifier, &config, stream));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=xx";
+
+    #[test]
+    fn multipart_parts_keep_their_data_and_filename() {
+        let body = b"--xx\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\n\r\nRIFF\r\n--xx\r\n\
+                     Content-Disposition: form-data; name=\"note\"\r\n\r\nhello\r\n--xx--\r\n";
+        let parts = parse_multipart(body, CONTENT_TYPE).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].filename.as_deref(), Some("a.wav"));
+        assert_eq!(parts[0].data, b"RIFF");
+    }
+
+    #[test]
+    fn malformed_multipart_is_a_bad_request() {
+        for body in [&b"--xx\r\nContent-Type: audio/wav\r\n\r\n--xx--"[..], b"--xx\r\n\r\n", b"--xx", b"--x"] {
+            let result = parse_multipart(body, CONTENT_TYPE);
+            assert!(matches!(result, Err(HttpError(400, _))), "{:?}", String::from_utf8_lossy(body));
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::audio::read_audio;
+use crate::dataset::{collect_samples, write_npy, write_string, Sample};
+use crate::dsp::{hamming_window, power_spectrum};
+use crate::features::create_mel_filter_bank;
+use crate::jsmath;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use threadpool::ThreadPool;
+
+// Settings for log-mel spectrograms. Framing, windowing, the filter bank and
+// the log step are the ones the MFCC extractor uses, just without the DCT.
+#[derive(Debug, Clone)]
+pub struct LogMelConfig {
+    pub n_fft: usize,      // Frame length, a power of 2
+    pub hop: usize,        // Samples between frame starts
+    pub n_mels: usize,     // Number of Mel bands
+    pub fmin: f64,         // Lowest filter edge (Hz)
+    pub fmax: Option<f64>, // Highest filter edge (Hz), Nyquist if unset
+}
+
+impl Default for LogMelConfig {
+    fn default() -> Self {
+        LogMelConfig {
+            n_fft: 2048,
+            hop: 512,
+            n_mels: 64,
+            fmin: 0.0,
+            fmax: None,
+        }
+    }
+}
+
+// Log-mel spectrogram of a mono signal as frames x n_mels. Only full frames
+// are used, so signals shorter than n_fft give no frames.
+pub fn log_mel_spectrogram(signal: &[f32], sample_rate: u32, config: &LogMelConfig) -> Vec<Vec<f32>> {
+    let fmax = config.fmax.unwrap_or(sample_rate as f64 / 2.0).min(sample_rate as f64 / 2.0);
+    let mel_filters = create_mel_filter_bank(config.n_mels, config.n_fft, sample_rate as f64, config.fmin, fmax);
+
+    let mut frames = Vec::new();
+    let mut start = 0;
+    while start + config.n_fft <= signal.len() {
+        let power = power_spectrum(&hamming_window(&signal[start..start + config.n_fft]));
+        frames.push(
+            mel_filters
+                .iter()
+                .map(|filter| {
+                    let energy: f64 = power.iter().zip(filter).map(|(p, w)| p * w).sum();
+                    jsmath::log(energy + 1e-8) as f32
+                })
+                .collect(),
+        );
+        start += config.hop;
+    }
+    frames
+}
+
+// Packed log-mel file (.bin). Little-endian, strings are a u16 byte length
+// followed by UTF-8 bytes.
+//
+//   [u8; 4]  magic "BRLM"
+//   u32      format version (1)
+//   u32      n_fft
+//   u32      hop
+//   u32      n_mels
+//   f32      fmin (Hz)
+//   f32      fmax (Hz, 0 means Nyquist of each file)
+//   u32      number of labels L
+//   L x string  label names
+//   u32      number of records N
+//   N x record:
+//     u32      label index
+//     string   recording id
+//     string   file name
+//     u32      sample rate
+//     u32      number of frames T
+//     T x n_mels f32  log-mel energies, frame by frame
+pub const LOGMEL_MAGIC: &[u8; 4] = b"BRLM";
+pub const LOGMEL_VERSION: u32 = 1;
+
+// Where and how to write the spectrograms
+pub enum LogMelOutput {
+    Npy(PathBuf),    // One .npy per file in this directory, plus index.csv
+    Packed(PathBuf), // A single packed file
+}
+
+// Open output of an export. Spectrograms are written one at a time as they
+// are computed; the packed record count is filled in when the export ends.
+enum LogMelWriter {
+    Npy { dir: PathBuf, index: Box<csv::Writer<File>> },
+    Packed { writer: BufWriter<File>, count_offset: u64 },
+}
+
+impl LogMelWriter {
+    fn create(output: &LogMelOutput, config: &LogMelConfig, labels: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        match output {
+            LogMelOutput::Npy(dir) => {
+                std::fs::create_dir_all(dir)?;
+                let mut index = csv::Writer::from_path(dir.join("index.csv"))?;
+                index.write_record(["file", "species", "recording_id", "source_file", "sample_rate", "frames"])?;
+                Ok(LogMelWriter::Npy { dir: dir.clone(), index: Box::new(index) })
+            }
+            LogMelOutput::Packed(path) => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                writer.write_all(LOGMEL_MAGIC)?;
+                writer.write_all(&LOGMEL_VERSION.to_le_bytes())?;
+                writer.write_all(&(config.n_fft as u32).to_le_bytes())?;
+                writer.write_all(&(config.hop as u32).to_le_bytes())?;
+                writer.write_all(&(config.n_mels as u32).to_le_bytes())?;
+                writer.write_all(&(config.fmin as f32).to_le_bytes())?;
+                writer.write_all(&(config.fmax.unwrap_or(0.0) as f32).to_le_bytes())?;
+                writer.write_all(&(labels.len() as u32).to_le_bytes())?;
+                for label in labels {
+                    write_string(&mut writer, label)?;
+                }
+                let count_offset = writer.stream_position()?;
+                writer.write_all(&0u32.to_le_bytes())?;
+                Ok(LogMelWriter::Packed { writer, count_offset })
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        sample: &Sample,
+        label: u32,
+        sample_rate: u32,
+        spectrogram: &[Vec<f32>],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let source_file = sample.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        match self {
+            LogMelWriter::Npy { dir, index } => {
+                let stem = sample.path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                let npy_name = format!("{}.npy", stem);
+                let n_mels = spectrogram.first().map_or(0, Vec::len);
+                let data: Vec<u8> = spectrogram.iter().flatten().flat_map(|v| v.to_le_bytes()).collect();
+                write_npy(&dir.join(&npy_name), "<f4", &[spectrogram.len(), n_mels], &data)?;
+                index.write_record([
+                    &npy_name,
+                    &sample.species,
+                    &sample.recording_id,
+                    &source_file,
+                    &sample_rate.to_string(),
+                    &spectrogram.len().to_string(),
+                ])?;
+            }
+            LogMelWriter::Packed { writer, .. } => {
+                writer.write_all(&label.to_le_bytes())?;
+                write_string(writer, &sample.recording_id)?;
+                write_string(writer, &source_file)?;
+                writer.write_all(&sample_rate.to_le_bytes())?;
+                writer.write_all(&(spectrogram.len() as u32).to_le_bytes())?;
+                for value in spectrogram.iter().flatten() {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self, records: u32) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            LogMelWriter::Npy { mut index, .. } => index.flush()?,
+            LogMelWriter::Packed { mut writer, count_offset } => {
+                writer.seek(SeekFrom::Start(count_offset))?;
+                writer.write_all(&records.to_le_bytes())?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Compute log-mel spectrograms for every recording (or clip) in the catalog,
+// writing each one in catalog order as soon as it and those before it are done
+pub fn export_log_mel(
+    output_dir: &str,
+    use_clips: bool,
+    config: &LogMelConfig,
+    output: &LogMelOutput,
+    threads: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !config.n_fft.is_power_of_two() || config.hop == 0 || config.n_mels == 0 {
+        return Err("n_fft must be a power of 2 and hop and n_mels must be positive".into());
+    }
+
+    let samples = collect_samples(output_dir, use_clips)?;
+    let labels: Vec<String> = samples
+        .iter()
+        .map(|s| s.species.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let mut writer = LogMelWriter::create(output, config, &labels)?;
+
+    // Every file reports back, with None if it was skipped, so the writer
+    // knows when the next one in order is done
+    let pool = ThreadPool::new(threads.max(1));
+    let (sender, receiver) = mpsc::channel();
+    for (index, sample) in samples.iter().cloned().enumerate() {
+        let sender = sender.clone();
+        let config = config.clone();
+        pool.execute(move || {
+            let result = match read_audio(&sample.path) {
+                Ok(audio) => {
+                    let spectrogram = log_mel_spectrogram(&audio.channel(0), audio.sample_rate, &config);
+                    if spectrogram.is_empty() {
+                        println!("Skipping {}: shorter than one frame", sample.path.display());
+                        None
+                    } else {
+                        Some((audio.sample_rate, spectrogram))
+                    }
+                }
+                Err(e) => {
+                    println!("Error reading {}: {}", sample.path.display(), e);
+                    None
+                }
+            };
+            let _ = sender.send((index, result));
+        });
+    }
+    drop(sender);
+
+    // Results that finished ahead of an earlier file wait here
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    let mut written = 0;
+    for (index, result) in receiver {
+        pending.insert(index, result);
+        while let Some(result) = pending.remove(&next) {
+            if let Some((sample_rate, spectrogram)) = result {
+                let sample = &samples[next];
+                let label = labels.iter().position(|l| *l == sample.species).unwrap() as u32;
+                writer.write(sample, label, sample_rate, &spectrogram)?;
+                written += 1;
+            }
+            next += 1;
+        }
+    }
+    pool.join();
+    writer.finish(written)?;
+
+    let destination = match output {
+        LogMelOutput::Npy(dir) | LogMelOutput::Packed(dir) => dir,
+    };
+    println!(
+        "Wrote {} of {} log-mel spectrograms ({} bands) to {}",
+        written,
+        samples.len(),
+        config.n_mels,
+        destination.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{write_wav, AudioBuffer};
+    use crate::dataset::read_string;
+    use crate::RecordingMetadata;
+    use std::io::Read;
+
+    fn small_config() -> LogMelConfig {
+        LogMelConfig { n_fft: 256, hop: 128, n_mels: 16, ..LogMelConfig::default() }
+    }
+
+    fn read_u32(reader: &mut impl Read) -> u32 {
+        let mut bytes = [0; 4];
+        reader.read_exact(&mut bytes).unwrap();
+        u32::from_le_bytes(bytes)
+    }
+
+    #[test]
+    fn spectrogram_has_one_row_per_full_frame() {
+        let config = small_config();
+        let signal: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.3).sin()).collect();
+        let spectrogram = log_mel_spectrogram(&signal, 22050, &config);
+        // Frames start at 0, 128, ..., 640; one at 768 would run past the end
+        assert_eq!(spectrogram.len(), (1000 - 256) / 128 + 1);
+        assert!(spectrogram.iter().all(|frame| frame.len() == 16 && frame.iter().all(|v| v.is_finite())));
+        assert!(log_mel_spectrogram(&signal[..255], 22050, &config).is_empty());
+    }
+
+    #[test]
+    fn packed_export_keeps_catalog_order_across_threads() {
+        let dir = std::env::temp_dir().join(format!("spectrogram_order_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Longer files take longer, so later short ones finish first. The
+        // third is shorter than a frame and is skipped.
+        let lengths = [4000, 300, 100, 2000, 600, 1200];
+        let metadata: Vec<RecordingMetadata> = lengths
+            .iter()
+            .enumerate()
+            .map(|(i, &length)| {
+                let filename = format!("XC{}.wav", i);
+                let samples = (0..length).map(|n| (n as f32 * 0.01 * (i + 1) as f32).sin() * 0.5).collect();
+                write_wav(&dir.join(&filename), &AudioBuffer { sample_rate: 22050, channels: 1, samples }).unwrap();
+                RecordingMetadata {
+                    id: i.to_string(),
+                    filename,
+                    species: if i % 2 == 0 { "wren" } else { "robin" }.to_string(),
+                    is_downloaded: true,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        crate::write_metadata_csv(&dir.join("metadata.csv"), &metadata).unwrap();
+
+        let packed = dir.join("logmel.bin");
+        let output_dir = dir.to_str().unwrap();
+        export_log_mel(output_dir, false, &small_config(), &LogMelOutput::Packed(packed.clone()), 4).unwrap();
+
+        let mut reader = std::io::BufReader::new(File::open(&packed).unwrap());
+        let mut header = [0; 28];
+        reader.read_exact(&mut header).unwrap();
+        assert_eq!(&header[..4], LOGMEL_MAGIC);
+        let labels: Vec<String> = (0..read_u32(&mut reader)).map(|_| read_string(&mut reader).unwrap()).collect();
+        assert_eq!(labels, ["robin", "wren"]);
+
+        let expected = [0, 1, 3, 4, 5];
+        assert_eq!(read_u32(&mut reader) as usize, expected.len());
+        for i in expected {
+            let label = read_u32(&mut reader);
+            assert_eq!(read_string(&mut reader).unwrap(), i.to_string());
+            assert_eq!(read_string(&mut reader).unwrap(), format!("XC{}.wav", i));
+            assert_eq!(read_u32(&mut reader), 22050);
+            let frames = read_u32(&mut reader) as usize;
+            assert_eq!(label, if i % 2 == 0 { 1 } else { 0 });
+            assert_eq!(frames, (lengths[i] - 256) / 128 + 1);
+            let mut data = vec![0; frames * 16 * 4];
+            reader.read_exact(&mut data).unwrap();
+        }
+        assert_eq!(reader.read(&mut [0]).unwrap(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,394 @@
+use crate::augment::Rng;
+use crate::clips::{clips_csv_path, load_clips};
+use crate::mapping::ClassMapping;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+pub const SPLITS: [&str; 3] = ["train", "val", "test"];
+
+// What recordings must stay together on one side of the split
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Recording, // Each recording on its own (clips still follow their recording)
+    Recordist, // All recordings by the same recordist
+    Site,      // All recordings from the same location
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recording" | "id" => Ok(GroupBy::Recording),
+            "recordist" => Ok(GroupBy::Recordist),
+            "site" | "location" => Ok(GroupBy::Site),
+            _ => Err(format!("Unknown grouping: {} (expected recording, recordist or site)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SplitConfig {
+    pub val_fraction: f64,
+    pub test_fraction: f64,
+    pub group_by: GroupBy,
+    pub seed: u64,       // Breaks ties between equally sized groups
+    pub reassign: bool,  // Discard existing assignments instead of keeping them
+}
+
+impl Default for SplitConfig {
+    fn default() -> Self {
+        SplitConfig {
+            val_fraction: 0.1,
+            test_fraction: 0.1,
+            group_by: GroupBy::Recording,
+            seed: 0,
+            reassign: false,
+        }
+    }
+}
+
+fn normalize_key(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+// Assign every downloaded recording to train, val or test. Groups of
+// recordings (see GroupBy) are placed greedily, largest first, into the
+// split furthest below its target share for the species in the group, so
+// each species is divided roughly in the requested proportions. Recordings
+// that already have a split keep it unless `reassign` is set, which keeps
+// test sets stable as the catalog grows. The assignment is stored in
+// metadata.csv and listed in <dir>/splits/<split>.csv and
+// <dir>/splits/<split>_clips.csv; clips follow their parent recording and
+// augmented clips are only listed for training.
+pub fn split_directory(output_dir: &str, config: &SplitConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let train_fraction = 1.0 - config.val_fraction - config.test_fraction;
+    if config.val_fraction < 0.0 || config.test_fraction < 0.0 || train_fraction <= 0.0 {
+        return Err("val and test fractions must be non-negative and leave room for training".into());
+    }
+    let targets = [train_fraction, config.val_fraction, config.test_fraction];
+
+    let metadata_path = Path::new(output_dir).join("metadata.csv");
+    let mut metadata = crate::load_existing_metadata(&metadata_path)?;
+    let recordings: Vec<usize> = (0..metadata.len()).filter(|&i| metadata[i].is_downloaded).collect();
+    if recordings.is_empty() {
+        println!("No downloaded recordings in {}", output_dir);
+        return Ok(());
+    }
+
+    // Recordings without a recordist or location form a group of their own
+    let mut missing_keys = 0;
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for &i in &recordings {
+        let meta = &metadata[i];
+        let key = match config.group_by {
+            GroupBy::Recording => String::new(),
+            GroupBy::Recordist => normalize_key(&meta.recordist),
+            GroupBy::Site => normalize_key(&meta.location),
+        };
+        let key = if key.is_empty() {
+            if config.group_by != GroupBy::Recording {
+                missing_keys += 1;
+            }
+            format!("id:{}", meta.id)
+        } else {
+            key
+        };
+        groups.entry(key).or_default().push(i);
+    }
+    if missing_keys > 0 {
+        println!(
+            "{} recordings have no {} and are grouped on their own (re-run the scraper to fill them in)",
+            missing_keys,
+            if config.group_by == GroupBy::Recordist { "recordist" } else { "location" }
+        );
+    }
+
+    // Splits are balanced per class, after the species mapping
+    let mapping = ClassMapping::load(output_dir)?;
+    let classes: Vec<String> = metadata.iter().map(|m| mapping.recording_class(m)).collect();
+    let mut totals: HashMap<String, f64> = HashMap::new();
+    for &i in &recordings {
+        *totals.entry(classes[i].clone()).or_insert(0.0) += 1.0;
+    }
+    let mut counts: Vec<HashMap<String, f64>> = vec![HashMap::new(); SPLITS.len()];
+    let mut group_splits: BTreeMap<&str, usize> = BTreeMap::new();
+
+    // Keep existing assignments, taking the majority when grouping has
+    // brought together recordings from different splits
+    let mut conflicts = 0;
+    if !config.reassign {
+        for (key, members) in &groups {
+            let mut votes = [0usize; SPLITS.len()];
+            for &i in members {
+                if let Some(s) = SPLITS.iter().position(|s| *s == metadata[i].split) {
+                    votes[s] += 1;
+                }
+            }
+            if votes.iter().sum::<usize>() == 0 {
+                continue;
+            }
+            if votes.iter().filter(|&&v| v > 0).count() > 1 {
+                conflicts += 1;
+            }
+            let split = (0..SPLITS.len()).max_by_key(|&s| (votes[s], std::cmp::Reverse(s))).unwrap();
+            group_splits.insert(key, split);
+            for &i in members {
+                *counts[split].entry(classes[i].clone()).or_insert(0.0) += 1.0;
+            }
+        }
+    }
+    if conflicts > 0 {
+        println!("{} groups had recordings in more than one split and were merged into one", conflicts);
+    }
+    let kept_groups = group_splits.len();
+
+    // Largest groups first; they are the hardest to fit
+    let mut pending: Vec<(&String, &Vec<usize>)> =
+        groups.iter().filter(|(key, _)| !group_splits.contains_key(key.as_str())).collect();
+    pending.sort_by_key(|(key, members)| {
+        (std::cmp::Reverse(members.len()), Rng::for_item(config.seed, key).next_u64())
+    });
+
+    for (key, members) in pending {
+        let mut group_species: HashMap<&str, f64> = HashMap::new();
+        for &i in members {
+            *group_species.entry(classes[i].as_str()).or_insert(0.0) += 1.0;
+        }
+
+        // How far each split is below its target share for these species
+        let deficit = |s: usize| -> f64 {
+            group_species
+                .iter()
+                .map(|(species, n)| {
+                    let total = totals[*species];
+                    let current = counts[s].get(*species).copied().unwrap_or(0.0);
+                    n * (targets[s] * total - current) / total
+                })
+                .sum()
+        };
+        let split = (0..SPLITS.len())
+            .filter(|&s| targets[s] > 0.0)
+            .max_by(|&a, &b| deficit(a).total_cmp(&deficit(b)).then(b.cmp(&a)))
+            .unwrap();
+
+        group_splits.insert(key.as_str(), split);
+        for (species, n) in group_species {
+            *counts[split].entry(species.to_string()).or_insert(0.0) += n;
+        }
+    }
+
+    // Store the assignment in the catalog
+    let mut recording_splits: HashMap<String, usize> = HashMap::new();
+    let mut recording_groups: HashMap<String, String> = HashMap::new();
+    for (key, members) in &groups {
+        let split = group_splits[key.as_str()];
+        for &i in members {
+            metadata[i].split = SPLITS[split].to_string();
+            recording_splits.insert(metadata[i].id.clone(), split);
+            recording_groups.insert(metadata[i].id.clone(), key.clone());
+        }
+    }
+    crate::write_metadata_csv(&metadata_path, &metadata)?;
+
+    // Per-split manifests
+    let splits_dir = Path::new(output_dir).join("splits");
+    std::fs::create_dir_all(&splits_dir)?;
+    for (s, name) in SPLITS.iter().enumerate() {
+        let mut writer = csv::Writer::from_path(splits_dir.join(format!("{}.csv", name)))?;
+        writer.write_record(["filename", "species", "recording_id", "group"])?;
+        for &i in &recordings {
+            let meta = &metadata[i];
+            if recording_splits[&meta.id] == s {
+                writer.write_record([&meta.filename, &classes[i], &meta.id, &recording_groups[&meta.id]])?;
+            }
+        }
+        writer.flush()?;
+    }
+
+    let clips = load_clips(&clips_csv_path(output_dir))?;
+    let mut unassigned_clips = 0;
+    let mut held_out_augmented = 0;
+    if !clips.is_empty() {
+        let mut writers = SPLITS
+            .iter()
+            .map(|name| csv::Writer::from_path(splits_dir.join(format!("{}_clips.csv", name))))
+            .collect::<Result<Vec<_>, _>>()?;
+        for writer in &mut writers {
+            writer.write_record(["filename", "species", "recording_id", "group", "source"])?;
+        }
+        for clip in &clips {
+            let Some(&split) = recording_splits.get(&clip.parent_id) else {
+                unassigned_clips += 1;
+                continue;
+            };
+            // Augmented copies of held-out recordings would only inflate the scores
+            if clip.source == "augment" && split != 0 {
+                held_out_augmented += 1;
+                continue;
+            }
+            writers[split].write_record([
+                &format!("clips/{}", clip.filename),
+                &clip.species,
+                &clip.parent_id,
+                &recording_groups[&clip.parent_id],
+                &clip.source,
+            ])?;
+        }
+        for writer in &mut writers {
+            writer.flush()?;
+        }
+    }
+
+    // Per-species summary
+    let species: BTreeSet<&String> = totals.keys().collect();
+    println!("{:<32} {:>6} {:>6} {:>6}", "species", "train", "val", "test");
+    for name in species {
+        let row: Vec<f64> = counts.iter().map(|c| c.get(name).copied().unwrap_or(0.0)).collect();
+        println!("{:<32} {:>6} {:>6} {:>6}", name, row[0], row[1], row[2]);
+        let missing: Vec<&str> = (1..SPLITS.len())
+            .filter(|&s| targets[s] > 0.0 && row[s] == 0.0)
+            .map(|s| SPLITS[s])
+            .collect();
+        if !missing.is_empty() {
+            println!("  warning: no {} recordings for {} (too few groups)", missing.join(" or "), name);
+        }
+    }
+    println!(
+        "Assigned {} recordings in {} groups ({} kept from a previous split), manifests in {}",
+        recordings.len(),
+        groups.len(),
+        kept_groups,
+        splits_dir.display()
+    );
+    if held_out_augmented > 0 {
+        println!("Left {} augmented clips of val/test recordings out of the manifests", held_out_augmented);
+    }
+    if unassigned_clips > 0 {
+        println!("{} clips belong to recordings that are not downloaded and were not listed", unassigned_clips);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clips::{write_clips_csv, ClipMetadata};
+    use crate::RecordingMetadata;
+
+    // Catalog of downloaded (species, recordist) recordings in a fresh directory
+    fn catalog(name: &str, recordings: &[(&str, &str)]) -> String {
+        let dir = std::env::temp_dir().join(format!("split_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let metadata: Vec<RecordingMetadata> = recordings
+            .iter()
+            .enumerate()
+            .map(|(i, &(species, recordist))| RecordingMetadata {
+                id: i.to_string(),
+                filename: format!("{}_{}.wav", species, i),
+                species: species.to_string(),
+                is_downloaded: true,
+                recordist: recordist.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        crate::write_metadata_csv(&dir.join("metadata.csv"), &metadata).unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    fn clip(filename: &str, parent_id: &str, source: &str) -> ClipMetadata {
+        ClipMetadata {
+            filename: filename.to_string(),
+            species: "wren".to_string(),
+            parent_id: parent_id.to_string(),
+            parent_filename: String::new(),
+            start_secs: 0.0,
+            end_secs: 1.0,
+            source: source.to_string(),
+            augmentation: String::new(),
+            verified: false,
+            low_hz: None,
+            high_hz: None,
+        }
+    }
+
+    fn splits(dir: &str) -> Vec<RecordingMetadata> {
+        crate::load_existing_metadata(&Path::new(dir).join("metadata.csv")).unwrap()
+    }
+
+    fn manifest_column(dir: &str, name: &str, column: usize) -> Vec<String> {
+        let mut reader = csv::Reader::from_path(Path::new(dir).join("splits").join(name)).unwrap();
+        reader.records().map(|r| r.unwrap()[column].to_string()).collect()
+    }
+
+    #[test]
+    fn each_species_is_divided_in_the_requested_proportions() {
+        let recordings: Vec<(&str, &str)> = (0..20).flat_map(|_| [("wren", ""), ("robin", "")]).collect();
+        let dir = catalog("stratified", &recordings);
+        split_directory(&dir, &SplitConfig::default()).unwrap();
+
+        let metadata = splits(&dir);
+        for species in ["wren", "robin"] {
+            let count = |split: &str| metadata.iter().filter(|m| m.species == species && m.split == split).count();
+            assert_eq!((count("train"), count("val"), count("test")), (16, 2, 2), "{}", species);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn groups_and_their_clips_stay_on_one_side() {
+        let recordings: Vec<(&str, &str)> = (0..24).map(|i| ("wren", ["Ann", "Bo", "Cy", "Di", "Ed", "Flo"][i % 6])).collect();
+        let dir = catalog("grouped", &recordings);
+        let clips: Vec<ClipMetadata> = (0..24)
+            .flat_map(|i| {
+                let id = i.to_string();
+                [clip(&format!("wren_{}.wav", 2 * i), &id, "segment"), clip(&format!("wren_{}.wav", 2 * i + 1), &id, "augment")]
+            })
+            .collect();
+        write_clips_csv(&clips_csv_path(&dir), &clips).unwrap();
+        let config = SplitConfig { val_fraction: 0.2, test_fraction: 0.2, group_by: GroupBy::Recordist, ..Default::default() };
+        split_directory(&dir, &config).unwrap();
+
+        let metadata = splits(&dir);
+        let mut recordist_splits: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        for meta in &metadata {
+            recordist_splits.entry(meta.recordist.as_str()).or_default().insert(meta.split.as_str());
+        }
+        assert!(recordist_splits.values().all(|s| s.len() == 1), "{:?}", recordist_splits);
+        for split in SPLITS {
+            assert!(metadata.iter().any(|m| m.split == split), "no {} recordings", split);
+        }
+
+        // Clips are listed with their recording; augmented ones only for training
+        for split in SPLITS {
+            let parents = manifest_column(&dir, &format!("{}_clips.csv", split), 2);
+            let sources = manifest_column(&dir, &format!("{}_clips.csv", split), 4);
+            for (parent, source) in parents.iter().zip(&sources) {
+                assert_eq!(metadata.iter().find(|m| &m.id == parent).unwrap().split, split);
+                assert!(split == "train" || source != "augment");
+            }
+            let recordings_in_split = metadata.iter().filter(|m| m.split == split).count();
+            let expected = if split == "train" { 2 * recordings_in_split } else { recordings_in_split };
+            assert_eq!(parents.len(), expected, "{}", split);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn earlier_assignments_are_kept_unless_reassigned() {
+        let recordings: Vec<(&str, &str)> = (0..10).map(|_| ("wren", "")).collect();
+        let dir = catalog("kept", &recordings);
+        let mut metadata = splits(&dir);
+        for meta in &mut metadata {
+            meta.split = "test".to_string();
+        }
+        crate::write_metadata_csv(&Path::new(&dir).join("metadata.csv"), &metadata).unwrap();
+
+        split_directory(&dir, &SplitConfig::default()).unwrap();
+        assert!(splits(&dir).iter().all(|m| m.split == "test"));
+        split_directory(&dir, &SplitConfig { reassign: true, ..Default::default() }).unwrap();
+        assert_eq!(splits(&dir).iter().filter(|m| m.split == "train").count(), 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
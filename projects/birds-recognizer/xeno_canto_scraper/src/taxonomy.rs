@@ -0,0 +1,450 @@
+use crate::clips::{class_key, clip_counters, clips_csv_path, load_clips, relabel_clip, write_clips_csv};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// Checklist categories that are below species rank (eBird/Clements use
+// "issf" and "group (polytypic)" for identifiable subspecies)
+const SUBSPECIES_CATEGORIES: [&str; 5] = ["issf", "subspecies", "form", "group (polytypic)", "group (monotypic)"];
+
+// Which name of a taxon becomes its class key. eBird codes are not
+// offered: their digits ("comgal1") cannot appear in clip filenames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStyle {
+    Common,     // barn_swallow, what format_species_name gives for the title
+    Scientific, // hirundo_rustica
+}
+
+impl std::str::FromStr for KeyStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "common" => Ok(KeyStyle::Common),
+            "scientific" => Ok(KeyStyle::Scientific),
+            "code" => Err("species codes cannot be class keys: clip filenames only hold letters and underscores (use common or scientific)".to_string()),
+            _ => Err(format!("Unknown class key: {} (expected common or scientific)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Taxon {
+    pub scientific_name: String,
+    pub common_name: String,
+    pub code: String,
+    pub parent: Option<usize>, // Species a subspecies belongs to
+}
+
+#[derive(Debug, Clone)]
+pub struct Taxonomy {
+    pub taxa: Vec<Taxon>,
+    names: HashMap<String, usize>, // Normalized names, codes and synonyms
+    pub collapse_subspecies: bool, // Resolve subspecies to their species
+    pub key_style: KeyStyle,
+}
+
+// Case, hyphens, apostrophes and spacing do not tell taxa apart:
+// "Grey-headed Woodpecker" and "grey headed woodpecker" are the same bird
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase()
+        .replace(['-', '_'], " ")
+        .replace(['\'', '’', '.'], "")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Genus and species of a scientific name, dropping any subspecies epithet
+fn binomial(scientific_name: &str) -> Option<String> {
+    let normalized = normalize_name(scientific_name);
+    let mut words = normalized.split(' ');
+    Some(format!("{} {}", words.next()?, words.next()?))
+}
+
+impl Taxonomy {
+    // Load a checklist CSV. Columns are found by header, so exports of the
+    // IOC, eBird/Clements and custom lists all work: a scientific name
+    // column is required, common name, species code, category and a
+    // ';'-separated synonyms column are used when present.
+    pub fn load(path: &Path) -> Result<Taxonomy, Box<dyn std::error::Error>> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+        let headers: Vec<String> = reader.headers()?.iter().map(|h| h.trim().to_lowercase()).collect();
+        let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.as_str()));
+        let scientific_column = column(&["scientific_name", "scientific name", "sci_name", "scientific"])
+            .ok_or(format!("{} has no scientific name column", path.display()))?;
+        let common_column = column(&["common_name", "common name", "english_name", "english name", "primary_com_name", "english"]);
+        let code_column = column(&["species_code", "code", "ebird_code"]);
+        let synonyms_column = column(&["synonyms", "aliases"]);
+        let category_column = column(&["category", "rank"]);
+
+        let field = |record: &csv::StringRecord, column: Option<usize>| {
+            column.and_then(|c| record.get(c)).unwrap_or("").trim().to_string()
+        };
+        let mut taxa = Vec::new();
+        let mut subspecies = Vec::new();
+        let mut aliases: Vec<(String, usize)> = Vec::new();
+        for result in reader.records() {
+            let record = result?;
+            let scientific_name = field(&record, Some(scientific_column));
+            if scientific_name.is_empty() {
+                continue;
+            }
+            let category = field(&record, category_column).to_lowercase();
+            let index = taxa.len();
+            subspecies.push(
+                SUBSPECIES_CATEGORIES.contains(&category.as_str()) || normalize_name(&scientific_name).split(' ').count() > 2,
+            );
+            for synonym in field(&record, synonyms_column).split(';').filter(|s| !s.trim().is_empty()) {
+                aliases.push((normalize_name(synonym), index));
+            }
+            taxa.push(Taxon {
+                common_name: field(&record, common_column),
+                code: field(&record, code_column),
+                scientific_name,
+                parent: None,
+            });
+        }
+
+        // Scientific names win over common names, codes and synonyms when
+        // the same string names two taxa; within a kind, an ambiguous name
+        // resolves to nothing
+        let mut names = HashMap::new();
+        let kinds: [Vec<(String, usize)>; 3] = [
+            taxa.iter().enumerate().map(|(i, t)| (normalize_name(&t.scientific_name), i)).collect(),
+            taxa.iter()
+                .enumerate()
+                .flat_map(|(i, t)| [(normalize_name(&t.common_name), i), (normalize_name(&t.code), i)])
+                .filter(|(name, _)| !name.is_empty())
+                .collect(),
+            aliases,
+        ];
+        for kind in kinds {
+            let mut found: HashMap<String, usize> = HashMap::new();
+            let mut ambiguous = HashSet::new();
+            for (name, index) in kind {
+                // A subspecies sharing its species' common name is not a clash
+                match found.get(&name) {
+                    Some(&other) if other != index => match (subspecies[other], subspecies[index]) {
+                        (false, true) => {}
+                        (true, false) => {
+                            found.insert(name, index);
+                        }
+                        _ => {
+                            ambiguous.insert(name);
+                        }
+                    },
+                    _ => {
+                        found.insert(name, index);
+                    }
+                }
+            }
+            for (name, index) in found {
+                if !ambiguous.contains(&name) {
+                    names.entry(name).or_insert(index);
+                }
+            }
+        }
+
+        // Link each subspecies to the species of its binomial
+        for (index, is_subspecies) in subspecies.iter().enumerate() {
+            if *is_subspecies
+                && let Some(species) = binomial(&taxa[index].scientific_name)
+                && let Some(&parent) = names.get(&species)
+                && parent != index
+            {
+                taxa[index].parent = Some(parent);
+            }
+        }
+
+        Ok(Taxonomy { taxa, names, collapse_subspecies: false, key_style: KeyStyle::Common })
+    }
+
+    // Find the taxon of a recording: by scientific name, then by its
+    // binomial for subspecies the checklist does not list, then by common
+    // name, code or synonym
+    pub fn resolve(&self, common_name: &str, scientific_name: &str) -> Option<usize> {
+        let scientific = normalize_name(scientific_name);
+        let index = self
+            .names
+            .get(&scientific)
+            .or_else(|| binomial(&scientific).and_then(|species| self.names.get(&species)))
+            .or_else(|| self.names.get(&normalize_name(common_name)))
+            .copied()?;
+        match self.taxa[index].parent {
+            Some(parent) if self.collapse_subspecies => Some(parent),
+            _ => Some(index),
+        }
+    }
+
+    // Stable class key of a taxon in the [a-z_] alphabet of clip names,
+    // falling back to the scientific name when the common name is missing
+    // or has no Latin letters
+    pub fn key(&self, index: usize) -> String {
+        let taxon = &self.taxa[index];
+        let names = match self.key_style {
+            KeyStyle::Common => [&taxon.common_name, &taxon.scientific_name],
+            KeyStyle::Scientific => [&taxon.scientific_name, &taxon.scientific_name],
+        };
+        names
+            .iter()
+            .find_map(|name| class_key(name))
+            .unwrap_or_else(|| crate::format_species_name(&taxon.scientific_name))
+    }
+
+    // Class key for a recording, None when the checklist does not know it
+    pub fn class_key(&self, common_name: &str, scientific_name: &str) -> Option<String> {
+        self.resolve(common_name, scientific_name).map(|index| self.key(index))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NormalizeTaxonomyConfig {
+    pub dry_run: bool, // Only write the report
+}
+
+// Move a recording and every other format of it (same stem) to a new stem
+fn rename_recording(output_dir: &str, old_stem: &str, new_stem: &str) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in std::fs::read_dir(output_dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            continue;
+        };
+        if let Some(rest) = name.strip_prefix(old_stem)
+            && rest.starts_with('.')
+            && path.is_file()
+        {
+            std::fs::rename(&path, Path::new(output_dir).join(format!("{}{}", new_stem, rest)))?;
+        }
+    }
+    Ok(())
+}
+
+// Map every recording of the directory to its checklist taxon and give it
+// the taxon's class key, renaming recordings and clips whose key changes.
+// Writes taxonomy.csv, with the taxon of each recording, and reports the
+// names the checklist did not resolve.
+pub fn normalize_taxonomy(
+    output_dir: &str,
+    taxonomy: &Taxonomy,
+    config: &NormalizeTaxonomyConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata_path = Path::new(output_dir).join("metadata.csv");
+    let mut metadata = crate::load_existing_metadata(&metadata_path)?;
+
+    let mut report = csv::Writer::from_path(Path::new(output_dir).join("taxonomy.csv"))?;
+    report.write_record([
+        "id", "filename", "common_name", "scientific_name", "species", "status", "taxon_scientific_name", "taxon_common_name",
+        "taxon_code", "class_key",
+    ])?;
+    let mut unresolved: HashMap<(String, String), usize> = HashMap::new();
+    let mut changes: HashMap<(String, String), usize> = HashMap::new();
+    let mut new_keys = Vec::new();
+    for meta in &metadata {
+        let index = taxonomy.resolve(&meta.common_name, &meta.scientific_name);
+        let (status, taxon, key) = match index {
+            Some(index) => {
+                let key = taxonomy.key(index);
+                (if key == meta.species { "resolved" } else { "renamed" }, Some(&taxonomy.taxa[index]), key)
+            }
+            None => {
+                *unresolved.entry((meta.common_name.clone(), meta.scientific_name.clone())).or_insert(0) += 1;
+                ("unresolved", None, meta.species.clone())
+            }
+        };
+        if key != meta.species {
+            *changes.entry((meta.species.clone(), key.clone())).or_insert(0) += 1;
+        }
+        report.write_record([
+            meta.id.as_str(),
+            &meta.filename,
+            &meta.common_name,
+            &meta.scientific_name,
+            &meta.species,
+            status,
+            taxon.map_or("", |t| t.scientific_name.as_str()),
+            taxon.map_or("", |t| t.common_name.as_str()),
+            taxon.map_or("", |t| t.code.as_str()),
+            &key,
+        ])?;
+        new_keys.push(key);
+    }
+    report.flush()?;
+
+    let mut changes: Vec<_> = changes.into_iter().collect();
+    changes.sort();
+    for ((from, to), count) in &changes {
+        println!("{} -> {} ({} recordings)", from, to, count);
+    }
+    let mut unresolved: Vec<_> = unresolved.into_iter().collect();
+    unresolved.sort();
+    for ((common_name, scientific_name), count) in &unresolved {
+        println!("Unresolved: {} ({}), {} recordings", common_name, scientific_name, count);
+    }
+    let merged: HashSet<&String> = new_keys.iter().collect();
+    let before: HashSet<&String> = metadata.iter().map(|m| &m.species).collect();
+    println!(
+        "{} of {} recordings resolved; {} classes become {}",
+        metadata.len() - unresolved.iter().map(|(_, count)| count).sum::<usize>(),
+        metadata.len(),
+        before.len(),
+        merged.len()
+    );
+    if config.dry_run {
+        println!("Report written to {}", Path::new(output_dir).join("taxonomy.csv").display());
+        return Ok(());
+    }
+
+    // Next free number per species, as load_or_create_metadata counts them
+    let mut counters: HashMap<String, usize> = HashMap::new();
+    for meta in &metadata {
+        if let Some(rest) = meta.filename.strip_prefix(&format!("{}_", meta.species))
+            && let Some((number, _)) = rest.split_once('.')
+            && let Ok(number) = number.parse::<usize>()
+        {
+            let max = counters.entry(meta.species.clone()).or_insert(0);
+            *max = (*max).max(number);
+        }
+    }
+
+    let mut renamed_files = HashMap::new();
+    let mut recording_keys = HashMap::new();
+    for (meta, key) in metadata.iter_mut().zip(new_keys) {
+        recording_keys.insert(meta.id.clone(), (meta.species.clone(), key.clone()));
+        if key != meta.species {
+            let counter = counters.entry(key.clone()).or_insert(0);
+            *counter += 1;
+            let extension = meta.filename.split_once('.').map_or("wav", |(_, extension)| extension).to_string();
+            let old_stem = crate::recording_stem(&meta.filename).to_string();
+            let new_stem = format!("{}_{}", key, counter);
+            if meta.is_downloaded {
+                rename_recording(output_dir, &old_stem, &new_stem)?;
+            }
+            let filename = format!("{}.{}", new_stem, extension);
+            renamed_files.insert(meta.filename.clone(), filename.clone());
+            meta.filename = filename;
+            meta.species = key;
+        }
+
+        // Background species are stored as keys of their common names
+        meta.background = meta
+            .background
+            .split(';')
+            .filter(|s| !s.is_empty())
+            .map(|species| taxonomy.class_key(species, "").unwrap_or_else(|| species.to_string()))
+            .collect::<Vec<_>>()
+            .join(";");
+    }
+    crate::write_metadata_csv(&metadata_path, &metadata)?;
+
+    // Clips follow their recording when they carry its species; clips
+    // relabeled to another species in review are resolved by name
+    let clips_path = clips_csv_path(output_dir);
+    let mut clips = load_clips(&clips_path)?;
+    let mut clip_counts = clip_counters(&clips);
+    let mut renamed_clips = HashMap::new();
+    let mut relabeled = 0;
+    for clip in clips.iter_mut() {
+        let key = match recording_keys.get(&clip.parent_id) {
+            Some((old, new)) if *old == clip.species => new.clone(),
+            _ => taxonomy.class_key(&clip.species, "").unwrap_or_else(|| clip.species.clone()),
+        };
+        if key != clip.species {
+            let old_filename = clip.filename.clone();
+            let filename = relabel_clip(output_dir, clip, &key, &mut clip_counts)?;
+            renamed_clips.insert(format!("clips/{}", old_filename), format!("clips/{}", filename));
+            relabeled += 1;
+        }
+    }
+    for clip in clips.iter_mut() {
+        if let Some(filename) = renamed_files.get(&clip.parent_filename).or_else(|| renamed_clips.get(&clip.parent_filename)) {
+            clip.parent_filename = filename.clone();
+        }
+    }
+    if !clips.is_empty() {
+        write_clips_csv(&clips_path, &clips)?;
+    }
+
+    println!("Renamed {} recordings and {} clips", renamed_files.len(), relabeled);
+    if !renamed_files.is_empty() || relabeled > 0 {
+        println!("Re-run --split and --extract-features to bring the manifests and features up to date");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKLIST: &str = "\
+scientific_name,common_name,species_code,category,synonyms
+Hirundo rustica,Barn Swallow,barswa,species,Swallow
+Motacilla alba,White Wagtail,whiwag,species,
+Motacilla alba yarrellii,White Wagtail,whiwag1,issf,Pied Wagtail
+Larus canus,Common Gull,mewgul,species,Mew Gull
+Larus brachyrhynchus,Short-billed Gull,mewgul2,species,Mew Gull
+Dendrocopos major,Great Spotted Woodpecker,grswoo,species,Barn Swallow
+Pica pica,,eurmag1,species,
+";
+
+    fn checklist(name: &str) -> Taxonomy {
+        let path = std::env::temp_dir().join(format!("checklist_{}_{}.csv", name, std::process::id()));
+        std::fs::write(&path, CHECKLIST).unwrap();
+        let taxonomy = Taxonomy::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        taxonomy
+    }
+
+    fn scientific(taxonomy: &Taxonomy, index: Option<usize>) -> Option<&str> {
+        index.map(|i| taxonomy.taxa[i].scientific_name.as_str())
+    }
+
+    #[test]
+    fn names_resolve_regardless_of_case_hyphens_and_spacing() {
+        let taxonomy = checklist("names");
+        let resolved = |common: &str, sci: &str| scientific(&taxonomy, taxonomy.resolve(common, sci));
+        assert_eq!(resolved("", "HIRUNDO  rustica"), Some("Hirundo rustica"));
+        assert_eq!(resolved("short billed gull", ""), Some("Larus brachyrhynchus"));
+        assert_eq!(resolved("barswa", ""), Some("Hirundo rustica"));
+        assert_eq!(resolved("Swallow", "unknown"), Some("Hirundo rustica"));
+        assert_eq!(resolved("Mystery Bird", "Aves indet"), None);
+    }
+
+    #[test]
+    fn ambiguous_names_resolve_to_nothing() {
+        let taxonomy = checklist("ambiguous");
+        // Two gulls list "Mew Gull" as a synonym
+        assert_eq!(taxonomy.resolve("Mew Gull", ""), None);
+        // A common name wins over another taxon's synonym
+        assert_eq!(scientific(&taxonomy, taxonomy.resolve("Barn Swallow", "")), Some("Hirundo rustica"));
+        // The scientific name is tried before an ambiguous common name
+        assert_eq!(scientific(&taxonomy, taxonomy.resolve("Mew Gull", "Larus canus")), Some("Larus canus"));
+    }
+
+    #[test]
+    fn subspecies_collapse_into_their_species_when_asked() {
+        let mut taxonomy = checklist("subspecies");
+        let yarrellii = taxonomy.resolve("", "Motacilla alba yarrellii");
+        assert_eq!(scientific(&taxonomy, yarrellii), Some("Motacilla alba yarrellii"));
+        assert_eq!(taxonomy.taxa[yarrellii.unwrap()].parent, taxonomy.resolve("", "Motacilla alba"));
+        // The shared common name is the species', not a clash
+        assert_eq!(scientific(&taxonomy, taxonomy.resolve("White Wagtail", "")), Some("Motacilla alba"));
+        // Subspecies missing from the checklist fall back to their binomial
+        assert_eq!(scientific(&taxonomy, taxonomy.resolve("", "Motacilla alba alba")), Some("Motacilla alba"));
+
+        taxonomy.collapse_subspecies = true;
+        assert_eq!(scientific(&taxonomy, taxonomy.resolve("Pied Wagtail", "")), Some("Motacilla alba"));
+        assert_eq!(taxonomy.class_key("", "Motacilla alba yarrellii").as_deref(), Some("white_wagtail"));
+    }
+
+    #[test]
+    fn keys_use_the_chosen_name_in_the_clip_alphabet() {
+        let mut taxonomy = checklist("keys");
+        assert_eq!(taxonomy.class_key("Great Spotted Woodpecker", "").as_deref(), Some("great_spotted_woodpecker"));
+        assert_eq!(taxonomy.class_key("Short-billed Gull", "").as_deref(), Some("short_billed_gull"));
+        // Without a common name the scientific one is used
+        assert_eq!(taxonomy.class_key("", "Pica pica").as_deref(), Some("pica_pica"));
+        taxonomy.key_style = KeyStyle::Scientific;
+        assert_eq!(taxonomy.class_key("Barn Swallow", "").as_deref(), Some("hirundo_rustica"));
+        assert!("code".parse::<KeyStyle>().is_err());
+    }
+}
@@ -0,0 +1,776 @@
+use crate::augment::Rng;
+use crate::classmap::{export_class_map, load_class_map};
+use crate::dataset::read_dataset;
+use crate::license::{write_attribution, AttributionFormat, LicenseFilter};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+// CPU trainer for the classifier the PWA trains in modules/training.js:
+//
+//   BatchNormalization
+//   for each hidden layer: Dense(relu) -> Dropout -> BatchNormalization
+//   Dense(softmax)
+//
+// trained with categorical cross-entropy on a feature dataset written by
+// --extract-features, and saved in the TF.js layers format the PWA loads.
+
+const BN_EPSILON: f32 = 1e-3;
+const BN_MOMENTUM: f32 = 0.99;
+const OPTIMIZER_EPSILON: f32 = 1e-7; // TF.js backend epsilon
+const TFJS_VERSION: &str = "tfjs-layers 4.22.0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Optimizer {
+    Sgd,
+    Adam,
+    RmsProp,
+}
+
+impl std::str::FromStr for Optimizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sgd" => Ok(Optimizer::Sgd),
+            "adam" => Ok(Optimizer::Adam),
+            "rmsprop" => Ok(Optimizer::RmsProp),
+            _ => Err(format!("Unknown optimizer: {} (expected adam, sgd or rmsprop)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrainConfig {
+    pub hidden_units: Vec<usize>, // Width of each hidden Dense layer
+    pub dropout: f32,             // Dropout rate after each hidden layer
+    pub optimizer: Optimizer,
+    pub learning_rate: f32,
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub patience: usize, // Epochs without a better validation loss before stopping
+    pub seed: u64,
+    pub exclude_background: bool, // Skip recordings (and their clips) with other species in the background
+}
+
+impl Default for TrainConfig {
+    // Same as trainModel in modules/training.js
+    fn default() -> Self {
+        TrainConfig {
+            hidden_units: vec![64, 32],
+            dropout: 0.3,
+            optimizer: Optimizer::Adam,
+            learning_rate: 0.001,
+            epochs: 100,
+            batch_size: 8,
+            patience: 10,
+            seed: 0,
+            exclude_background: false,
+        }
+    }
+}
+
+// Trainable tensor with its gradient and optimizer state
+#[derive(Debug, Clone)]
+struct Param {
+    value: Vec<f32>,
+    grad: Vec<f32>,
+    first_moment: Vec<f32>,  // Adam
+    second_moment: Vec<f32>, // Adam and RMSprop
+}
+
+impl Param {
+    fn new(value: Vec<f32>) -> Self {
+        let n = value.len();
+        Param { value, grad: vec![0.0; n], first_moment: vec![0.0; n], second_moment: vec![0.0; n] }
+    }
+
+    // Apply and clear the accumulated gradient; `step` counts from 1
+    fn update(&mut self, optimizer: Optimizer, learning_rate: f32, step: i32) {
+        for i in 0..self.value.len() {
+            let g = self.grad[i];
+            match optimizer {
+                Optimizer::Sgd => self.value[i] -= learning_rate * g,
+                Optimizer::Adam => {
+                    const BETA1: f32 = 0.9;
+                    const BETA2: f32 = 0.999;
+                    self.first_moment[i] = BETA1 * self.first_moment[i] + (1.0 - BETA1) * g;
+                    self.second_moment[i] = BETA2 * self.second_moment[i] + (1.0 - BETA2) * g * g;
+                    let m = self.first_moment[i] / (1.0 - BETA1.powi(step));
+                    let v = self.second_moment[i] / (1.0 - BETA2.powi(step));
+                    self.value[i] -= learning_rate * m / (v.sqrt() + OPTIMIZER_EPSILON);
+                }
+                Optimizer::RmsProp => {
+                    const RHO: f32 = 0.9;
+                    self.second_moment[i] = RHO * self.second_moment[i] + (1.0 - RHO) * g * g;
+                    self.value[i] -= learning_rate * g / (self.second_moment[i] + OPTIMIZER_EPSILON).sqrt();
+                }
+            }
+        }
+        self.grad.fill(0.0);
+    }
+}
+
+// Standard normal sample (Box-Muller)
+fn normal(rng: &mut Rng) -> f32 {
+    let u1 = rng.unit().max(1e-12);
+    let u2 = rng.unit();
+    ((-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()) as f32
+}
+
+#[derive(Debug, Clone)]
+struct Dense {
+    inputs: usize,
+    units: usize,
+    kernel: Param, // inputs x units, row-major
+    bias: Param,
+    input_cache: Vec<f32>,
+}
+
+impl Dense {
+    // Glorot initialization from a truncated normal, as TF.js does for
+    // VarianceScaling(fan_avg, normal)
+    fn new(inputs: usize, units: usize, rng: &mut Rng) -> Self {
+        let stddev = (2.0 / (inputs + units) as f32).sqrt() / 0.879_625_7;
+        let kernel = (0..inputs * units)
+            .map(|_| loop {
+                let sample = normal(rng);
+                if sample.abs() <= 2.0 {
+                    break sample * stddev;
+                }
+            })
+            .collect();
+        Dense { inputs, units, kernel: Param::new(kernel), bias: Param::new(vec![0.0; units]), input_cache: Vec::new() }
+    }
+
+    fn forward(&mut self, x: &[f32], batch: usize) -> Vec<f32> {
+        let mut output = Vec::with_capacity(batch * self.units);
+        for row in x.chunks_exact(self.inputs) {
+            let mut out = self.bias.value.clone();
+            for (xi, weights) in row.iter().zip(self.kernel.value.chunks_exact(self.units)) {
+                for (o, w) in out.iter_mut().zip(weights) {
+                    *o += xi * w;
+                }
+            }
+            output.extend(out);
+        }
+        self.input_cache = x.to_vec();
+        output
+    }
+
+    fn backward(&mut self, dy: &[f32]) -> Vec<f32> {
+        let mut dx = vec![0.0; self.input_cache.len()];
+        for ((x_row, dy_row), dx_row) in self
+            .input_cache
+            .chunks_exact(self.inputs)
+            .zip(dy.chunks_exact(self.units))
+            .zip(dx.chunks_exact_mut(self.inputs))
+        {
+            for (g, d) in self.bias.grad.iter_mut().zip(dy_row) {
+                *g += d;
+            }
+            for i in 0..self.inputs {
+                let weights = &self.kernel.value[i * self.units..(i + 1) * self.units];
+                let grads = &mut self.kernel.grad[i * self.units..(i + 1) * self.units];
+                let mut sum = 0.0;
+                for j in 0..self.units {
+                    grads[j] += x_row[i] * dy_row[j];
+                    sum += dy_row[j] * weights[j];
+                }
+                dx_row[i] = sum;
+            }
+        }
+        dx
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BatchNorm {
+    width: usize,
+    gamma: Param,
+    beta: Param,
+    moving_mean: Vec<f32>,
+    moving_variance: Vec<f32>,
+    normalized_cache: Vec<f32>,
+    inv_std_cache: Vec<f32>,
+}
+
+impl BatchNorm {
+    fn new(width: usize) -> Self {
+        BatchNorm {
+            width,
+            gamma: Param::new(vec![1.0; width]),
+            beta: Param::new(vec![0.0; width]),
+            moving_mean: vec![0.0; width],
+            moving_variance: vec![1.0; width],
+            normalized_cache: Vec::new(),
+            inv_std_cache: Vec::new(),
+        }
+    }
+
+    // Batch statistics while training (updating the moving averages),
+    // moving averages otherwise
+    fn forward(&mut self, x: &[f32], batch: usize, training: bool) -> Vec<f32> {
+        let (mean, variance) = if training {
+            let mut mean = vec![0.0; self.width];
+            for row in x.chunks_exact(self.width) {
+                for (m, v) in mean.iter_mut().zip(row) {
+                    *m += v / batch as f32;
+                }
+            }
+            let mut variance = vec![0.0; self.width];
+            for row in x.chunks_exact(self.width) {
+                for ((s, v), m) in variance.iter_mut().zip(row).zip(&mean) {
+                    *s += (v - m) * (v - m) / batch as f32;
+                }
+            }
+            for i in 0..self.width {
+                self.moving_mean[i] += (mean[i] - self.moving_mean[i]) * (1.0 - BN_MOMENTUM);
+                self.moving_variance[i] += (variance[i] - self.moving_variance[i]) * (1.0 - BN_MOMENTUM);
+            }
+            (mean, variance)
+        } else {
+            (self.moving_mean.clone(), self.moving_variance.clone())
+        };
+
+        let inv_std: Vec<f32> = variance.iter().map(|v| 1.0 / (v + BN_EPSILON).sqrt()).collect();
+        let mut normalized = Vec::with_capacity(x.len());
+        let mut output = Vec::with_capacity(x.len());
+        for row in x.chunks_exact(self.width) {
+            for i in 0..self.width {
+                let n = (row[i] - mean[i]) * inv_std[i];
+                normalized.push(n);
+                output.push(n * self.gamma.value[i] + self.beta.value[i]);
+            }
+        }
+        self.normalized_cache = normalized;
+        self.inv_std_cache = inv_std;
+        output
+    }
+
+    fn backward(&mut self, dy: &[f32], batch: usize) -> Vec<f32> {
+        let mut sum_dn = vec![0.0; self.width];
+        let mut sum_dn_n = vec![0.0; self.width];
+        for (dy_row, n_row) in dy.chunks_exact(self.width).zip(self.normalized_cache.chunks_exact(self.width)) {
+            for i in 0..self.width {
+                self.gamma.grad[i] += dy_row[i] * n_row[i];
+                self.beta.grad[i] += dy_row[i];
+                let dn = dy_row[i] * self.gamma.value[i];
+                sum_dn[i] += dn;
+                sum_dn_n[i] += dn * n_row[i];
+            }
+        }
+        let b = batch as f32;
+        let mut dx = Vec::with_capacity(dy.len());
+        for (dy_row, n_row) in dy.chunks_exact(self.width).zip(self.normalized_cache.chunks_exact(self.width)) {
+            for i in 0..self.width {
+                let dn = dy_row[i] * self.gamma.value[i];
+                dx.push(self.inv_std_cache[i] / b * (b * dn - sum_dn[i] - n_row[i] * sum_dn_n[i]));
+            }
+        }
+        dx
+    }
+}
+
+// Dense(relu) -> Dropout -> BatchNormalization
+#[derive(Debug, Clone)]
+struct HiddenBlock {
+    dense: Dense,
+    mask: Vec<f32>, // ReLU gate times dropout scale, per activation
+    norm: BatchNorm,
+}
+
+#[derive(Debug, Clone)]
+struct Network {
+    input_norm: BatchNorm,
+    hidden: Vec<HiddenBlock>,
+    output: Dense,
+    dropout: f32,
+}
+
+impl Network {
+    fn new(inputs: usize, classes: usize, config: &TrainConfig, rng: &mut Rng) -> Self {
+        let mut width = inputs;
+        let mut hidden = Vec::new();
+        for &units in &config.hidden_units {
+            hidden.push(HiddenBlock { dense: Dense::new(width, units, rng), mask: Vec::new(), norm: BatchNorm::new(units) });
+            width = units;
+        }
+        Network {
+            input_norm: BatchNorm::new(inputs),
+            hidden,
+            output: Dense::new(width, classes, rng),
+            dropout: config.dropout,
+        }
+    }
+
+    // Class probabilities for a batch of feature vectors
+    fn forward(&mut self, x: &[f32], batch: usize, training: bool, rng: &mut Rng) -> Vec<f32> {
+        let mut values = self.input_norm.forward(x, batch, training);
+        let keep = 1.0 - self.dropout;
+        for block in &mut self.hidden {
+            let z = block.dense.forward(&values, batch);
+            block.mask = z
+                .iter()
+                .map(|&v| {
+                    if v <= 0.0 {
+                        0.0
+                    } else if !training || self.dropout <= 0.0 {
+                        1.0
+                    } else if rng.unit() < keep as f64 {
+                        1.0 / keep
+                    } else {
+                        0.0
+                    }
+                })
+                .collect();
+            let activated: Vec<f32> = z.iter().zip(&block.mask).map(|(v, m)| v * m).collect();
+            values = block.norm.forward(&activated, batch, training);
+        }
+
+        let mut logits = self.output.forward(&values, batch);
+        for row in logits.chunks_exact_mut(self.output.units) {
+            let max = row.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let mut sum = 0.0;
+            for v in row.iter_mut() {
+                *v = (*v - max).exp();
+                sum += *v;
+            }
+            for v in row.iter_mut() {
+                *v /= sum;
+            }
+        }
+        logits
+    }
+
+    // Accumulate gradients of the mean cross-entropy for the last forward pass
+    fn backward(&mut self, probabilities: &[f32], targets: &[usize]) {
+        let batch = targets.len();
+        let classes = self.output.units;
+        let mut dy = probabilities.to_vec();
+        for (row, &target) in dy.chunks_exact_mut(classes).zip(targets) {
+            row[target] -= 1.0;
+            for v in row.iter_mut() {
+                *v /= batch as f32;
+            }
+        }
+
+        let mut dy = self.output.backward(&dy);
+        for block in self.hidden.iter_mut().rev() {
+            let d_activated = block.norm.backward(&dy, batch);
+            let dz: Vec<f32> = d_activated.iter().zip(&block.mask).map(|(d, m)| d * m).collect();
+            dy = block.dense.backward(&dz);
+        }
+        self.input_norm.backward(&dy, batch);
+    }
+
+    fn params(&mut self) -> Vec<&mut Param> {
+        let mut params = vec![&mut self.input_norm.gamma, &mut self.input_norm.beta];
+        for block in &mut self.hidden {
+            params.extend([&mut block.dense.kernel, &mut block.dense.bias, &mut block.norm.gamma, &mut block.norm.beta]);
+        }
+        params.extend([&mut self.output.kernel, &mut self.output.bias]);
+        params
+    }
+}
+
+// Mean cross-entropy and accuracy of a set of predictions. Probabilities
+// are clipped like TF.js does before taking the log.
+fn loss_and_accuracy(probabilities: &[f32], targets: &[usize], classes: usize) -> (f64, f64) {
+    let mut loss = 0.0;
+    let mut correct = 0;
+    for (row, &target) in probabilities.chunks_exact(classes).zip(targets) {
+        loss -= (row[target].clamp(OPTIMIZER_EPSILON, 1.0 - OPTIMIZER_EPSILON) as f64).ln();
+        let best = (0..classes).max_by(|&a, &b| row[a].total_cmp(&row[b])).unwrap();
+        if best == target {
+            correct += 1;
+        }
+    }
+    let n = targets.len().max(1) as f64;
+    (loss / n, correct as f64 / n)
+}
+
+// Layer configs in the shape tfjs-layers writes them
+fn batch_norm_config(name: &str, input_dim: Option<usize>) -> Value {
+    let mut config = json!({
+        "axis": -1, "momentum": BN_MOMENTUM, "epsilon": BN_EPSILON, "center": true, "scale": true,
+        "beta_initializer": {"class_name": "Zeros", "config": {}},
+        "gamma_initializer": {"class_name": "Ones", "config": {}},
+        "moving_mean_initializer": {"class_name": "Zeros", "config": {}},
+        "moving_variance_initializer": {"class_name": "Ones", "config": {}},
+        "beta_regularizer": null, "gamma_regularizer": null, "beta_constraint": null, "gamma_constraint": null,
+        "name": name, "trainable": true
+    });
+    if let Some(dim) = input_dim {
+        config["batch_input_shape"] = json!([null, dim]);
+        config["dtype"] = json!("float32");
+    }
+    json!({"class_name": "BatchNormalization", "config": config})
+}
+
+fn dense_config(name: &str, units: usize, activation: &str) -> Value {
+    json!({"class_name": "Dense", "config": {
+        "units": units, "activation": activation, "use_bias": true,
+        "kernel_initializer": {"class_name": "VarianceScaling", "config": {"scale": 1, "mode": "fan_avg", "distribution": "normal", "seed": null}},
+        "bias_initializer": {"class_name": "Zeros", "config": {}},
+        "kernel_regularizer": null, "bias_regularizer": null, "activity_regularizer": null,
+        "kernel_constraint": null, "bias_constraint": null,
+        "name": name, "trainable": true
+    }})
+}
+
+fn dropout_config(name: &str, rate: f32) -> Value {
+    json!({"class_name": "Dropout", "config": {"rate": rate, "noise_shape": null, "seed": null, "name": name, "trainable": true}})
+}
+
+// Write <stem>.json and <stem>.weights.bin into `model_dir`. Trainable
+// weights come first in layer order, followed by the moving statistics,
+// as tfjs-layers orders them.
+fn save_tfjs_model(network: &Network, model_dir: &Path, stem: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut layers = Vec::new();
+    let mut trainable: Vec<(String, Vec<usize>, &[f32])> = Vec::new();
+    let mut statistics: Vec<(String, Vec<usize>, &[f32])> = Vec::new();
+    let (mut norms, mut denses, mut dropouts) = (0, 0, 0);
+
+    let mut add_norm = |norm: &'_ BatchNorm, input_dim: Option<usize>, layers: &mut Vec<Value>| {
+        norms += 1;
+        let name = format!("batch_normalization_BatchNormalization{}", norms);
+        layers.push(batch_norm_config(&name, input_dim));
+        (name, norm.width)
+    };
+
+    let (name, width) = add_norm(&network.input_norm, Some(network.input_norm.width), &mut layers);
+    trainable.push((format!("{}/gamma", name), vec![width], &network.input_norm.gamma.value));
+    trainable.push((format!("{}/beta", name), vec![width], &network.input_norm.beta.value));
+    statistics.push((format!("{}/moving_mean", name), vec![width], &network.input_norm.moving_mean));
+    statistics.push((format!("{}/moving_variance", name), vec![width], &network.input_norm.moving_variance));
+
+    let mut add_dense = |dense: &Dense, activation: &str, layers: &mut Vec<Value>| {
+        denses += 1;
+        let name = format!("dense_Dense{}", denses);
+        layers.push(dense_config(&name, dense.units, activation));
+        name
+    };
+
+    for block in &network.hidden {
+        let name = add_dense(&block.dense, "relu", &mut layers);
+        trainable.push((format!("{}/kernel", name), vec![block.dense.inputs, block.dense.units], &block.dense.kernel.value));
+        trainable.push((format!("{}/bias", name), vec![block.dense.units], &block.dense.bias.value));
+
+        dropouts += 1;
+        layers.push(dropout_config(&format!("dropout_Dropout{}", dropouts), network.dropout));
+
+        let (name, width) = add_norm(&block.norm, None, &mut layers);
+        trainable.push((format!("{}/gamma", name), vec![width], &block.norm.gamma.value));
+        trainable.push((format!("{}/beta", name), vec![width], &block.norm.beta.value));
+        statistics.push((format!("{}/moving_mean", name), vec![width], &block.norm.moving_mean));
+        statistics.push((format!("{}/moving_variance", name), vec![width], &block.norm.moving_variance));
+    }
+
+    let name = add_dense(&network.output, "softmax", &mut layers);
+    trainable.push((format!("{}/kernel", name), vec![network.output.inputs, network.output.units], &network.output.kernel.value));
+    trainable.push((format!("{}/bias", name), vec![network.output.units], &network.output.bias.value));
+
+    let weights_name = format!("{}.weights.bin", stem);
+    let mut bytes = Vec::new();
+    let mut specs = Vec::new();
+    for (name, shape, values) in trainable.iter().chain(&statistics) {
+        bytes.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+        specs.push(json!({"name": name, "shape": shape, "dtype": "float32"}));
+    }
+
+    let model = json!({
+        "modelTopology": {
+            "class_name": "Sequential",
+            "config": {"name": "sequential_1", "layers": layers},
+            "keras_version": TFJS_VERSION,
+            "backend": "tensor_flow.js"
+        },
+        "format": "layers-model",
+        "generatedBy": format!("xeno_canto_scraper {}", env!("CARGO_PKG_VERSION")),
+        "convertedBy": null,
+        "weightsManifest": [{"paths": [format!("./{}", weights_name)], "weights": specs}]
+    });
+    std::fs::write(model_dir.join(&weights_name), bytes)?;
+    std::fs::write(model_dir.join(format!("{}.json", stem)), serde_json::to_string(&model)?)?;
+    Ok(())
+}
+
+// Train on the recordings assigned to "train" by --split and stop early on
+// the "val" ones; test recordings are never used. Without a split, a
+// seeded fifth of the recordings is held out for validation instead. The
+// class map in `model_dir` is brought up to date first so output indices
+// stay stable across runs.
+pub fn train_model(
+    output_dir: &str,
+    features_path: &Path,
+    model_dir: &Path,
+    config: &TrainConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if config.batch_size == 0 || !(0.0..1.0).contains(&config.dropout) {
+        return Err("batch size must be positive and dropout in [0, 1)".into());
+    }
+    if !features_path.exists() {
+        return Err(format!("{} not found, run --extract-features first", features_path.display()).into());
+    }
+    let dataset = read_dataset(features_path)?;
+
+    export_class_map(output_dir, model_dir)?;
+    let class_map = load_class_map(&model_dir.join("class-map.json"))?;
+    let class_index: HashMap<&str, usize> = class_map.iter().map(|(label, index)| (label.as_str(), *index)).collect();
+    let classes = class_map.iter().map(|(_, index)| index + 1).max().unwrap_or(0);
+    let targets: Vec<Option<usize>> =
+        dataset.labels.iter().map(|label| class_index.get(label.as_str()).copied()).collect();
+    if dataset.labels.iter().collect::<BTreeSet<_>>().len() < 2 {
+        return Err("need at least 2 species to train".into());
+    }
+
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+    let excluded_licenses = LicenseFilter::load(output_dir)?.excluded_ids(&metadata);
+    let with_background: HashSet<String> = metadata
+        .iter()
+        .filter(|m| config.exclude_background && !m.background.is_empty())
+        .map(|m| m.id.clone())
+        .collect();
+    let mut splits: HashMap<String, String> = metadata.into_iter().map(|m| (m.id, m.split)).collect();
+    if !splits.values().any(|s| s == "val") {
+        println!("No validation split assigned (see --split), holding out 20% of the recordings");
+        for (id, split) in splits.iter_mut() {
+            if split.is_empty() || split == "train" {
+                let mut rng = Rng::for_item(config.seed, id);
+                *split = if rng.unit() < 0.2 { "val" } else { "train" }.to_string();
+            }
+        }
+    }
+
+    let mut train = Vec::new();
+    let mut val = Vec::new();
+    let mut unmapped = 0;
+    let mut excluded = 0;
+    let mut unlicensed = 0;
+    let mut augmented = 0;
+    for (i, record) in dataset.records.iter().enumerate() {
+        if excluded_licenses.contains(&record.recording_id) {
+            unlicensed += 1;
+            continue;
+        }
+        if with_background.contains(&record.recording_id) {
+            excluded += 1;
+            continue;
+        }
+        let Some(target) = targets[record.label as usize] else {
+            unmapped += 1;
+            continue;
+        };
+        // Augmented copies only add training variety; validating on them
+        // would score the model on altered audio
+        match splits.get(&record.recording_id).map(String::as_str) {
+            Some("val" | "test") if record.source == "augment" => augmented += 1,
+            Some("val") => val.push((i, target)),
+            Some("test") => {}
+            _ => train.push((i, target)),
+        }
+    }
+    if unmapped > 0 {
+        println!("Skipping {} records whose species is not in the catalog", unmapped);
+    }
+    if excluded > 0 {
+        println!("Skipping {} records of recordings with background species", excluded);
+    }
+    if unlicensed > 0 {
+        println!("Skipping {} records of recordings with an excluded license", unlicensed);
+    }
+    if augmented > 0 {
+        println!("Skipping {} augmented records of validation and test recordings", augmented);
+    }
+    if train.is_empty() {
+        return Err("no training records".into());
+    }
+    println!(
+        "Training on {} records, validating on {} ({} features, {} classes)",
+        train.len(),
+        val.len(),
+        dataset.dim,
+        classes
+    );
+
+    let gather = |set: &[(usize, usize)]| -> (Vec<f32>, Vec<usize>) {
+        let x = set.iter().flat_map(|(i, _)| dataset.records[*i].features.iter().copied()).collect();
+        (x, set.iter().map(|(_, t)| *t).collect())
+    };
+    let (val_x, val_targets) = gather(&val);
+
+    let mut rng = Rng::for_item(config.seed, "train");
+    let mut network = Network::new(dataset.dim, classes, config, &mut rng);
+    let mut best: Option<(f64, Network, usize)> = None;
+    let mut waited = 0;
+    let mut step = 0;
+
+    for epoch in 1..=config.epochs {
+        // Fisher-Yates shuffle
+        for i in (1..train.len()).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            train.swap(i, j);
+        }
+
+        let mut epoch_loss = 0.0;
+        let mut epoch_correct = 0.0;
+        for batch in train.chunks(config.batch_size) {
+            let (x, batch_targets) = gather(batch);
+            let probabilities = network.forward(&x, batch.len(), true, &mut rng);
+            let (loss, accuracy) = loss_and_accuracy(&probabilities, &batch_targets, classes);
+            epoch_loss += loss * batch.len() as f64;
+            epoch_correct += accuracy * batch.len() as f64;
+            network.backward(&probabilities, &batch_targets);
+            step += 1;
+            for param in network.params() {
+                param.update(config.optimizer, config.learning_rate, step);
+            }
+        }
+        let train_loss = epoch_loss / train.len() as f64;
+        let train_accuracy = epoch_correct / train.len() as f64;
+
+        if val.is_empty() {
+            println!("Epoch {}/{}: loss {:.4}, accuracy {:.3}", epoch, config.epochs, train_loss, train_accuracy);
+            continue;
+        }
+        let probabilities = network.forward(&val_x, val.len(), false, &mut rng);
+        let (val_loss, val_accuracy) = loss_and_accuracy(&probabilities, &val_targets, classes);
+        println!(
+            "Epoch {}/{}: loss {:.4}, accuracy {:.3}, val_loss {:.4}, val_accuracy {:.3}",
+            epoch, config.epochs, train_loss, train_accuracy, val_loss, val_accuracy
+        );
+
+        // Early stopping on the validation loss, keeping the best weights
+        if best.as_ref().is_none_or(|(best_loss, _, _)| val_loss < *best_loss) {
+            best = Some((val_loss, network.clone(), epoch));
+            waited = 0;
+        } else {
+            waited += 1;
+            if waited >= config.patience {
+                println!("No improvement for {} epochs, stopping", config.patience);
+                break;
+            }
+        }
+    }
+
+    if let Some((loss, best_network, epoch)) = best {
+        println!("Keeping the weights from epoch {} (val_loss {:.4})", epoch, loss);
+        network = best_network;
+    }
+    save_tfjs_model(&network, model_dir, "mfcc-model")?;
+    println!("Saved model to {}", model_dir.join("mfcc-model.json").display());
+
+    // Credit the recordings the weights were fit on
+    let used: HashSet<String> =
+        train.iter().chain(&val).map(|(i, _)| dataset.records[*i].recording_id.clone()).collect();
+    for format in [AttributionFormat::Markdown, AttributionFormat::Json] {
+        write_attribution(output_dir, &used, format, &model_dir.join(format.file_name()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    fn config() -> TrainConfig {
+        TrainConfig { hidden_units: vec![6, 4], dropout: 0.0, ..TrainConfig::default() }
+    }
+
+    // Two well separated clusters of 4-dimensional points, alternating classes
+    fn separable(rng: &mut Rng, count: usize) -> (Vec<f32>, Vec<usize>) {
+        let targets: Vec<usize> = (0..count).map(|i| i % 2).collect();
+        let x = targets
+            .iter()
+            .flat_map(|&t| (0..4).map(|_| if t == 0 { 2.0 } else { -2.0 } + 0.5 * normal(rng)).collect::<Vec<_>>())
+            .collect();
+        (x, targets)
+    }
+
+    fn loss(network: &mut Network, x: &[f32], targets: &[usize], rng: &mut Rng) -> f64 {
+        let probabilities = network.forward(x, targets.len(), true, rng);
+        loss_and_accuracy(&probabilities, targets, 2).0
+    }
+
+    #[test]
+    fn saved_model_loads_with_identical_outputs() {
+        let mut rng = Rng::for_item(1, "save");
+        let mut network = Network::new(4, 2, &config(), &mut rng);
+        // Training passes move the moving statistics away from their defaults
+        let (x, targets) = separable(&mut rng, 8);
+        for _ in 0..20 {
+            network.forward(&x, targets.len(), true, &mut rng);
+        }
+
+        let dir = std::env::temp_dir().join(format!("trainer_save_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        save_tfjs_model(&network, &dir, "mfcc-model").unwrap();
+        let model = Model::load(&dir.join("mfcc-model.json")).unwrap();
+        assert_eq!((model.input_dim, model.output_dim), (4, 2));
+
+        let expected = network.forward(&x, targets.len(), false, &mut rng);
+        for (row, want) in x.chunks_exact(4).zip(expected.chunks_exact(2)) {
+            let got = model.predict(row).unwrap();
+            for (g, w) in got.iter().zip(want) {
+                assert!((g - w).abs() < 1e-5, "loaded {} vs trained {}", g, w);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn loss_decreases_on_separable_data() {
+        let mut rng = Rng::for_item(2, "separable");
+        let mut network = Network::new(4, 2, &config(), &mut rng);
+        let (x, targets) = separable(&mut rng, 16);
+
+        let initial = loss(&mut network, &x, &targets, &mut rng);
+        for step in 1..=50 {
+            let probabilities = network.forward(&x, targets.len(), true, &mut rng);
+            network.backward(&probabilities, &targets);
+            for param in network.params() {
+                param.update(Optimizer::Adam, 0.01, step);
+            }
+        }
+        let trained = loss(&mut network, &x, &targets, &mut rng);
+        assert!(trained < initial / 4.0, "loss went from {} to {}", initial, trained);
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = Rng::for_item(3, "gradients");
+        let mut network = Network::new(4, 2, &config(), &mut rng);
+        let (x, targets) = separable(&mut rng, 6);
+
+        let probabilities = network.forward(&x, targets.len(), true, &mut rng);
+        network.backward(&probabilities, &targets);
+        let analytic: Vec<Vec<f32>> = network.params().iter().map(|p| p.grad.clone()).collect();
+
+        // First few entries of every parameter: both batch norms, the hidden
+        // and output kernels and biases. The step is small enough that no
+        // pre-activation on this data crosses the ReLU kink.
+        const STEP: f32 = 3e-4;
+        for (p, grads) in analytic.iter().enumerate() {
+            for (k, &grad) in grads.iter().enumerate().take(3) {
+                let original = network.params()[p].value[k];
+                network.params()[p].value[k] = original + STEP;
+                let plus = loss(&mut network, &x, &targets, &mut rng);
+                network.params()[p].value[k] = original - STEP;
+                let minus = loss(&mut network, &x, &targets, &mut rng);
+                network.params()[p].value[k] = original;
+
+                let numeric = ((plus - minus) / (2.0 * STEP as f64)) as f32;
+                assert!(
+                    (numeric - grad).abs() <= 1e-3 + 0.05 * grad.abs(),
+                    "param {}[{}]: backward {} vs finite difference {}",
+                    p,
+                    k,
+                    grad,
+                    numeric
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::audio::{read_audio, write_wav};
+use crate::chunk::{chunk_audio, ChunkConfig};
+use crate::clips::{clip_counters, clips_csv_path, clips_dir, load_clips, next_clip_filename, write_clips_csv, ClipMetadata};
+use crate::dsp::rms_db;
+use crate::license::LicenseFilter;
+use crate::mapping::ClassMapping;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// Clip source of the negative class
+const SOURCE: &str = "unknown";
+
+#[derive(Debug, Clone)]
+pub struct UnknownConfig {
+    pub label: String,                    // Class name, "unknown" to match the PWA's unknown.png
+    pub targets: Option<HashSet<String>>, // Species the model should learn; the rest become negatives
+    pub window: ChunkConfig,              // Length of the negative clips
+    pub per_recording: usize,             // Clips taken from each recording
+    pub max_noise_db: Option<f64>,        // Noise windows louder than this (RMS, dBFS) are not used
+}
+
+// `count` of `items` spread evenly over the list
+fn spread<T>(items: Vec<T>, count: usize) -> Vec<T> {
+    let len = items.len();
+    if len <= count {
+        return items;
+    }
+    let picks: HashSet<usize> = (0..count).map(|i| i * len / count).collect();
+    items.into_iter().enumerate().filter(|(i, _)| picks.contains(i)).map(|(_, item)| item).collect()
+}
+
+// Assemble a negative class from two sources:
+//  - windows of recordings whose species is not a target, unless a target
+//    is heard in their background
+//  - noise-only windows of target recordings: the stretches between the
+//    calls --segment or --import-labels found, in recordings without
+//    background species
+// Recordings that already gave negatives are skipped.
+pub fn build_unknown_class(output_dir: &str, config: &UnknownConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = crate::load_existing_metadata(&Path::new(output_dir).join("metadata.csv"))?;
+    let clips_path = clips_csv_path(output_dir);
+    let mut clips = load_clips(&clips_path)?;
+    let mut counters = clip_counters(&clips);
+    std::fs::create_dir_all(clips_dir(output_dir))?;
+
+    let mapping = ClassMapping::load(output_dir)?;
+    let licenses = LicenseFilter::load(output_dir)?;
+    let is_target = |species: &str| config.targets.as_ref().is_none_or(|targets| targets.contains(species));
+    let processed: HashSet<String> = clips.iter().filter(|c| c.source == SOURCE).map(|c| c.parent_id.clone()).collect();
+
+    // Where the calls are, per recording
+    let mut calls: HashMap<String, Vec<(f64, f64)>> = HashMap::new();
+    for clip in clips.iter().filter(|c| c.source == "segment" || c.source == "labels") {
+        calls.entry(clip.parent_id.clone()).or_default().push((clip.start_secs, clip.end_secs));
+    }
+
+    let (mut from_other_species, mut from_noise) = (0, 0);
+    let mut new_clips = Vec::new();
+    for meta in metadata.iter().filter(|m| m.is_downloaded && !processed.contains(&m.id) && licenses.allows_recording(m)) {
+        let background: Vec<&str> = meta.background.split(';').filter(|s| !s.is_empty()).collect();
+        let other_species = !is_target(&mapping.recording_class(meta));
+        let usable = if other_species {
+            !background.iter().any(|species| is_target(&mapping.class_of(species, meta)))
+        } else {
+            background.is_empty() && calls.contains_key(&meta.id)
+        };
+        if !usable {
+            continue;
+        }
+
+        let audio = match read_audio(&Path::new(output_dir).join(&meta.filename)) {
+            Ok(audio) => audio,
+            Err(e) => {
+                println!("Error reading {}: {}", meta.filename, e);
+                continue;
+            }
+        };
+        let windows: Vec<_> = chunk_audio(&audio, &config.window)
+            .into_iter()
+            .filter(|(start, end, window)| {
+                if other_species {
+                    return true;
+                }
+                let overlaps_call = calls[&meta.id].iter().any(|&(s, e)| *start < e && s < *end);
+                !overlaps_call && config.max_noise_db.is_none_or(|max| rms_db(&window.mono()) <= max)
+            })
+            .collect();
+
+        for (start_secs, end_secs, window) in spread(windows, config.per_recording) {
+            let (species, filename) = next_clip_filename(&config.label, &mut counters)?;
+            write_wav(&clips_dir(output_dir).join(&filename), &window)?;
+            new_clips.push(ClipMetadata {
+                filename,
+                species,
+                parent_id: meta.id.clone(),
+                parent_filename: meta.filename.clone(),
+                start_secs,
+                end_secs,
+                source: SOURCE.to_string(),
+                augmentation: String::new(),
+                verified: false,
+                low_hz: None,
+                high_hz: None,
+            });
+            if other_species {
+                from_other_species += 1;
+            } else {
+                from_noise += 1;
+            }
+        }
+    }
+
+    clips.extend(new_clips);
+    write_clips_csv(&clips_path, &clips)?;
+    println!(
+        "Added {} {} clips: {} from non-target species, {} noise-only",
+        from_other_species + from_noise,
+        config.label,
+        from_other_species,
+        from_noise
+    );
+    if config.targets.is_none() {
+        println!("No --targets given, so every species is a target and only noise was used");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioBuffer;
+    use crate::RecordingMetadata;
+
+    #[test]
+    fn spread_picks_evenly() {
+        let items: Vec<usize> = (0..10).collect();
+        assert_eq!(spread(items.clone(), 3), [0, 3, 6]);
+        assert_eq!(spread(items.clone(), 5), [0, 2, 4, 6, 8]);
+        assert_eq!(spread(items.clone(), 10), items);
+        assert_eq!(spread(items.clone(), 20), items);
+        assert!(spread(items, 0).is_empty());
+    }
+
+    #[test]
+    fn noise_windows_avoid_calls_and_backgrounds_exclude_recordings() {
+        let dir = std::env::temp_dir().join(format!("unknown_build_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output_dir = dir.to_str().unwrap();
+
+        // Ten seconds each: a target wren with one call, a robin, and a
+        // robin with the wren in its background
+        let recording = |id: &str, species: &str, background: &str| {
+            let filename = format!("XC{}.wav", id);
+            let audio = AudioBuffer { sample_rate: 8000, channels: 1, samples: vec![0.01; 80000] };
+            write_wav(&dir.join(&filename), &audio).unwrap();
+            RecordingMetadata {
+                id: id.to_string(),
+                filename,
+                species: species.to_string(),
+                background: background.to_string(),
+                is_downloaded: true,
+                ..Default::default()
+            }
+        };
+        let metadata = [recording("1", "wren", ""), recording("2", "robin", ""), recording("3", "robin", "wren")];
+        crate::write_metadata_csv(&dir.join("metadata.csv"), &metadata).unwrap();
+        let call = ClipMetadata {
+            filename: "wren_1.wav".to_string(),
+            species: "wren".to_string(),
+            parent_id: "1".to_string(),
+            parent_filename: "XC1.wav".to_string(),
+            start_secs: 2.0,
+            end_secs: 4.5,
+            source: "segment".to_string(),
+            augmentation: String::new(),
+            verified: false,
+            low_hz: None,
+            high_hz: None,
+        };
+        write_clips_csv(&clips_csv_path(output_dir), &[call]).unwrap();
+
+        let config = UnknownConfig {
+            label: "unknown".to_string(),
+            targets: Some(HashSet::from(["wren".to_string()])),
+            window: ChunkConfig { window_secs: 1.0, hop_secs: 1.0, pad_tail: false, min_rms_db: None },
+            per_recording: 100,
+            max_noise_db: None,
+        };
+        build_unknown_class(output_dir, &config).unwrap();
+
+        let negatives: Vec<ClipMetadata> =
+            load_clips(&clips_csv_path(output_dir)).unwrap().into_iter().filter(|c| c.source == SOURCE).collect();
+        let starts = |parent: &str| -> Vec<f64> {
+            negatives.iter().filter(|c| c.parent_id == parent).map(|c| c.start_secs).collect()
+        };
+        // The windows at 2, 3 and 4 s touch the 2.0-4.5 s call
+        assert_eq!(starts("1"), [0.0, 1.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(starts("2").len(), 10);
+        assert!(starts("3").is_empty());
+        assert!(negatives.iter().all(|c| c.species == "unknown"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+/**
+ * Regenerates the MFCC parity fixtures used by the tests in src/features.rs.
+ *
+ * Writes a few deterministic 16-bit WAV files and runs them through the real
+ * extractors in modules/mfcc.js, storing the vectors as one number per line.
+ * Numbers are printed with JavaScript's shortest round-trip formatting, so
+ * the Rust side can compare them bit for bit.
+ *
+ * Usage: node tests/fixtures/mfcc/generate.mjs
+ */
+
+import { writeFileSync, readFileSync } from 'node:fs';
+import { dirname, join } from 'node:path';
+import { fileURLToPath } from 'node:url';
+
+const here = dirname(fileURLToPath(import.meta.url));
+const { extractEnhancedMfccFeatures, simpleExtractMfccFeatures } =
+  await import(join(here, '../../../../modules/mfcc.js'));
+
+// Minimal stand-in for the Web Audio decoder: 16-bit PCM WAV only, no
+// resampling, samples scaled by 1/32768 into Float32Arrays
+globalThis.window = {
+  AudioContext: class {
+    async decodeAudioData(arrayBuffer) {
+      const view = new DataView(arrayBuffer);
+      let offset = 12;
+      let channels = 1;
+      let sampleRate = 44100;
+      while (offset + 8 <= view.byteLength) {
+        const id = String.fromCharCode(...new Uint8Array(arrayBuffer, offset, 4));
+        const size = view.getUint32(offset + 4, true);
+        if (id === 'fmt ') {
+          channels = view.getUint16(offset + 10, true);
+          sampleRate = view.getUint32(offset + 12, true);
+        } else if (id === 'data') {
+          const frames = size / 2 / channels;
+          const data = Array.from({ length: channels }, () => new Float32Array(frames));
+          for (let i = 0; i < frames; i++) {
+            for (let c = 0; c < channels; c++) {
+              data[c][i] = view.getInt16(offset + 8 + (i * channels + c) * 2, true) / 32768;
+            }
+          }
+          return { sampleRate, getChannelData: c => data[c] };
+        }
+        offset += 8 + size;
+      }
+      throw new Error('No data chunk');
+    }
+  }
+};
+
+function encodeWav(channels, sampleRate) {
+  const frames = channels[0].length;
+  const buffer = Buffer.alloc(44 + frames * channels.length * 2);
+  buffer.write('RIFF', 0);
+  buffer.writeUInt32LE(36 + frames * channels.length * 2, 4);
+  buffer.write('WAVEfmt ', 8);
+  buffer.writeUInt32LE(16, 16);
+  buffer.writeUInt16LE(1, 20);
+  buffer.writeUInt16LE(channels.length, 22);
+  buffer.writeUInt32LE(sampleRate, 24);
+  buffer.writeUInt32LE(sampleRate * channels.length * 2, 28);
+  buffer.writeUInt16LE(channels.length * 2, 32);
+  buffer.writeUInt16LE(16, 34);
+  buffer.write('data', 36);
+  buffer.writeUInt32LE(frames * channels.length * 2, 40);
+  for (let i = 0; i < frames; i++) {
+    channels.forEach((channel, c) => {
+      const value = Math.max(-32768, Math.min(32767, Math.round(channel[i] * 32768)));
+      buffer.writeInt16LE(value, 44 + (i * channels.length + c) * 2);
+    });
+  }
+  return buffer;
+}
+
+// Deterministic noise so the fixtures do not change between runs
+function noise(seed) {
+  let state = seed;
+  return () => {
+    state = (state * 1103515245 + 12345) % 2147483648;
+    return state / 1073741824 - 1;
+  };
+}
+
+// Rising chirp with a warble and some background noise
+function birdLike(frames, sampleRate, seed) {
+  const rand = noise(seed);
+  const signal = new Array(frames);
+  let phase = 0;
+  for (let i = 0; i < frames; i++) {
+    const t = i / sampleRate;
+    const frequency = 2500 + 1500 * t + 300 * Math.sin(2 * Math.PI * 12 * t);
+    phase += (2 * Math.PI * frequency) / sampleRate;
+    const envelope = 0.5 + 0.4 * Math.sin(2 * Math.PI * 3 * t);
+    signal[i] = 0.4 * envelope * Math.sin(phase) + 0.05 * rand();
+  }
+  return signal;
+}
+
+const fixtures = [
+  { name: 'chirp_44100', sampleRate: 44100, channels: [birdLike(44100, 44100, 1)] },
+  {
+    name: 'stereo_22050',
+    sampleRate: 22050,
+    channels: [birdLike(33075, 22050, 2), birdLike(33075, 22050, 3)]
+  },
+  // Exactly one analysis frame
+  { name: 'single_frame', sampleRate: 44100, channels: [birdLike(2049, 44100, 4)] }
+];
+
+for (const { name, sampleRate, channels } of fixtures) {
+  const wavPath = join(here, `${name}.wav`);
+  writeFileSync(wavPath, encodeWav(channels, sampleRate));
+
+  const blob = { arrayBuffer: async () => new Uint8Array(readFileSync(wavPath)).buffer };
+  const log = console.log;
+  console.log = () => {};
+  const enhanced = await extractEnhancedMfccFeatures(blob);
+  const simple = await simpleExtractMfccFeatures(blob);
+  console.log = log;
+
+  writeFileSync(join(here, `${name}.enhanced.txt`), enhanced.map(String).join('\n') + '\n');
+  writeFileSync(join(here, `${name}.simple.txt`), simple.map(String).join('\n') + '\n');
+  console.log(`Wrote ${name}`);
+}
@@ -0,0 +1,119 @@
+/**
+ * Regenerates the model reference outputs used by the tests in src/model.rs.
+ *
+ * Loads the PWA's shipped model (../model/mfcc-model.json) and runs it on the
+ * simple MFCC vectors written by generate.mjs, storing the class scores as
+ * one number per line in <name>.predictions.txt.
+ *
+ * With @tensorflow/tfjs installed the scores come from TF.js itself, exactly
+ * as the PWA computes them. Without it, a float32 port of the TF.js CPU
+ * kernels for the layers in the model is used instead. The first line of
+ * each file records which one produced it.
+ *
+ * Usage: npm install @tensorflow/tfjs && node tests/fixtures/mfcc/predict.mjs
+ */
+
+import { writeFileSync, readFileSync } from 'node:fs';
+import { dirname, join } from 'node:path';
+import { fileURLToPath } from 'node:url';
+
+const here = dirname(fileURLToPath(import.meta.url));
+const modelDir = join(here, '../../../../model');
+const modelJson = JSON.parse(readFileSync(join(modelDir, 'mfcc-model.json'), 'utf8'));
+
+function readWeightData() {
+  const buffers = modelJson.weightsManifest.flatMap(group =>
+    group.paths.map(path => readFileSync(join(modelDir, path)))
+  );
+  const bytes = Buffer.concat(buffers);
+  return bytes.buffer.slice(bytes.byteOffset, bytes.byteOffset + bytes.byteLength);
+}
+
+async function tfjsPredictor() {
+  let tf;
+  try {
+    tf = await import('@tensorflow/tfjs');
+  } catch {
+    return null;
+  }
+  const model = await tf.loadLayersModel(
+    tf.io.fromMemory({
+      modelTopology: modelJson.modelTopology,
+      weightSpecs: modelJson.weightsManifest.flatMap(group => group.weights),
+      weightData: readWeightData()
+    })
+  );
+  return {
+    source: `tfjs ${tf.version.tfjs}`,
+    predict: features => Array.from(model.predict(tf.tensor2d([features], [1, features.length])).dataSync())
+  };
+}
+
+// Float32 port of the CPU kernels for BatchNormalization, Dense, Dropout
+// (inference) and the relu/softmax activations
+function portPredictor() {
+  const data = readWeightData();
+  const weights = {};
+  let offset = 0;
+  for (const spec of modelJson.weightsManifest.flatMap(group => group.weights)) {
+    const count = spec.shape.reduce((a, b) => a * b, 1);
+    weights[spec.name] = new Float32Array(data, offset, count);
+    offset += count * 4;
+  }
+
+  const f = Math.fround;
+  const activations = {
+    linear: x => x,
+    relu: x => x.map(v => Math.max(v, 0)),
+    softmax: x => {
+      const max = Math.max(...x);
+      const exps = x.map(v => f(Math.exp(f(v - max))));
+      const sum = exps.reduce((a, b) => f(a + b), 0);
+      return exps.map(v => f(v / sum));
+    }
+  };
+
+  const layers = modelJson.modelTopology.config.layers.map(({ class_name: type, config }) => {
+    const w = key => weights[`${config.name}/${key}`];
+    if (type === 'BatchNormalization') {
+      const [mean, variance, gamma, beta] = ['moving_mean', 'moving_variance', 'gamma', 'beta'].map(w);
+      return x =>
+        x.map((v, i) => {
+          const inv = f(1 / f(Math.sqrt(f(variance[i] + f(config.epsilon)))));
+          return f(f(f(f(v - mean[i]) * gamma[i]) * inv) + beta[i]);
+        });
+    }
+    if (type === 'Dense') {
+      const kernel = w('kernel');
+      const bias = w('bias');
+      return x => {
+        const out = [];
+        for (let u = 0; u < config.units; u++) {
+          let sum = 0;
+          for (let i = 0; i < x.length; i++) sum = f(sum + f(x[i] * kernel[i * config.units + u]));
+          out.push(f(sum + (bias ? bias[u] : 0)));
+        }
+        return activations[config.activation](out);
+      };
+    }
+    if (type === 'Dropout') return x => x;
+    throw new Error(`Layer ${type} is not ported`);
+  });
+
+  return {
+    source: 'float32 port of the TF.js CPU kernels',
+    predict: features => layers.reduce((x, layer) => layer(x), features.map(f))
+  };
+}
+
+const predictor = (await tfjsPredictor()) ?? portPredictor();
+if (!predictor.source.startsWith('tfjs')) {
+  console.warn('@tensorflow/tfjs not found, using the float32 port');
+}
+
+for (const name of ['chirp_44100', 'stereo_22050', 'single_frame']) {
+  const features = readFileSync(join(here, `${name}.simple.txt`), 'utf8').trim().split('\n').map(Number);
+  const scores = predictor.predict(features);
+  writeFileSync(join(here, `${name}.predictions.txt`), `# ${predictor.source}\n` + scores.map(String).join('\n') + '\n');
+  console.log(`Wrote ${name} (${predictor.source})`);
+}